@@ -0,0 +1,276 @@
+//! Pairing, and sessions for the web interface. Every browser gets a read-only session on load.
+//! Entering the pairing PIN printed in the console upgrades it to control, which is needed to arm,
+//! spin motors, move servos, or change settings. Each PIN works once.
+//!
+//! Every request that changes anything must echo the session's CSRF token in the `X-CSRFToken`
+//! header. The page reads it from the `csrftoken` cookie; other sites can't.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Instant};
+
+use rocket::{
+    http::{Cookie, CookieJar, SameSite, Status},
+    request::{FromRequest, Outcome},
+    Request,
+};
+use serde::Serialize;
+
+pub const SESSION_COOKIE: &str = "preflight_session";
+pub const CSRF_COOKIE: &str = "csrftoken";
+pub const CSRF_HEADER: &str = "X-CSRFToken";
+
+const PIN_DIGITS: u32 = 6;
+/// After this many wrong PINs, we replace the PIN, so it can't be guessed by trying them all.
+const MAX_PIN_ATTEMPTS: u8 = 5;
+/// Past this, the least-recently used session is dropped.
+const MAX_SESSIONS: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Role {
+    /// Can view readings, and do things that don't affect the aircraft, like recording.
+    ReadOnly,
+    /// Paired; can also command the aircraft, and change its settings.
+    Control,
+}
+
+struct Session {
+    role: Role,
+    csrf_token: String,
+    last_used: Instant,
+}
+
+struct Pairing {
+    pin: String,
+    failed_attempts: u8,
+}
+
+// Keyed by session token.
+static SESSIONS: Mutex<BTreeMap<String, Session>> = Mutex::new(BTreeMap::new());
+static PAIRING: Mutex<Option<Pairing>> = Mutex::new(None);
+
+/// The message to send with a rejected request; see the catchers in `main`.
+#[derive(Debug)]
+pub struct AuthError(pub String);
+
+/// `len` random bytes, as hex.
+fn random_token(len: usize) -> String {
+    let mut buf = vec![0; len];
+    getrandom::getrandom(&mut buf).expect("The OS has no random number source");
+
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn random_pin() -> String {
+    let modulus = 10_u32.pow(PIN_DIGITS);
+    // Discard the top of the range, so every PIN is equally likely.
+    let limit = u32::MAX - u32::MAX % modulus;
+
+    loop {
+        let mut buf = [0; 4];
+        getrandom::getrandom(&mut buf).expect("The OS has no random number source");
+        let v = u32::from_le_bytes(buf);
+
+        if v < limit {
+            return format!("{:0width$}", v % modulus, width = PIN_DIGITS as usize);
+        }
+    }
+}
+
+/// Compare without exiting early, so response times don't reveal how much of a secret matched.
+fn secrets_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Replace the pairing PIN, and return the new one, to show the user.
+pub fn new_pin() -> String {
+    let pin = random_pin();
+
+    *PAIRING.lock().unwrap() = Some(Pairing {
+        pin: pin.clone(),
+        failed_attempts: 0,
+    });
+
+    pin
+}
+
+/// Continue the session in `cookies`, or start a read-only one. Returns its role.
+pub fn start_session(cookies: &CookieJar<'_>) -> Role {
+    let mut sessions = SESSIONS.lock().unwrap();
+
+    let existing = cookies
+        .get(SESSION_COOKIE)
+        .map(|c| c.value().to_owned())
+        .filter(|token| sessions.contains_key(token));
+
+    let token = match existing {
+        Some(t) => t,
+        None => {
+            if sessions.len() >= MAX_SESSIONS {
+                let oldest = sessions
+                    .iter()
+                    .min_by_key(|(_, s)| s.last_used)
+                    .map(|(t, _)| t.clone());
+                if let Some(t) = oldest {
+                    sessions.remove(&t);
+                }
+            }
+
+            let token = random_token(32);
+            sessions.insert(
+                token.clone(),
+                Session {
+                    role: Role::ReadOnly,
+                    csrf_token: random_token(32),
+                    last_used: Instant::now(),
+                },
+            );
+            token
+        }
+    };
+
+    let session = sessions.get_mut(&token).unwrap();
+    session.last_used = Instant::now();
+
+    // These last until the browser closes; the sessions themselves, until we exit.
+    cookies.add(
+        Cookie::build((SESSION_COOKIE, token))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Strict),
+    );
+    // The page's script reads this one.
+    cookies.add(
+        Cookie::build((CSRF_COOKIE, session.csrf_token.clone()))
+            .path("/")
+            .same_site(SameSite::Strict),
+    );
+
+    session.role
+}
+
+/// Pair a session, giving it control, if the PIN is right. Each PIN works once; the next one is
+/// printed to the console.
+pub fn pair(viewer: &Viewer, pin: &str) -> Result<(), String> {
+    let mut pairing = PAIRING.lock().unwrap();
+
+    let p = match pairing.as_mut() {
+        Some(p) => p,
+        None => return Err("Pairing isn't available.".into()),
+    };
+
+    if !secrets_match(pin.trim(), &p.pin) {
+        p.failed_attempts += 1;
+
+        if p.failed_attempts >= MAX_PIN_ATTEMPTS {
+            drop(pairing);
+            println!("Too many wrong pairing PINs. The new one is {}.", new_pin());
+            return Err(
+                "Wrong PIN. There have been too many wrong ones, so there's a new PIN; see the \
+                console."
+                    .into(),
+            );
+        }
+        return Err("Wrong PIN.".into());
+    }
+
+    drop(pairing);
+
+    if let Some(s) = SESSIONS.lock().unwrap().get_mut(&viewer.token) {
+        s.role = Role::Control;
+    }
+
+    println!(
+        "Paired a device. The PIN for the next one is {}.",
+        new_pin()
+    );
+
+    Ok(())
+}
+
+/// Give up control, keeping the session.
+pub fn unpair(viewer: &Viewer) {
+    if let Some(s) = SESSIONS.lock().unwrap().get_mut(&viewer.token) {
+        s.role = Role::ReadOnly;
+    }
+}
+
+fn reject<T>(req: &Request<'_>, status: Status, msg: &str) -> Outcome<T, AuthError> {
+    req.local_cache(|| AuthError(msg.to_owned()));
+    Outcome::Error((status, AuthError(msg.to_owned())))
+}
+
+/// A request from any session, with the right CSRF token. For requests that change something,
+/// but not on the aircraft.
+pub struct Viewer {
+    token: String,
+    pub role: Role,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Viewer {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match req.cookies().get(SESSION_COOKIE) {
+            Some(c) => c.value().to_owned(),
+            None => return reject(req, Status::Unauthorized, "No session. Reload the page."),
+        };
+
+        let mut sessions = SESSIONS.lock().unwrap();
+        let session = match sessions.get_mut(&token) {
+            Some(s) => s,
+            // Eg Preflight was restarted.
+            None => {
+                return reject(
+                    req,
+                    Status::Unauthorized,
+                    "Your session has expired. Reload the page.",
+                )
+            }
+        };
+
+        let csrf_ok = req
+            .headers()
+            .get_one(CSRF_HEADER)
+            .is_some_and(|t| secrets_match(t, &session.csrf_token));
+        if !csrf_ok {
+            return reject(
+                req,
+                Status::Forbidden,
+                "Missing, or wrong CSRF token. Reload the page.",
+            );
+        }
+
+        session.last_used = Instant::now();
+
+        Outcome::Success(Self {
+            token,
+            role: session.role,
+        })
+    }
+}
+
+/// A request from a paired session, with the right CSRF token. For commanding the aircraft, or
+/// changing its settings.
+pub struct Operator;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Operator {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.guard::<Viewer>().await {
+            Outcome::Success(v) if v.role == Role::Control => Outcome::Success(Self),
+            Outcome::Success(_) => reject(
+                req,
+                Status::Forbidden,
+                "This device is read-only. Pair it using the PIN shown where Preflight is running.",
+            ),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(s) => Outcome::Forward(s),
+        }
+    }
+}
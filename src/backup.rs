@@ -0,0 +1,292 @@
+//! Whole-aircraft configuration backups: Everything configurable on the FC in one versioned file,
+//! for restoring after a re-flash, or copying between boards. Saved as JSON or TOML. Sections the
+//! board doesn't support are left out, and skipped on restore.
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    export::{self, Row},
+    rc_cal::RcCalProfile,
+    types::{
+        AircraftType, FirmwareVersion, ImuBias, Location, MagCalibration, MotorDirections,
+        ParamValue, PidConfig, ProtocolVersion, MAX_WAYPOINTS, WAYPOINT_MAX_NAME_LEN,
+    },
+    ServoCalibrations,
+};
+
+/// Bumped when the file layout changes in a way older versions of this program can't read.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Identifying fields, not part of the diff. They're not restored; we check the backup was made on
+/// a compatible aircraft.
+const METADATA_FIELDS: [&str; 3] = ["format_version", "created", "board"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    /// Parse from a name, or file extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_ref() {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|e| Self::from_name(&e.to_string_lossy()))
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Toml => "application/toml",
+        }
+    }
+}
+
+/// The board a backup was taken from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BoardInfo {
+    pub aircraft_type: AircraftType,
+    pub board_id: u16,
+    pub firmware_build: u32,
+    pub firmware_version: FirmwareVersion,
+    pub protocol_version: ProtocolVersion,
+}
+
+/// A waypoint, and its slot in the FC's table.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WaypointSlot {
+    pub slot: usize,
+    #[serde(flatten)]
+    pub location: Location,
+}
+
+/// Check waypoint slots, and name lengths against what the FC's table holds.
+pub fn validate_waypoints(waypoints: &[WaypointSlot]) -> Result<(), String> {
+    for wp in waypoints {
+        if wp.slot >= MAX_WAYPOINTS {
+            return Err(format!(
+                "Waypoint slot {} is past the last slot, {}.",
+                wp.slot,
+                MAX_WAYPOINTS - 1
+            ));
+        }
+        if wp.location.name.len() > WAYPOINT_MAX_NAME_LEN {
+            return Err(format!(
+                "Waypoint name {} is longer than {} characters.",
+                wp.location.name, WAYPOINT_MAX_NAME_LEN
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The FC's whole waypoint table, with slots not listed left empty. Validate first.
+pub fn waypoint_table(waypoints: &[WaypointSlot]) -> [Option<Location>; MAX_WAYPOINTS] {
+    let mut result = [(); MAX_WAYPOINTS].map(|_| Option::<Location>::default());
+    for wp in waypoints {
+        result[wp.slot] = Some(wp.location.clone());
+    }
+
+    result
+}
+
+/// The occupied slots of the FC's waypoint table.
+pub fn waypoint_slots(table: &[Option<Location>; MAX_WAYPOINTS]) -> Vec<WaypointSlot> {
+    table
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, wp)| wp.clone().map(|location| WaypointSlot { slot, location }))
+        .collect()
+}
+
+/// The contents of a backup file. For TOML, plain values must come before tables, here and in
+/// each section.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfigBackup {
+    pub format_version: u32,
+    /// RFC 3339.
+    pub created: String,
+    pub board: BoardInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motor_directions: Option<MotorDirections>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub servo_calibration: Option<ServoCalibrations>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rc_calibration: Option<RcCalProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<PidConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imu_bias: Option<ImuBias>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mag_calibration: Option<MagCalibration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub waypoints: Option<Vec<WaypointSlot>>,
+    /// Parameter table values, by name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<BTreeMap<String, ParamValue>>,
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl ConfigBackup {
+    pub fn to_string(&self, format: Format) -> Result<String, io::Error> {
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            Format::Toml => toml::to_string(self).map_err(|e| e.to_string()),
+        }
+        .map_err(invalid_data)
+    }
+
+    /// Parse a backup in either format. JSON files start with `{`; TOML files can't.
+    pub fn parse(contents: &str) -> Result<Self, io::Error> {
+        let result: Self = if contents.trim_start().starts_with('{') {
+            serde_json::from_str(contents).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(contents).map_err(|e| e.to_string())
+        }
+        .map_err(|e| invalid_data(format!("Problem parsing the backup: {}", e)))?;
+
+        if result.format_version > FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "This backup uses format version {}; we only support up to {}. Update Preflight.",
+                result.format_version, FORMAT_VERSION
+            )));
+        }
+
+        Ok(result)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Save to `path`, as JSON or TOML depending on its extension.
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let format = Format::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The backup file must end in .json or .toml",
+            )
+        })?;
+
+        fs::write(path, self.to_string(format)?)
+    }
+
+    /// Check everything we can without the FC, so a bad file doesn't leave the board
+    /// half-restored.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(cals) = &self.servo_calibration {
+            cals.left
+                .validate()
+                .map_err(|e| format!("Left servo: {}", e))?;
+            cals.right
+                .validate()
+                .map_err(|e| format!("Right servo: {}", e))?;
+        }
+
+        if let Some(profile) = &self.rc_calibration {
+            for axis in crate::rc_cal::AXES {
+                profile.get(axis).validate(axis)?;
+            }
+        }
+
+        if let Some(pid) = &self.pid {
+            pid.validate()?;
+        }
+
+        if let Some(waypoints) = &self.waypoints {
+            validate_waypoints(waypoints)?;
+        }
+
+        Ok(())
+    }
+
+    /// The configurable fields, flattened to dotted paths. Waypoints are keyed by slot, so
+    /// adding one doesn't shift the rest.
+    fn fields(&self) -> Row {
+        let mut v = serde_json::to_value(self).unwrap_or(Value::Null);
+
+        if let Some(obj) = v.as_object_mut() {
+            for field in METADATA_FIELDS {
+                obj.remove(field);
+            }
+
+            if let Some(Value::Array(waypoints)) = obj.remove("waypoints") {
+                let by_slot = waypoints
+                    .into_iter()
+                    .map(|mut wp| {
+                        let slot = wp
+                            .as_object_mut()
+                            .and_then(|w| w.remove("slot"))
+                            .unwrap_or(Value::Null);
+                        (slot.to_string(), wp)
+                    })
+                    .collect();
+                obj.insert("waypoints".into(), Value::Object(by_slot));
+            }
+        }
+
+        let mut result = Row::new();
+        export::flatten("", &v, &mut result);
+        result
+    }
+}
+
+/// A field that differs between two configurations. `None` where one doesn't have the field,
+/// eg a section the board doesn't support.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConfigDiff {
+    /// Dotted path, eg `pid.roll.rate.kp`, or `params.ANGLE_MAX`.
+    pub field: String,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+}
+
+/// Configurable fields that differ between two backups.
+pub fn diff(a: &ConfigBackup, b: &ConfigBackup) -> Vec<ConfigDiff> {
+    let (a, b) = (a.fields(), b.fields());
+
+    let mut fields: Vec<&String> = a.keys().chain(b.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter(|f| a.get(*f) != b.get(*f))
+        .map(|f| ConfigDiff {
+            field: f.clone(),
+            a: a.get(f).cloned(),
+            b: b.get(f).cloned(),
+        })
+        .collect()
+}
+
+/// What a restore wrote, and what it skipped.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RestoreReport {
+    /// Sections written, and confirmed by reading back.
+    pub restored: Vec<String>,
+    /// Sections, or parameters we couldn't restore onto this board, and why.
+    pub skipped: Vec<String>,
+}
@@ -0,0 +1,264 @@
+//! Battery monitoring: Cell-count detection, per-cell voltage, capacity used, remaining-capacity
+//! estimate, and low-voltage alerts. Pack profiles are loaded from `battery.toml`, if present.
+
+use std::{fs, io, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+pub const PROFILES_FILE: &str = "battery.toml";
+
+/// Below this pack voltage, we assume no battery is connected (eg powered over USB), and don't
+/// detect cell count, or integrate current.
+const MIN_PACK_V: f32 = 2.;
+
+/// Resting cell voltage vs state of charge, in %. From typical discharge curves at low current.
+const LIPO_CURVE: [(f32, f32); 12] = [
+    (3.27, 0.),
+    (3.61, 5.),
+    (3.69, 10.),
+    (3.73, 20.),
+    (3.77, 30.),
+    (3.80, 40.),
+    (3.84, 50.),
+    (3.87, 60.),
+    (3.95, 70.),
+    (4.02, 80.),
+    (4.11, 90.),
+    (4.20, 100.),
+];
+
+const LI_ION_CURVE: [(f32, f32); 12] = [
+    (2.80, 0.),
+    (3.30, 5.),
+    (3.45, 10.),
+    (3.55, 20.),
+    (3.62, 30.),
+    (3.68, 40.),
+    (3.74, 50.),
+    (3.80, 60.),
+    (3.88, 70.),
+    (3.97, 80.),
+    (4.07, 90.),
+    (4.20, 100.),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Chemistry {
+    LiPo,
+    LiIon,
+}
+
+impl Chemistry {
+    /// Fully-charged cell voltage.
+    pub fn cell_v_full(&self) -> f32 {
+        match self {
+            Self::LiPo => 4.2,
+            Self::LiIon => 4.2,
+        }
+    }
+
+    fn curve(&self) -> &'static [(f32, f32)] {
+        match self {
+            Self::LiPo => &LIPO_CURVE,
+            Self::LiIon => &LI_ION_CURVE,
+        }
+    }
+
+    /// Estimate state of charge, in %, from a resting cell voltage.
+    pub fn soc_from_voltage(&self, cell_v: f32) -> f32 {
+        let curve = self.curve();
+
+        if cell_v <= curve[0].0 {
+            return 0.;
+        }
+
+        for pair in curve.windows(2) {
+            let ((v0, soc0), (v1, soc1)) = (pair[0], pair[1]);
+            if cell_v <= v1 {
+                return soc0 + (cell_v - v0) / (v1 - v0) * (soc1 - soc0);
+            }
+        }
+
+        100.
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackProfile {
+    pub name: String,
+    pub chemistry: Chemistry,
+    /// Number of cells in series. If `None`, we detect it from pack voltage on connection.
+    #[serde(default)]
+    pub cell_count: Option<u8>,
+    pub capacity_mah: f32,
+    /// Per-cell voltage alert thresholds, in V.
+    pub low_cell_v: f32,
+    pub critical_cell_v: f32,
+}
+
+impl Default for PackProfile {
+    fn default() -> Self {
+        Self {
+            name: "Default LiPo".into(),
+            chemistry: Chemistry::LiPo,
+            cell_count: None,
+            capacity_mah: 1_500.,
+            low_cell_v: 3.5,
+            critical_cell_v: 3.3,
+        }
+    }
+}
+
+/// The contents of `PROFILES_FILE`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackProfiles {
+    /// Name of the profile in use.
+    pub active: String,
+    pub profiles: Vec<PackProfile>,
+}
+
+impl Default for PackProfiles {
+    fn default() -> Self {
+        let profile = PackProfile::default();
+
+        Self {
+            active: profile.name.clone(),
+            profiles: vec![profile],
+        }
+    }
+}
+
+impl PackProfiles {
+    /// Load profiles from `PROFILES_FILE`, or use a default LiPo profile if it doesn't exist.
+    pub fn load() -> Result<Self, io::Error> {
+        match fs::read_to_string(PROFILES_FILE) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Problem parsing {}: {}", PROFILES_FILE, e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PackProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn active(&self) -> PackProfile {
+        self.get(&self.active).cloned().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum BatteryAlert {
+    Normal,
+    Low,
+    Critical,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BatteryStatus {
+    pub profile: String,
+    pub chemistry: Chemistry,
+    /// `None` if no battery is connected.
+    pub cell_count: Option<u8>,
+    pub cell_v: Option<f32>,
+    pub mah_used: f32,
+    /// Estimated remaining capacity, in %.
+    pub remaining_pct: Option<f32>,
+    pub alert: BatteryAlert,
+}
+
+/// Tracks a pack across readings.
+pub struct Battery {
+    pub profile: PackProfile,
+    cell_count: Option<u8>,
+    mah_used: f32,
+    /// State of charge when we first saw this pack, in %, estimated from its resting voltage.
+    initial_soc: Option<f32>,
+    last_update: Option<Instant>,
+    batt_v: f32,
+}
+
+impl Battery {
+    pub fn new(profile: PackProfile) -> Self {
+        Self {
+            cell_count: profile.cell_count,
+            profile,
+            mah_used: 0.,
+            initial_soc: None,
+            last_update: None,
+            batt_v: 0.,
+        }
+    }
+
+    /// The fewest cells that can produce this voltage without being overcharged.
+    fn detect_cell_count(&self, batt_v: f32) -> u8 {
+        // Allow a little margin for measurement error on a fully-charged pack.
+        (batt_v / (self.profile.chemistry.cell_v_full() * 1.01)).ceil() as u8
+    }
+
+    /// Update with a new reading. `current` is in A.
+    pub fn update(&mut self, batt_v: f32, current: f32) {
+        let now = Instant::now();
+        self.batt_v = batt_v;
+
+        if batt_v < MIN_PACK_V {
+            // Pack unplugged; the next one may be different.
+            *self = Self::new(self.profile.clone());
+            return;
+        }
+
+        let cells = match self.cell_count {
+            Some(c) => c,
+            None => {
+                // Detect once, at connection; voltage sag under load would skew later detections.
+                let c = self.detect_cell_count(batt_v);
+                self.cell_count = Some(c);
+                c
+            }
+        };
+
+        if self.initial_soc.is_none() {
+            let cell_v = batt_v / cells as f32;
+            self.initial_soc = Some(self.profile.chemistry.soc_from_voltage(cell_v));
+        }
+
+        if let Some(last) = self.last_update {
+            let hours = (now - last).as_secs_f32() / 3_600.;
+            self.mah_used += current.max(0.) * hours * 1_000.;
+        }
+
+        self.last_update = Some(now);
+    }
+
+    pub fn status(&self) -> BatteryStatus {
+        let cell_v = match self.cell_count {
+            Some(c) if c > 0 && self.batt_v >= MIN_PACK_V => Some(self.batt_v / c as f32),
+            _ => None,
+        };
+
+        let remaining_pct = self
+            .initial_soc
+            .map(|soc| (soc - self.mah_used / self.profile.capacity_mah * 100.).clamp(0., 100.));
+
+        let alert = match cell_v {
+            Some(v) if v <= self.profile.critical_cell_v => BatteryAlert::Critical,
+            Some(v) if v <= self.profile.low_cell_v => BatteryAlert::Low,
+            _ => BatteryAlert::Normal,
+        };
+
+        BatteryStatus {
+            profile: self.profile.name.clone(),
+            chemistry: self.profile.chemistry,
+            cell_count: cell_v.and(self.cell_count),
+            cell_v,
+            mah_used: self.mah_used,
+            remaining_pct,
+            alert,
+        }
+    }
+}
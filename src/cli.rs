@@ -0,0 +1,546 @@
+//! The command line, for scripts, and laptops without a browser. Without a subcommand, we launch
+//! the web interface.
+
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::{Args, Parser, Subcommand};
+use serialport::SerialPortType;
+
+use crate::{
+    backup::{self, ConfigBackup, ConfigDiff, WaypointSlot},
+    cached_data, dfu, export, history,
+    history::Recorder,
+    net, read_config, reboot_to_bootloader, require_aircraft_type, require_config_section,
+    require_disarmed, set_cached_waypoints, take_reading, to_euler, tui,
+    types::{AircraftType, FirmwareVersion, RotorPosition, REFRESH_INTERVAL},
+    update_firmware, with_fc, write_config, FC_SERIAL_NUMBER, RECORDER,
+};
+
+/// Motor tests longer than this are refused, in seconds.
+const MAX_MOTOR_TEST_DURATION: f32 = 10.;
+
+#[derive(Parser)]
+#[command(
+    name = "preflight",
+    version,
+    about = "Verify systems, and change settings on AnyLeaf flight controllers",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// For the web interface, when launched without a subcommand.
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Address to listen on. Defaults to this computer only; see `--lan`.
+    #[arg(long, env = "PREFLIGHT_ADDRESS")]
+    pub address: Option<IpAddr>,
+    /// Port to listen on. Ports below 1024 need root on Linux.
+    #[arg(long, env = "PREFLIGHT_PORT")]
+    pub port: Option<u16>,
+    /// Let other devices on the network connect, eg a phone. Same as `--address 0.0.0.0`, and
+    /// overrides `--address`, or `PREFLIGHT_ADDRESS`.
+    #[arg(long)]
+    pub lan: bool,
+}
+
+impl ServeArgs {
+    /// Where to listen: From these arguments, then `server.toml`, then the defaults.
+    pub fn socket_addr(&self) -> Result<SocketAddr, io::Error> {
+        let config = net::ServerConfig::load()?;
+
+        let address = if self.lan {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            self.address
+                .or(config.address)
+                .unwrap_or(net::DEFAULT_ADDRESS)
+        };
+        let port = self.port.or(config.port).unwrap_or(net::DEFAULT_PORT);
+
+        Ok(SocketAddr::new(address, port))
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Launch the web interface. This is the default.
+    Serve(ServeArgs),
+    /// List serial ports, and any FC waiting in its bootloader.
+    ListDevices,
+    /// Print a snapshot of the FC's readings, as JSON.
+    Read,
+    /// Print readings as they arrive, until Ctrl+C.
+    Watch {
+        /// Time between readings, in ms.
+        #[arg(long, default_value_t = 200)]
+        interval: u64,
+        /// Print each reading as a line of JSON, instead of a summary.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Arm the motors.
+    Arm {
+        /// Don't ask for confirmation.
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Disarm the motors.
+    Disarm,
+    /// Spin one motor briefly, with the aircraft disarmed. Ctrl+C stops it early.
+    MotorTest {
+        /// front-left, front-right, aft-left, or aft-right.
+        #[arg(value_parser = parse_motor)]
+        motor: RotorPosition,
+        /// How long to spin it, in seconds.
+        #[arg(long, default_value_t = 2.)]
+        duration: f32,
+        /// Don't ask for confirmation.
+        #[arg(long)]
+        yes: bool,
+    },
+    /// A terminal dashboard: Readings, preflight checks, and a motor test. For working over SSH.
+    Tui,
+    /// Read, or replace the FC's waypoints.
+    #[command(subcommand)]
+    Waypoints(WaypointsCommand),
+    /// Record readings to the `recordings` folder, until Ctrl+C.
+    Record {
+        /// Stop after this many seconds.
+        #[arg(long)]
+        duration: Option<f32>,
+    },
+    /// Export a recording as CSV, or Parquet, depending on the output's extension.
+    Export { recording: PathBuf, output: PathBuf },
+    /// Back up the FC's configuration, as JSON, or TOML depending on the extension.
+    Backup { file: PathBuf },
+    /// Restore a configuration backup onto the FC.
+    Restore { file: PathBuf },
+    /// Compare a backup with another, or with the FC if there's no second one.
+    Diff { a: PathBuf, b: Option<PathBuf> },
+    /// Update the FC's firmware over USB, from a raw binary image.
+    Flash {
+        image: PathBuf,
+        /// The version the FC must report afterwards, eg 1.2.3.
+        #[arg(value_parser = parse_version)]
+        version: Option<FirmwareVersion>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WaypointsCommand {
+    /// Print the FC's waypoints as JSON, in the same layout as configuration backups.
+    Get,
+    /// Replace the FC's waypoints with a JSON file from `waypoints get`. Slots not listed are
+    /// cleared.
+    Set { file: PathBuf },
+}
+
+fn parse_motor(name: &str) -> Result<RotorPosition, String> {
+    name.parse()
+}
+
+fn parse_version(v: &str) -> Result<FirmwareVersion, String> {
+    dfu::parse_version(v).ok_or_else(|| format!("Invalid version: {}", v))
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Run a subcommand, other than `serve`.
+pub fn run(command: Command) -> Result<(), io::Error> {
+    match command {
+        Command::Serve(_) => unreachable!("`serve` is handled by `main`"),
+        Command::ListDevices => list_devices(),
+        Command::Read => read(),
+        Command::Watch { interval, json } => watch(interval, json),
+        Command::Arm { yes } => arm(yes),
+        Command::Disarm => disarm(),
+        Command::MotorTest {
+            motor,
+            duration,
+            yes,
+        } => motor_test(motor, duration, yes),
+        Command::Tui => tui::run(),
+        Command::Waypoints(WaypointsCommand::Get) => get_waypoints(),
+        Command::Waypoints(WaypointsCommand::Set { file }) => set_waypoints(&file),
+        Command::Record { duration } => record(duration),
+        Command::Export { recording, output } => export(&recording, &output),
+        Command::Backup { file } => backup(&file),
+        Command::Restore { file } => restore(&file),
+        Command::Diff { a, b } => diff(&a, b.as_deref()),
+        Command::Flash { image, version } => flash(&image, version),
+    }
+}
+
+/// Set a flag on Ctrl+C, instead of exiting, so we can stop motors, or finish files first.
+fn ctrl_c_flag() -> Result<Arc<AtomicBool>, io::Error> {
+    let flag = Arc::new(AtomicBool::new(false));
+
+    let f = flag.clone();
+    ctrlc::set_handler(move || f.store(true, Ordering::SeqCst))
+        .map_err(|e| io::Error::other(format!("Problem handling Ctrl+C: {}", e)))?;
+
+    Ok(flag)
+}
+
+/// Ask on the terminal, unless `yes` is set.
+fn confirm(prompt: &str, yes: bool) -> Result<(), io::Error> {
+    if yes {
+        return Ok(());
+    }
+
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled."));
+    }
+
+    Ok(())
+}
+
+fn list_devices() -> Result<(), io::Error> {
+    let ports = serialport::available_ports().map_err(io::Error::other)?;
+
+    if ports.is_empty() {
+        println!("No serial ports found.");
+    }
+
+    for port in &ports {
+        match &port.port_type {
+            SerialPortType::UsbPort(info) => {
+                let is_fc = info.serial_number.as_deref() == Some(FC_SERIAL_NUMBER);
+
+                println!(
+                    "{}  USB {:04x}:{:04x}  {}{}",
+                    port.port_name,
+                    info.vid,
+                    info.pid,
+                    info.product.as_deref().unwrap_or(""),
+                    if is_fc { "  (flight controller)" } else { "" },
+                );
+            }
+            SerialPortType::BluetoothPort => println!("{}  Bluetooth", port.port_name),
+            SerialPortType::PciPort => println!("{}  PCI", port.port_name),
+            SerialPortType::Unknown => println!("{}", port.port_name),
+        }
+    }
+
+    if dfu::bootloader_present() {
+        println!(
+            "USB {:04x}:{:04x}  STM32 bootloader, in DFU mode. Use `preflight flash` to update \
+            its firmware.",
+            dfu::BOOTLOADER_VID,
+            dfu::BOOTLOADER_PID
+        );
+    }
+
+    Ok(())
+}
+
+fn read() -> Result<(), io::Error> {
+    take_reading()?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&cached_data()).map_err(io::Error::other)?
+    );
+
+    Ok(())
+}
+
+fn watch(interval: u64, json: bool) -> Result<(), io::Error> {
+    let stop = ctrl_c_flag()?;
+    let interval = Duration::from_millis(interval.max(REFRESH_INTERVAL as u64));
+
+    while !stop.load(Ordering::SeqCst) {
+        let start = Instant::now();
+
+        match take_reading() {
+            Ok(()) => {
+                let data = cached_data();
+
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&data).map_err(io::Error::other)?
+                    );
+                } else {
+                    let (roll, pitch, yaw) = to_euler(&data.attitude_quat);
+                    let agl = match data.altimeter_agl {
+                        Some(a) => format!("{:.1}m", a),
+                        None => "-".into(),
+                    };
+
+                    println!(
+                        "roll {:6.1}°  pitch {:6.1}°  yaw {:6.1}°  alt {:.1}m  agl {}  \
+                        {:.2}V {:.1}A  {:?}  LQ {}%",
+                        roll,
+                        pitch,
+                        yaw,
+                        data.altimeter,
+                        agl,
+                        data.batt_v,
+                        data.current,
+                        data.controls.arm_status,
+                        data.link_stats.uplink_link_quality,
+                    );
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+
+        thread::sleep(interval.saturating_sub(start.elapsed()));
+    }
+
+    Ok(())
+}
+
+fn arm(yes: bool) -> Result<(), io::Error> {
+    confirm("Arm the motors? Keep clear of the propellers.", yes)?;
+
+    with_fc(|fc| fc.send_arm_command())?;
+
+    println!("Armed.");
+    Ok(())
+}
+
+fn disarm() -> Result<(), io::Error> {
+    with_fc(|fc| fc.send_disarm_command())?;
+
+    println!("Disarmed.");
+    Ok(())
+}
+
+fn motor_test(motor: RotorPosition, duration: f32, yes: bool) -> Result<(), io::Error> {
+    if !(0. ..=MAX_MOTOR_TEST_DURATION).contains(&duration) {
+        return Err(invalid_input(format!(
+            "The duration must be from 0 to {} seconds.",
+            MAX_MOTOR_TEST_DURATION
+        )));
+    }
+
+    require_aircraft_type(AircraftType::Quadcopter)?;
+    require_disarmed()?;
+
+    confirm(
+        &format!(
+            "Spin the {:?} motor for {}s? Remove the propellers first.",
+            motor, duration
+        ),
+        yes,
+    )?;
+
+    let stop = ctrl_c_flag()?;
+
+    with_fc(|fc| fc.send_start_motor_command(motor))?;
+    println!("Spinning the {:?} motor. Ctrl+C stops it.", motor);
+
+    let start = Instant::now();
+    while start.elapsed().as_secs_f32() < duration && !stop.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    // Try again on a fresh connection if this fails, rather than leave it spinning.
+    if with_fc(|fc| fc.send_stop_motor_command(motor)).is_err() {
+        with_fc(|fc| fc.send_stop_motor_command(motor))?;
+    }
+
+    println!("Stopped.");
+    Ok(())
+}
+
+fn get_waypoints() -> Result<(), io::Error> {
+    require_config_section("waypoints", crate::aircraft_info()?.aircraft_type)?;
+
+    let table = with_fc(|fc| fc.read_waypoints())?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&backup::waypoint_slots(&table)).map_err(io::Error::other)?
+    );
+
+    Ok(())
+}
+
+fn set_waypoints(path: &Path) -> Result<(), io::Error> {
+    let waypoints: Vec<WaypointSlot> = serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| invalid_input(format!("Problem parsing the waypoints: {}", e)))?;
+    backup::validate_waypoints(&waypoints).map_err(invalid_input)?;
+
+    require_config_section("waypoints", crate::aircraft_info()?.aircraft_type)?;
+    require_disarmed()?;
+
+    let table = backup::waypoint_table(&waypoints);
+
+    let applied = with_fc(|fc| {
+        fc.send_waypoints(&table)?;
+        fc.read_waypoints()
+    })?;
+
+    if applied != table {
+        return Err(io::Error::other(
+            "The FC's waypoints don't match what we sent.",
+        ));
+    }
+    set_cached_waypoints(applied);
+
+    println!("Set {} waypoints.", waypoints.len());
+    Ok(())
+}
+
+fn record(duration: Option<f32>) -> Result<(), io::Error> {
+    let stop = ctrl_c_flag()?;
+
+    let recorder = Recorder::start()?;
+    println!("Recording to {}. Ctrl+C stops it.", recorder.path.display());
+    *RECORDER.lock().unwrap() = Some(recorder);
+
+    let interval = Duration::from_millis(REFRESH_INTERVAL as u64);
+    let start = Instant::now();
+
+    while !stop.load(Ordering::SeqCst) && duration.is_none_or(|d| start.elapsed().as_secs_f32() < d)
+    {
+        let reading_start = Instant::now();
+        if let Err(e) = take_reading() {
+            eprintln!("{}", e);
+        }
+
+        // Writing failed, and the recorder was dropped.
+        if RECORDER.lock().unwrap().is_none() {
+            return Err(io::Error::other("The recording stopped early."));
+        }
+
+        thread::sleep(interval.saturating_sub(reading_start.elapsed()));
+    }
+
+    let recorder = RECORDER.lock().unwrap().take().unwrap();
+    let num_samples = recorder.num_samples;
+    let path = recorder.finish()?;
+
+    println!("Saved {} readings to {}", num_samples, path.display());
+    Ok(())
+}
+
+fn export(input: &Path, output: &Path) -> Result<(), io::Error> {
+    let format = output
+        .extension()
+        .and_then(|e| export::Format::from_name(&e.to_string_lossy()))
+        .ok_or_else(|| invalid_input("The output file must end in .csv or .parquet".into()))?;
+
+    let samples = history::load_recording(input)?;
+    fs::write(output, export::export(&samples, format)?)?;
+
+    println!(
+        "Exported {} readings to {}",
+        samples.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+fn print_diffs(diffs: &[ConfigDiff], a_name: &str, b_name: &str) {
+    if diffs.is_empty() {
+        println!("No differences.");
+        return;
+    }
+
+    let show = |v: &Option<serde_json::Value>| match v {
+        Some(v) => v.to_string(),
+        None => "(none)".into(),
+    };
+
+    println!("{} vs {}:", a_name, b_name);
+    for d in diffs {
+        println!("  {}: {} -> {}", d.field, show(&d.a), show(&d.b));
+    }
+}
+
+fn backup(path: &Path) -> Result<(), io::Error> {
+    let config = read_config()?;
+    config.save(path)?;
+
+    println!(
+        "Backed up the configuration of board {} to {}",
+        config.board.board_id,
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn restore(path: &Path) -> Result<(), io::Error> {
+    let config = ConfigBackup::load(path)?;
+
+    let report = write_config(&config)?;
+
+    println!("Restored, and confirmed: {}", report.restored.join(", "));
+    for skipped in &report.skipped {
+        println!("Skipped {}", skipped);
+    }
+
+    Ok(())
+}
+
+fn diff(a: &Path, b: Option<&Path>) -> Result<(), io::Error> {
+    let a_name = a.display().to_string();
+    let a = ConfigBackup::load(a)?;
+
+    match b {
+        Some(b) => {
+            let diffs = backup::diff(&a, &ConfigBackup::load(b)?);
+            print_diffs(&diffs, &a_name, &b.display().to_string());
+        }
+        None => print_diffs(&backup::diff(&a, &read_config()?), &a_name, "FC"),
+    }
+
+    Ok(())
+}
+
+fn flash(path: &Path, expected: Option<FirmwareVersion>) -> Result<(), io::Error> {
+    let image = fs::read(path)?;
+    dfu::check_image(&image).map_err(invalid_input)?;
+
+    // This fails if the FC is already in its bootloader.
+    let _ = take_reading();
+
+    reboot_to_bootloader()?;
+
+    let mut last = None;
+    let version = update_firmware(&image, expected, |s| {
+        let percent = (s.done * 100).checked_div(s.total).unwrap_or(0);
+        if last != Some((s.stage, percent)) {
+            print!("\r{:?}: {}%   ", s.stage, percent);
+            let _ = io::stdout().flush();
+            last = Some((s.stage, percent));
+        }
+    })?;
+
+    println!(
+        "\nUpdated to firmware v{}.{}.{}",
+        version.major, version.minor, version.patch
+    );
+
+    Ok(())
+}
@@ -0,0 +1,603 @@
+//! Firmware updates over USB, using the STM32 system bootloader's DFU mode (ST's DfuSe extension;
+//! AN3156). The FC reboots into the bootloader on command, and enumerates as a DFU device instead
+//! of a serial port. We erase the pages the image covers, write it, read it back to verify, then
+//! start the new firmware.
+
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use rusb::{Context, DeviceHandle, Direction, Recipient, RequestType, UsbContext};
+use serde::Serialize;
+
+use crate::types::FirmwareVersion;
+
+/// The STM32 system bootloader's USB IDs.
+pub const BOOTLOADER_VID: u16 = 0x0483;
+pub const BOOTLOADER_PID: u16 = 0xdf11;
+
+/// Where the image is written, and where the FC starts executing from.
+pub const FLASH_BASE: u32 = 0x0800_0000;
+
+/// Larger than any STM32G4's flash, so anything bigger can't be firmware.
+pub const MAX_IMAGE_SIZE: usize = 512 * 1_024;
+
+/// A stack pointer outside SRAM means the image isn't firmware, or wasn't linked for this MCU.
+const SRAM_START: u32 = 0x2000_0000;
+const SRAM_END: u32 = 0x2002_0000;
+
+/// Flash is programmed in double words; pad the image to this, with erased (0xff) bytes.
+const WRITE_ALIGN: usize = 8;
+
+/// Used if the bootloader doesn't report its transfer size.
+const DEFAULT_TRANSFER_SIZE: u16 = 2_048;
+
+const USB_TIMEOUT: Duration = Duration::from_secs(5);
+
+// DFU class requests.
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_ABORT: u8 = 6;
+
+// DfuSe commands, sent as DNLOAD block 0.
+const DFUSE_SET_ADDRESS: u8 = 0x21;
+const DFUSE_ERASE_PAGE: u8 = 0x41;
+
+/// The DFU interface's class, and subclass.
+const DFU_CLASS: u8 = 0xfe;
+const DFU_SUBCLASS: u8 = 1;
+
+/// DFU functional descriptor type, in the interface's extra descriptor bytes.
+const DFU_FUNCTIONAL_DESCRIPTOR: u8 = 0x21;
+
+// DFU states reported by GETSTATUS.
+const STATE_IDLE: u8 = 2;
+const STATE_DNLOAD_IDLE: u8 = 5;
+const STATE_MANIFEST: u8 = 7;
+const STATE_UPLOAD_IDLE: u8 = 9;
+const STATE_ERROR: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashStage {
+    WaitingForBootloader,
+    Erasing,
+    Writing,
+    Verifying,
+    Restarting,
+    CheckingVersion,
+    Done,
+    Failed,
+}
+
+/// Progress of a firmware update, as reported over the API.
+#[derive(Clone, Debug, Serialize)]
+pub struct FlashStatus {
+    pub stage: FlashStage,
+    /// Bytes erased, written, or verified in this stage.
+    pub done: usize,
+    pub total: usize,
+    /// Set once done, or failed.
+    pub message: Option<String>,
+    /// The firmware version the FC reports after the update.
+    pub firmware_version: Option<FirmwareVersion>,
+}
+
+impl FlashStatus {
+    pub fn new(stage: FlashStage) -> Self {
+        Self {
+            stage,
+            done: 0,
+            total: 0,
+            message: None,
+            firmware_version: None,
+        }
+    }
+}
+
+/// Parse a version of the form `1.2.3`.
+pub fn parse_version(v: &str) -> Option<FirmwareVersion> {
+    let parts: Vec<u8> = v
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+
+    match parts[..] {
+        [major, minor, patch] => Some(FirmwareVersion {
+            major,
+            minor,
+            patch,
+        }),
+        _ => None,
+    }
+}
+
+fn word(image: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(image[i * 4..i * 4 + 4].try_into().unwrap())
+}
+
+/// Check that an image looks like firmware for the FC, before we touch the bootloader: It's a
+/// raw binary (not ELF, or Intel HEX), fits in flash, and starts with a vector table whose stack
+/// pointer is in SRAM, and whose reset handler is in the image.
+pub fn check_image(image: &[u8]) -> Result<(), String> {
+    if image.len() < 8 {
+        return Err("The image is too small to be firmware.".into());
+    }
+
+    if image.starts_with(b"\x7fELF") {
+        return Err(
+            "This is an ELF file. Convert it to a raw binary, eg with `objcopy -O binary`.".into(),
+        );
+    }
+
+    if image.starts_with(b":") {
+        return Err("This looks like an Intel HEX file. Use a raw binary (.bin) instead.".into());
+    }
+
+    if image.len() > MAX_IMAGE_SIZE {
+        return Err(format!(
+            "The image is {} bytes; flash holds at most {}.",
+            image.len(),
+            MAX_IMAGE_SIZE
+        ));
+    }
+
+    let sp = word(image, 0);
+    if !(SRAM_START..=SRAM_END).contains(&sp) {
+        return Err(format!(
+            "The image's initial stack pointer, {:#010x}, isn't in SRAM. It may not be firmware \
+            for this board.",
+            sp
+        ));
+    }
+
+    // Cortex-M vectors have the Thumb bit set.
+    let reset = word(image, 1);
+    let image_end = FLASH_BASE + image.len() as u32;
+    if reset & 1 == 0 || !(FLASH_BASE..image_end).contains(&(reset & !1)) {
+        return Err(format!(
+            "The image's reset handler, {:#010x}, isn't in the image. It may not be linked to run \
+            from {:#010x}.",
+            reset, FLASH_BASE
+        ));
+    }
+
+    Ok(())
+}
+
+/// Pad an image to whole flash writes.
+pub fn pad_image(image: &[u8]) -> Vec<u8> {
+    let mut result = image.to_vec();
+    result.resize(image.len().div_ceil(WRITE_ALIGN) * WRITE_ALIGN, 0xff);
+    result
+}
+
+/// Flash pages, from the bootloader's memory layout string, eg
+/// `@Internal Flash  /0x08000000/128*0002Kg`.
+#[derive(Clone, Debug)]
+pub struct FlashLayout {
+    /// (start address, size), in bytes.
+    pub pages: Vec<(u32, u32)>,
+}
+
+impl FlashLayout {
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('/').skip(1);
+        let mut addr =
+            u32::from_str_radix(parts.next()?.trim().trim_start_matches("0x"), 16).ok()?;
+
+        let mut pages = Vec::new();
+        for segment in parts.next()?.split(',') {
+            let (count, size) = segment.trim().split_once('*')?;
+            let count: u32 = count.parse().ok()?;
+
+            // eg `0002Kg`: A size, a unit, and the sector type.
+            let digits_end = size.find(|c: char| !c.is_ascii_digit())?;
+            let mut page_size: u32 = size[..digits_end].parse().ok()?;
+            match size[digits_end..].chars().next()? {
+                'K' => page_size *= 1_024,
+                'M' => page_size *= 1_024 * 1_024,
+                _ => (),
+            }
+
+            for _ in 0..count {
+                pages.push((addr, page_size));
+                addr += page_size;
+            }
+        }
+
+        Some(Self { pages })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.pages.iter().map(|(_, size)| size).sum()
+    }
+
+    /// Pages overlapping `len` bytes from `FLASH_BASE`.
+    fn pages_for(&self, len: usize) -> Vec<(u32, u32)> {
+        let end = FLASH_BASE + len as u32;
+        self.pages
+            .iter()
+            .filter(|(start, size)| *start < end && start + size > FLASH_BASE)
+            .copied()
+            .collect()
+    }
+}
+
+/// Whether an STM32 bootloader is on the USB bus, in DFU mode. This doesn't need permission to
+/// open it. False if we can't reach the USB stack.
+pub fn bootloader_present() -> bool {
+    Context::new()
+        .and_then(|context| context.devices())
+        .map(|devices| {
+            devices.iter().any(|d| {
+                d.device_descriptor().is_ok_and(|desc| {
+                    desc.vendor_id() == BOOTLOADER_VID && desc.product_id() == BOOTLOADER_PID
+                })
+            })
+        })
+        .unwrap_or(false)
+}
+
+fn usb_error(e: rusb::Error) -> io::Error {
+    match e {
+        rusb::Error::Access => io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "No permission to open the bootloader's USB device. On Linux, add a udev rule for \
+            0483:df11, or run as root.",
+        ),
+        e => io::Error::other(format!("USB error: {}", e)),
+    }
+}
+
+/// Find the transfer size in the DFU functional descriptor, among an interface's extra
+/// descriptors. Each starts with its length, then its type.
+fn transfer_size(extra: &[u8]) -> Option<u16> {
+    let mut i = 0;
+    while i + 1 < extra.len() {
+        let len = extra[i] as usize;
+        if len == 0 {
+            return None;
+        }
+        if extra[i + 1] == DFU_FUNCTIONAL_DESCRIPTOR && len >= 7 && i + len <= extra.len() {
+            return Some(u16::from_le_bytes([extra[i + 5], extra[i + 6]]));
+        }
+        i += len;
+    }
+
+    None
+}
+
+fn dfu_error(msg: String) -> io::Error {
+    io::Error::other(msg)
+}
+
+/// A connection to the STM32 bootloader, in DFU mode.
+pub struct Dfu {
+    handle: DeviceHandle<Context>,
+    interface: u16,
+    transfer_size: u16,
+    pub layout: FlashLayout,
+}
+
+impl Dfu {
+    /// Wait for the bootloader to enumerate, and connect to its internal flash.
+    pub fn open(timeout: Duration) -> Result<Self, io::Error> {
+        // Unlike rusb's global context, this reports a missing, or inaccessible USB stack as an
+        // error, instead of panicking.
+        let context = Context::new().map_err(usb_error)?;
+        let start = Instant::now();
+
+        let handle = loop {
+            if let Some(h) = context.open_device_with_vid_pid(BOOTLOADER_VID, BOOTLOADER_PID) {
+                break h;
+            }
+            if start.elapsed() > timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The FC didn't show up in bootloader (DFU) mode. Hold its boot button while \
+                    plugging it in, and try again.",
+                ));
+            }
+            thread::sleep(Duration::from_millis(200));
+        };
+
+        let config = handle.device().config_descriptor(0).map_err(usb_error)?;
+
+        // The internal flash is alternate setting 0. Its name holds the page layout, and the
+        // DFU functional descriptor holds the transfer size.
+        let descriptor = config
+            .interfaces()
+            .flat_map(|i| i.descriptors())
+            .find(|d| {
+                d.class_code() == DFU_CLASS
+                    && d.sub_class_code() == DFU_SUBCLASS
+                    && d.setting_number() == 0
+            })
+            .ok_or_else(|| dfu_error("The bootloader has no flash interface.".into()))?;
+
+        let interface = descriptor.interface_number();
+
+        // The functional descriptor usually follows the last alternate setting.
+        let transfer_size = config
+            .interfaces()
+            .flat_map(|i| i.descriptors())
+            .find_map(|d| transfer_size(d.extra()))
+            .unwrap_or(DEFAULT_TRANSFER_SIZE);
+
+        let layout_str = descriptor
+            .description_string_index()
+            .map(|i| handle.read_string_descriptor_ascii(i))
+            .transpose()
+            .map_err(usb_error)?
+            .unwrap_or_default();
+
+        let layout = FlashLayout::parse(&layout_str).ok_or_else(|| {
+            dfu_error(format!(
+                "Can't read the bootloader's flash layout: {:?}",
+                layout_str
+            ))
+        })?;
+
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle.claim_interface(interface).map_err(usb_error)?;
+        handle
+            .set_alternate_setting(interface, 0)
+            .map_err(usb_error)?;
+
+        let result = Self {
+            handle,
+            interface: interface as u16,
+            transfer_size,
+            layout,
+        };
+
+        // Clear anything left over from an interrupted update.
+        if result.get_status()?.1 == STATE_ERROR {
+            result.control_out(DFU_CLRSTATUS, 0, &[])?;
+        }
+        result.abort()?;
+
+        Ok(result)
+    }
+
+    fn control_out(&self, request: u8, value: u16, data: &[u8]) -> Result<(), io::Error> {
+        let request_type =
+            rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        self.handle
+            .write_control(
+                request_type,
+                request,
+                value,
+                self.interface,
+                data,
+                USB_TIMEOUT,
+            )
+            .map_err(usb_error)?;
+        Ok(())
+    }
+
+    fn control_in(&self, request: u8, value: u16, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let request_type =
+            rusb::request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        self.handle
+            .read_control(
+                request_type,
+                request,
+                value,
+                self.interface,
+                buf,
+                USB_TIMEOUT,
+            )
+            .map_err(usb_error)
+    }
+
+    /// Returns (status, state).
+    fn get_status(&self) -> Result<(u8, u8), io::Error> {
+        let mut buf = [0; 6];
+        self.control_in(DFU_GETSTATUS, 0, &mut buf)?;
+
+        // The bootloader tells us how long to wait before it's ready for the next request.
+        let poll_timeout = u32::from_le_bytes([buf[1], buf[2], buf[3], 0]);
+        thread::sleep(Duration::from_millis(poll_timeout as u64));
+
+        Ok((buf[0], buf[4]))
+    }
+
+    fn abort(&self) -> Result<(), io::Error> {
+        self.control_out(DFU_ABORT, 0, &[])
+    }
+
+    /// Send a download block, and wait for the bootloader to process it.
+    fn download(&self, block: u16, data: &[u8]) -> Result<(), io::Error> {
+        self.control_out(DFU_DNLOAD, block, data)?;
+
+        // The first status starts the operation; the second reports its result.
+        self.get_status()?;
+        let (status, state) = self.get_status()?;
+
+        if status != 0 || state != STATE_DNLOAD_IDLE {
+            return Err(dfu_error(format!(
+                "The bootloader reported an error (status {}, state {}). Flash may be \
+                write-protected.",
+                status, state
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn dfuse_command(&self, command: u8, addr: u32) -> Result<(), io::Error> {
+        let mut payload = [0; 5];
+        payload[0] = command;
+        payload[1..].clone_from_slice(&addr.to_le_bytes());
+
+        self.download(0, &payload)
+    }
+
+    /// Erase the pages an image of `len` bytes covers. `progress` is called with bytes erased,
+    /// and the total.
+    pub fn erase(
+        &mut self,
+        len: usize,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), io::Error> {
+        if len as u32 > self.layout.size() {
+            return Err(dfu_error(format!(
+                "The image is {} bytes, but the FC's flash is only {}.",
+                len,
+                self.layout.size()
+            )));
+        }
+
+        let pages = self.layout.pages_for(len);
+        let total = pages.iter().map(|(_, size)| *size as usize).sum();
+
+        let mut done = 0;
+        for (start, size) in pages {
+            self.dfuse_command(DFUSE_ERASE_PAGE, start)?;
+            done += size as usize;
+            progress(done, total);
+        }
+
+        Ok(())
+    }
+
+    /// Write an image from `FLASH_BASE`. Pad it with `pad_image` first.
+    pub fn write(
+        &mut self,
+        image: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), io::Error> {
+        self.dfuse_command(DFUSE_SET_ADDRESS, FLASH_BASE)?;
+
+        // Block n is written at the address pointer + (n - 2) * transfer size.
+        for (i, chunk) in image.chunks(self.transfer_size as usize).enumerate() {
+            self.download(2 + i as u16, chunk)?;
+            progress(i * self.transfer_size as usize + chunk.len(), image.len());
+        }
+
+        Ok(())
+    }
+
+    /// Read back flash from `FLASH_BASE`, and compare it with the image.
+    pub fn verify(
+        &mut self,
+        image: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), io::Error> {
+        self.dfuse_command(DFUSE_SET_ADDRESS, FLASH_BASE)?;
+        self.abort()?;
+
+        let mut buf = vec![0; self.transfer_size as usize];
+
+        for (i, chunk) in image.chunks(self.transfer_size as usize).enumerate() {
+            let read = self.control_in(DFU_UPLOAD, 2 + i as u16, &mut buf[..chunk.len()])?;
+
+            if buf[..read] != *chunk {
+                let offset = i * self.transfer_size as usize;
+                return Err(dfu_error(format!(
+                    "Flash doesn't match the image near {:#010x}.",
+                    FLASH_BASE + offset as u32
+                )));
+            }
+            progress(i * self.transfer_size as usize + chunk.len(), image.len());
+        }
+
+        let (_, state) = self.get_status()?;
+        if state == STATE_UPLOAD_IDLE {
+            self.abort()?;
+        }
+
+        Ok(())
+    }
+
+    /// Leave the bootloader, and start the firmware at `FLASH_BASE`.
+    pub fn start_firmware(self) -> Result<(), io::Error> {
+        self.dfuse_command(DFUSE_SET_ADDRESS, FLASH_BASE)?;
+        self.control_out(DFU_DNLOAD, 0, &[])?;
+
+        // The bootloader resets as it leaves, so it may not answer.
+        match self.get_status() {
+            Ok((_, state)) if state != STATE_MANIFEST && state != STATE_IDLE => {
+                Err(dfu_error(format!(
+                    "The bootloader didn't start the firmware (state {}).",
+                    state
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal image: a vector table with the stack pointer at the top of SRAM, and the reset
+    /// handler just after it.
+    fn image() -> Vec<u8> {
+        let mut result = vec![0; 0x200];
+        result[..4].copy_from_slice(&SRAM_END.to_le_bytes());
+        result[4..8].copy_from_slice(&(FLASH_BASE + 0x101).to_le_bytes());
+        result
+    }
+
+    #[test]
+    fn accepts_firmware() {
+        assert_eq!(check_image(&image()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(check_image(&[0; 4]).is_err());
+        assert!(check_image(b"\x7fELF\x01\x01\x01\x00").is_err());
+        assert!(check_image(b":020000040800F2\n").is_err());
+
+        let mut too_big = image();
+        too_big.resize(MAX_IMAGE_SIZE + 1, 0);
+        assert!(check_image(&too_big).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_vector_tables() {
+        let mut bad_sp = image();
+        bad_sp[..4].copy_from_slice(&FLASH_BASE.to_le_bytes());
+        assert!(check_image(&bad_sp).is_err());
+
+        // No Thumb bit.
+        let mut bad_reset = image();
+        bad_reset[4..8].copy_from_slice(&(FLASH_BASE + 0x100).to_le_bytes());
+        assert!(check_image(&bad_reset).is_err());
+
+        // Past the end of the image.
+        let mut bad_reset = image();
+        bad_reset[4..8].copy_from_slice(&(FLASH_BASE + 0x201).to_le_bytes());
+        assert!(check_image(&bad_reset).is_err());
+    }
+
+    #[test]
+    fn parses_versions() {
+        let v = |major, minor, patch| {
+            Some(FirmwareVersion {
+                major,
+                minor,
+                patch,
+            })
+        };
+
+        assert_eq!(parse_version("1.2.3"), v(1, 2, 3));
+        assert_eq!(parse_version(" v0.10.255 "), v(0, 10, 255));
+
+        assert_eq!(parse_version("1.2"), None);
+        assert_eq!(parse_version("1.2.3.4"), None);
+        assert_eq!(parse_version("1.x.3"), None);
+        assert_eq!(parse_version("1.2.256"), None);
+        assert_eq!(parse_version(""), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! Export recorded telemetry as CSV, or Apache Parquet. Each reading is flattened to one row,
+//! with a column per field, named by its dotted path; eg `link_stats.uplink_link_quality`.
+//! We add Euler angles derived from the attitude quaternion.
+
+use std::{collections::BTreeMap, io, sync::Arc};
+
+use parquet::{
+    basic::{LogicalType, Repetition, Type as PhysicalType},
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+use serde_json::Value;
+
+use crate::{history::Sample, to_euler, types::Quaternion};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Parquet,
+}
+
+impl Format {
+    /// Parse from a name, or file extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_ref() {
+            "csv" => Some(Self::Csv),
+            "parquet" | "pq" => Some(Self::Parquet),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+/// A flattened reading: column name to value.
+pub type Row = BTreeMap<String, Value>;
+
+/// Flatten nested objects and arrays to dotted paths. Arrays are indexed, eg `aux.0`.
+pub fn flatten(prefix: &str, v: &Value, row: &mut Row) {
+    match v {
+        Value::Object(map) => {
+            for (k, v) in map {
+                flatten(&join(prefix, k), v, row);
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                flatten(&join(prefix, &i.to_string()), v, row);
+            }
+        }
+        _ => {
+            row.insert(prefix.to_owned(), v.clone());
+        }
+    }
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn to_row(sample: &Sample) -> Row {
+    let mut row = Row::new();
+    row.insert("timestamp".into(), sample.timestamp.into());
+    flatten("", &sample.data, &mut row);
+
+    row
+}
+
+/// Add Euler angles, if the row has an attitude quaternion.
+fn add_euler(row: &mut Row) {
+    if !row.contains_key("attitude_quat.w") {
+        return;
+    }
+
+    let f = |k: &str| {
+        row.get(&format!("attitude_quat.{}", k))
+            .and_then(Value::as_f64)
+            .unwrap_or(0.) as f32
+    };
+    let (roll, pitch, yaw) = to_euler(&Quaternion {
+        w: f("w"),
+        x: f("x"),
+        y: f("y"),
+        z: f("z"),
+    });
+
+    row.insert("attitude_euler.roll".into(), (roll as f64).into());
+    row.insert("attitude_euler.pitch".into(), (pitch as f64).into());
+    row.insert("attitude_euler.yaw".into(), (yaw as f64).into());
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ColType {
+    Int,
+    Float,
+    Bool,
+    Str,
+}
+
+fn value_type(v: &Value) -> Option<ColType> {
+    match v {
+        Value::Null => None,
+        Value::Bool(_) => Some(ColType::Bool),
+        Value::Number(n) if n.is_i64() || n.is_u64() => Some(ColType::Int),
+        Value::Number(_) => Some(ColType::Float),
+        _ => Some(ColType::Str),
+    }
+}
+
+/// Column names in order, and the narrowest type that holds every value in each.
+fn columns(rows: &[Row]) -> Vec<(String, ColType)> {
+    let mut result: BTreeMap<String, Option<ColType>> = BTreeMap::new();
+
+    for row in rows {
+        for (name, v) in row {
+            let col = result.entry(name.clone()).or_insert(None);
+
+            *col = match (*col, value_type(v)) {
+                (c, None) => c,
+                (None, t) => t,
+                (Some(a), Some(b)) if a == b => Some(a),
+                (Some(ColType::Int), Some(ColType::Float))
+                | (Some(ColType::Float), Some(ColType::Int)) => Some(ColType::Float),
+                _ => Some(ColType::Str),
+            };
+        }
+    }
+
+    // Timestamp first; the rest alphabetical.
+    let mut result: Vec<(String, ColType)> = result
+        .into_iter()
+        .map(|(name, t)| (name, t.unwrap_or(ColType::Float)))
+        .collect();
+    result.sort_by_key(|(name, _)| name != "timestamp");
+
+    result
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn csv_value(v: Option<&Value>) -> String {
+    match v {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => csv_escape(s),
+        Some(v) => csv_escape(&v.to_string()),
+    }
+}
+
+/// Write rows as CSV, with a header row.
+fn to_csv(rows: &[Row]) -> Vec<u8> {
+    let cols = columns(rows);
+
+    let mut result = cols
+        .iter()
+        .map(|(name, _)| csv_escape(name))
+        .collect::<Vec<_>>()
+        .join(",");
+    result.push('\n');
+
+    for row in rows {
+        let line = cols
+            .iter()
+            .map(|(name, _)| csv_value(row.get(name)))
+            .collect::<Vec<_>>()
+            .join(",");
+        result.push_str(&line);
+        result.push('\n');
+    }
+
+    result.into_bytes()
+}
+
+fn parquet_err(e: parquet::errors::ParquetError) -> io::Error {
+    io::Error::other(format!("Problem writing Parquet: {}", e))
+}
+
+/// Write rows as Parquet, with a typed, optional column per field.
+fn to_parquet(rows: &[Row]) -> Result<Vec<u8>, io::Error> {
+    let cols = columns(rows);
+
+    let fields = cols
+        .iter()
+        .map(|(name, t)| {
+            let builder = match t {
+                ColType::Int => Type::primitive_type_builder(name, PhysicalType::INT64),
+                ColType::Float => Type::primitive_type_builder(name, PhysicalType::DOUBLE),
+                ColType::Bool => Type::primitive_type_builder(name, PhysicalType::BOOLEAN),
+                ColType::Str => Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                    .with_logical_type(Some(LogicalType::String)),
+            };
+
+            builder
+                .with_repetition(Repetition::OPTIONAL)
+                .build()
+                .map(Arc::new)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(parquet_err)?;
+
+    let schema = Type::group_type_builder("telemetry")
+        .with_fields(fields)
+        .build()
+        .map_err(parquet_err)?;
+
+    let props = Arc::new(WriterProperties::builder().build());
+    let mut writer =
+        SerializedFileWriter::new(Vec::new(), Arc::new(schema), props).map_err(parquet_err)?;
+
+    let mut row_group = writer.next_row_group().map_err(parquet_err)?;
+
+    for (name, t) in &cols {
+        let mut col = match row_group.next_column().map_err(parquet_err)? {
+            Some(c) => c,
+            None => break,
+        };
+
+        // Definition level 1 means present; 0 means null.
+        let values: Vec<Option<&Value>> = rows
+            .iter()
+            .map(|r| r.get(name).filter(|v| !v.is_null()))
+            .collect();
+        let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+        let present = values.iter().flatten();
+
+        match t {
+            ColType::Int => {
+                let v: Vec<i64> = present.map(|v| v.as_i64().unwrap_or(0)).collect();
+                col.typed::<Int64Type>()
+                    .write_batch(&v, Some(&def_levels), None)
+            }
+            ColType::Float => {
+                let v: Vec<f64> = present.map(|v| v.as_f64().unwrap_or(f64::NAN)).collect();
+                col.typed::<DoubleType>()
+                    .write_batch(&v, Some(&def_levels), None)
+            }
+            ColType::Bool => {
+                let v: Vec<bool> = present.map(|v| v.as_bool().unwrap_or(false)).collect();
+                col.typed::<BoolType>()
+                    .write_batch(&v, Some(&def_levels), None)
+            }
+            ColType::Str => {
+                let v: Vec<ByteArray> = present
+                    .map(|v| match v {
+                        Value::String(s) => ByteArray::from(s.as_str()),
+                        v => ByteArray::from(v.to_string().as_str()),
+                    })
+                    .collect();
+                col.typed::<ByteArrayType>()
+                    .write_batch(&v, Some(&def_levels), None)
+            }
+        }
+        .map_err(parquet_err)?;
+
+        col.close().map_err(parquet_err)?;
+    }
+
+    row_group.close().map_err(parquet_err)?;
+    writer.into_inner().map_err(parquet_err)
+}
+
+/// Export samples in the requested format.
+pub fn export<'a>(
+    samples: impl IntoIterator<Item = &'a Sample>,
+    format: Format,
+) -> Result<Vec<u8>, io::Error> {
+    export_rows(samples.into_iter().map(to_row).collect(), format)
+}
+
+/// Export flattened readings, eg from the history buffer, in the requested format.
+pub fn export_rows(mut rows: Vec<Row>, format: Format) -> Result<Vec<u8>, io::Error> {
+    for row in &mut rows {
+        add_euler(row);
+    }
+
+    match format {
+        Format::Csv => Ok(to_csv(&rows)),
+        Format::Parquet => to_parquet(&rows),
+    }
+}
@@ -0,0 +1,449 @@
+//! A bounded, in-memory history of readings, for charting things like voltage sag and link
+//! dropouts. Fields are addressed by dotted path into `ReadData`'s JSON, eg
+//! `link_stats.uplink_link_quality`. Readings can also be recorded to disk, for export.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::export::{self, Row};
+
+/// Recordings are saved here, one JSON sample per line.
+pub const RECORDINGS_DIR: &str = "recordings";
+
+/// Maximum number of samples we keep. At the 50ms refresh interval, this is 10 minutes.
+pub const HISTORY_LEN: usize = 12_000;
+
+/// Samples older than this are dropped, in ms.
+pub const HISTORY_MAX_AGE: i64 = 10 * 60 * 1_000;
+
+/// How often a recording is flushed to disk. If we crash, we lose about this much of it.
+const RECORDING_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sample {
+    /// ms since the UNIX epoch.
+    pub timestamp: i64,
+    pub data: Value,
+}
+
+/// A single reading of one field.
+#[derive(Serialize)]
+pub struct Point {
+    pub t: i64,
+    pub v: Value,
+}
+
+/// Aggregate of a numeric field over a time bucket. Non-numeric fields report the last value in
+/// the bucket, as `last`.
+#[derive(Serialize)]
+pub struct Bucket {
+    /// Start of the bucket, in ms since the UNIX epoch.
+    pub t: i64,
+    pub n: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub last: Value,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Series {
+    Raw(Vec<Point>),
+    Downsampled(Vec<Bucket>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Float,
+    Bool,
+    Text,
+}
+
+/// One field's values, aligned with `History::timestamps`. Stored as numbers, with NaN for null
+/// or missing; text values are indexes into `labels`. They're mostly enum names, so there are few.
+struct Column {
+    kind: Kind,
+    labels: Vec<String>,
+    values: VecDeque<f64>,
+}
+
+impl Column {
+    /// A column for `v`'s type, with `len` missing values before it.
+    fn new(v: &Value, len: usize) -> Option<Self> {
+        let kind = match v {
+            Value::Number(n) if n.is_i64() || n.is_u64() => Kind::Int,
+            Value::Number(_) => Kind::Float,
+            Value::Bool(_) => Kind::Bool,
+            Value::String(_) => Kind::Text,
+            _ => return None,
+        };
+
+        Some(Self {
+            kind,
+            labels: Vec::new(),
+            values: VecDeque::from(vec![f64::NAN; len]),
+        })
+    }
+
+    /// Values that don't match the column's type are stored as null.
+    fn push(&mut self, v: Option<&Value>) {
+        let encoded = match (self.kind, v) {
+            (Kind::Int | Kind::Float, Some(Value::Number(n))) => n.as_f64().unwrap_or(f64::NAN),
+            (Kind::Bool, Some(Value::Bool(b))) => *b as u8 as f64,
+            (Kind::Text, Some(Value::String(s))) => match self.labels.iter().position(|l| l == s) {
+                Some(i) => i as f64,
+                None => {
+                    self.labels.push(s.clone());
+                    (self.labels.len() - 1) as f64
+                }
+            },
+            _ => f64::NAN,
+        };
+
+        self.values.push_back(encoded);
+    }
+
+    fn get(&self, i: usize) -> Value {
+        let v = self.values[i];
+        if v.is_nan() {
+            return Value::Null;
+        }
+
+        match self.kind {
+            Kind::Int => (v as i64).into(),
+            Kind::Float => v.into(),
+            Kind::Bool => (v != 0.).into(),
+            Kind::Text => self.labels[v as usize].clone().into(),
+        }
+    }
+}
+
+/// Readings are kept by column, one per field, rather than as a JSON tree each; at `HISTORY_LEN`
+/// readings, those take tens of MB. We build JSON only when answering a query.
+#[derive(Default)]
+pub struct History {
+    timestamps: VecDeque<i64>,
+    columns: BTreeMap<String, Column>,
+}
+
+impl History {
+    pub fn push(&mut self, sample: &Sample) {
+        let mut row = Row::new();
+        export::flatten("", &sample.data, &mut row);
+
+        let len = self.timestamps.len();
+        for (field, v) in &row {
+            if !self.columns.contains_key(field) {
+                if let Some(col) = Column::new(v, len) {
+                    self.columns.insert(field.clone(), col);
+                }
+            }
+        }
+
+        self.timestamps.push_back(sample.timestamp);
+        for (field, col) in self.columns.iter_mut() {
+            col.push(row.get(field));
+        }
+
+        while self.timestamps.len() > HISTORY_LEN
+            || self
+                .timestamps
+                .front()
+                .is_some_and(|t| sample.timestamp - t > HISTORY_MAX_AGE)
+        {
+            self.timestamps.pop_front();
+            for col in self.columns.values_mut() {
+                col.values.pop_front();
+            }
+        }
+    }
+
+    /// Flattened readings, oldest first, for export.
+    pub fn rows(&self) -> Vec<Row> {
+        (0..self.timestamps.len())
+            .map(|i| {
+                let mut row = Row::new();
+                row.insert("timestamp".into(), self.timestamps[i].into());
+
+                for (field, col) in &self.columns {
+                    let v = col.get(i);
+                    if !v.is_null() {
+                        row.insert(field.clone(), v);
+                    }
+                }
+
+                row
+            })
+            .collect()
+    }
+
+    /// Return a time series for each requested field, for samples at or after `since` (ms since
+    /// the UNIX epoch). If `bucket` (ms) is set, downsample to min/max/mean per bucket.
+    pub fn query(
+        &self,
+        fields: &[&str],
+        since: Option<i64>,
+        bucket: Option<i64>,
+    ) -> Result<BTreeMap<String, Series>, String> {
+        // Validate field names, so typos don't silently return empty series.
+        if !self.timestamps.is_empty() {
+            for field in fields {
+                if !self.columns.contains_key(*field) {
+                    return Err(format!("Unknown field: {}", field));
+                }
+            }
+        }
+
+        if let Some(b) = bucket {
+            if b <= 0 {
+                return Err("Bucket size must be positive".into());
+            }
+        }
+
+        let since = since.unwrap_or(i64::MIN);
+        let start = self.timestamps.partition_point(|t| *t < since);
+        let mut result = BTreeMap::new();
+
+        for field in fields {
+            let points = self.columns.get(*field).into_iter().flat_map(|col| {
+                (start..self.timestamps.len()).map(move |i| Point {
+                    t: self.timestamps[i],
+                    v: col.get(i),
+                })
+            });
+
+            let series = match bucket {
+                Some(b) => Series::Downsampled(downsample(points, b)),
+                None => Series::Raw(points.collect()),
+            };
+
+            result.insert(field.to_string(), series);
+        }
+
+        Ok(result)
+    }
+}
+
+fn new_bucket(t: i64) -> Bucket {
+    Bucket {
+        t,
+        n: 0,
+        min: None,
+        max: None,
+        mean: None,
+        last: Value::Null,
+    }
+}
+
+/// Group time-ordered points into buckets of `size` ms.
+fn downsample(points: impl Iterator<Item = Point>, size: i64) -> Vec<Bucket> {
+    let mut result: Vec<Bucket> = Vec::new();
+    let mut sum = 0.;
+    let mut n_numeric = 0;
+
+    for point in points {
+        let t = point.t - point.t.rem_euclid(size);
+
+        if result.last().map(|b| b.t) != Some(t) {
+            result.push(new_bucket(t));
+            sum = 0.;
+            n_numeric = 0;
+        }
+
+        let bucket = result.last_mut().unwrap();
+        bucket.n += 1;
+
+        if let Some(v) = point.v.as_f64() {
+            bucket.min = Some(bucket.min.map_or(v, |m| m.min(v)));
+            bucket.max = Some(bucket.max.map_or(v, |m| m.max(v)));
+            sum += v;
+            n_numeric += 1;
+            bucket.mean = Some(sum / n_numeric as f64);
+        }
+
+        bucket.last = point.v;
+    }
+
+    result
+}
+
+/// Appends readings to a file in `RECORDINGS_DIR`, as JSON lines.
+pub struct Recorder {
+    pub path: PathBuf,
+    pub num_samples: usize,
+    file: BufWriter<File>,
+    last_flush: Instant,
+}
+
+impl Recorder {
+    /// Start a new recording, named by the current time.
+    pub fn start() -> Result<Self, io::Error> {
+        fs::create_dir_all(RECORDINGS_DIR)?;
+
+        let name = chrono::Local::now()
+            .format("%Y-%m-%d_%H-%M-%S.jsonl")
+            .to_string();
+        let path = Path::new(RECORDINGS_DIR).join(name);
+
+        Ok(Self {
+            file: BufWriter::new(File::create(&path)?),
+            path,
+            num_samples: 0,
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, sample: &Sample) -> Result<(), io::Error> {
+        serde_json::to_writer(&mut self.file, sample)?;
+        self.file.write_all(b"\n")?;
+        self.num_samples += 1;
+
+        if self.last_flush.elapsed() >= RECORDING_FLUSH_INTERVAL {
+            self.file.flush()?;
+            self.last_flush = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<PathBuf, io::Error> {
+        self.file.flush()?;
+        Ok(self.path)
+    }
+}
+
+/// Names of recordings in `RECORDINGS_DIR`, oldest first.
+pub fn list_recordings() -> Result<Vec<String>, io::Error> {
+    let mut result = Vec::new();
+
+    match fs::read_dir(RECORDINGS_DIR) {
+        Ok(entries) => {
+            for entry in entries {
+                let name = entry?.file_name().to_string_lossy().into_owned();
+                if name.ends_with(".jsonl") {
+                    result.push(name);
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+
+    result.sort();
+    Ok(result)
+}
+
+/// Load a recording from a path.
+pub fn load_recording(path: &Path) -> Result<Vec<Sample>, io::Error> {
+    let mut result = Vec::new();
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        result.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(result)
+}
+
+/// Load a recording in `RECORDINGS_DIR` by name. Rejects names that would escape the directory.
+pub fn load_named_recording(name: &str) -> Result<Vec<Sample>, io::Error> {
+    if name.contains('/') || name.contains('\\') || name.starts_with('.') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid recording name: {}", name),
+        ));
+    }
+
+    load_recording(&Path::new(RECORDINGS_DIR).join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn sample(timestamp: i64, data: Value) -> Sample {
+        Sample { timestamp, data }
+    }
+
+    fn raw(series: &Series) -> Vec<(i64, Value)> {
+        match series {
+            Series::Raw(points) => points.iter().map(|p| (p.t, p.v.clone())).collect(),
+            Series::Downsampled(_) => panic!("Expected raw points"),
+        }
+    }
+
+    #[test]
+    fn round_trips_fields() {
+        let mut history = History::default();
+        let data = json!({
+            "batt_v": 3.7_f32,
+            "altimeter_agl": null,
+            "controls": { "arm_status": "Disarmed", "aux": [172, 1811] },
+            "link_stats": { "timestamp": 1_700_000_000_123_i64 },
+            "battery": { "charging": false },
+        });
+        history.push(&sample(1_000, data.clone()));
+        history.push(&sample(
+            1_050,
+            json!({ "batt_v": 3.6_f32, "controls": { "arm_status": "Armed" } }),
+        ));
+
+        let fields = [
+            "batt_v",
+            "controls.arm_status",
+            "controls.aux.1",
+            "link_stats.timestamp",
+            "battery.charging",
+        ];
+        let result = history.query(&fields, None, None).unwrap();
+
+        assert_eq!(
+            raw(&result["batt_v"]),
+            [(1_000, data["batt_v"].clone()), (1_050, json!(3.6_f32))]
+        );
+        assert_eq!(
+            raw(&result["controls.arm_status"]),
+            [(1_000, json!("Disarmed")), (1_050, json!("Armed"))]
+        );
+        assert_eq!(
+            raw(&result["controls.aux.1"]),
+            [(1_000, json!(1811)), (1_050, Value::Null)]
+        );
+        assert_eq!(
+            raw(&result["link_stats.timestamp"])[0].1,
+            json!(1_700_000_000_123_i64)
+        );
+        assert_eq!(raw(&result["battery.charging"])[0].1, json!(false));
+
+        assert!(history.query(&["batt_volts"], None, None).is_err());
+        assert_eq!(
+            raw(&history.query(&["batt_v"], Some(1_001), None).unwrap()["batt_v"]).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn drops_old_samples() {
+        let mut history = History::default();
+        history.push(&sample(0, json!({ "batt_v": 4.2 })));
+        history.push(&sample(HISTORY_MAX_AGE + 1, json!({ "batt_v": 4.1 })));
+
+        assert_eq!(history.rows().len(), 1);
+        assert_eq!(history.rows()[0]["batt_v"], json!(4.1));
+    }
+}
@@ -0,0 +1,253 @@
+//! Radio link analysis: Tracks link quality and RSSI across readings, counts dropouts and
+//! failsafes, flags antenna-diversity imbalance and stale link data, and summarizes range tests.
+
+use std::{collections::VecDeque, time::Duration};
+
+use serde::Serialize;
+
+use crate::types::LinkStats;
+
+/// Uplink link quality, in %, below which we count a dropout.
+pub const DROPOUT_LQ: u8 = 50;
+
+/// If we haven't received link stats for this long, we consider them stale.
+pub const STALE_AFTER: Duration = Duration::from_secs(2);
+
+/// Antenna imbalance is judged over readings in this window, in ms.
+const BALANCE_WINDOW: i64 = 10_000;
+
+/// Difference between the antennas' mean RSSI, in dB, above which we flag an imbalance. A
+/// healthy diversity receiver's antennas are usually within a few dB of each other, unless one
+/// is shadowed by the frame.
+const IMBALANCE_DB: f32 = 10.;
+
+/// Link stats over a span of readings.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LinkSummary {
+    /// ms since the UNIX epoch.
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub num_samples: usize,
+    pub lq_min: Option<u8>,
+    pub lq_mean: Option<f32>,
+    /// Worst RSSI seen on each antenna, in dBm. Unreported (0dBm) readings are ignored.
+    pub rssi_1_min: Option<i16>,
+    pub rssi_2_min: Option<i16>,
+    /// Number of times LQ fell below `DROPOUT_LQ`.
+    pub dropouts: usize,
+    pub longest_dropout_ms: i64,
+    pub time_in_dropout_ms: i64,
+    /// Number of times the receiver reported an LQ of 0, ie lost the link entirely.
+    pub failsafes: usize,
+    /// The better antenna's RSSI at the first dropout. For a range test, this is roughly where
+    /// the link runs out of margin.
+    pub first_dropout_rssi: Option<i16>,
+    #[serde(skip)]
+    lq_sum: u64,
+    #[serde(skip)]
+    dropout_start: Option<i64>,
+    #[serde(skip)]
+    in_failsafe: bool,
+}
+
+fn min_opt<T: Ord + Copy>(a: Option<T>, b: T) -> Option<T> {
+    Some(a.map_or(b, |a| a.min(b)))
+}
+
+impl LinkSummary {
+    fn update(&mut self, ls: &LinkStats) {
+        let t = ls.timestamp;
+        let lq = ls.uplink_link_quality;
+
+        self.start.get_or_insert(t);
+        self.end = Some(t);
+        self.num_samples += 1;
+
+        self.lq_min = min_opt(self.lq_min, lq);
+        self.lq_sum += lq as u64;
+        self.lq_mean = Some(self.lq_sum as f32 / self.num_samples as f32);
+
+        if ls.uplink_rssi_1 != 0 {
+            self.rssi_1_min = min_opt(self.rssi_1_min, ls.uplink_rssi_1);
+        }
+        if ls.uplink_rssi_2 != 0 {
+            self.rssi_2_min = min_opt(self.rssi_2_min, ls.uplink_rssi_2);
+        }
+
+        match (lq < DROPOUT_LQ, self.dropout_start) {
+            (true, None) => {
+                self.dropouts += 1;
+                self.dropout_start = Some(t);
+
+                if self.first_dropout_rssi.is_none() {
+                    self.first_dropout_rssi = best_rssi(ls);
+                }
+            }
+            (false, Some(start)) => {
+                self.dropout_start = None;
+                self.close_dropout(start, t);
+            }
+            _ => (),
+        }
+
+        if lq == 0 && !self.in_failsafe {
+            self.failsafes += 1;
+        }
+        self.in_failsafe = lq == 0;
+    }
+
+    fn close_dropout(&mut self, start: i64, end: i64) {
+        let duration = end - start;
+        self.time_in_dropout_ms += duration;
+        self.longest_dropout_ms = self.longest_dropout_ms.max(duration);
+    }
+
+    /// A copy with any dropout in progress counted up to the latest reading.
+    fn snapshot(&self) -> Self {
+        let mut result = self.clone();
+
+        if let (Some(start), Some(end)) = (self.dropout_start, self.end) {
+            result.close_dropout(start, end);
+        }
+
+        result
+    }
+}
+
+/// The stronger of the two antennas' RSSI, ignoring unreported (0dBm) readings.
+fn best_rssi(ls: &LinkStats) -> Option<i16> {
+    [ls.uplink_rssi_1, ls.uplink_rssi_2]
+        .iter()
+        .filter(|r| **r != 0)
+        .max()
+        .copied()
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AntennaBalance {
+    /// Mean RSSI of each antenna over the last `BALANCE_WINDOW`, in dBm.
+    pub rssi_1_mean: Option<f32>,
+    pub rssi_2_mean: Option<f32>,
+    /// Antenna 1's mean minus antenna 2's, in dB.
+    pub difference_db: Option<f32>,
+    pub imbalanced: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LinkReport {
+    /// Time since we last received link stats from the FC, in ms. `None` if we never have.
+    pub age_ms: Option<u64>,
+    pub stale: bool,
+    /// The receiver currently reports a lost link. We can't tell this from stale data.
+    pub failsafe: bool,
+    pub session: LinkSummary,
+    pub antennas: AntennaBalance,
+    /// `None` if no range test is running, or has been run.
+    pub range_test: Option<RangeTest>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RangeTest {
+    pub running: bool,
+    pub summary: LinkSummary,
+}
+
+/// Tracks the link across readings.
+#[derive(Default)]
+pub struct LinkAnalyzer {
+    session: LinkSummary,
+    range_test: Option<RangeTest>,
+    /// (timestamp, RSSI 1, RSSI 2) over the last `BALANCE_WINDOW`.
+    recent: VecDeque<(i64, i16, i16)>,
+    latest_lq: Option<u8>,
+}
+
+impl LinkAnalyzer {
+    /// Update with a new reading. Its timestamp must be set.
+    pub fn update(&mut self, ls: &LinkStats) {
+        self.session.update(ls);
+
+        if let Some(test) = self.range_test.as_mut() {
+            if test.running {
+                test.summary.update(ls);
+            }
+        }
+
+        self.recent
+            .push_back((ls.timestamp, ls.uplink_rssi_1, ls.uplink_rssi_2));
+        while let Some((t, _, _)) = self.recent.front() {
+            if ls.timestamp - t <= BALANCE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+
+        self.latest_lq = Some(ls.uplink_link_quality);
+    }
+
+    /// Start a range test, discarding any previous one's results.
+    pub fn start_range_test(&mut self) {
+        self.range_test = Some(RangeTest {
+            running: true,
+            summary: LinkSummary::default(),
+        });
+    }
+
+    /// Stop the range test in progress, and return its summary.
+    pub fn stop_range_test(&mut self) -> Option<LinkSummary> {
+        let test = self.range_test.as_mut().filter(|t| t.running)?;
+        test.running = false;
+        test.summary = test.summary.snapshot();
+
+        Some(test.summary.clone())
+    }
+
+    fn antenna_balance(&self) -> AntennaBalance {
+        // Only compare readings where both antennas report; otherwise a single unreported
+        // antenna would look like a huge imbalance.
+        let both: Vec<(f32, f32)> = self
+            .recent
+            .iter()
+            .filter(|(_, r1, r2)| *r1 != 0 && *r2 != 0)
+            .map(|(_, r1, r2)| (*r1 as f32, *r2 as f32))
+            .collect();
+
+        if both.is_empty() {
+            return AntennaBalance {
+                rssi_1_mean: None,
+                rssi_2_mean: None,
+                difference_db: None,
+                imbalanced: false,
+            };
+        }
+
+        let n = both.len() as f32;
+        let rssi_1_mean = both.iter().map(|(r1, _)| r1).sum::<f32>() / n;
+        let rssi_2_mean = both.iter().map(|(_, r2)| r2).sum::<f32>() / n;
+        let difference = rssi_1_mean - rssi_2_mean;
+
+        AntennaBalance {
+            rssi_1_mean: Some(rssi_1_mean),
+            rssi_2_mean: Some(rssi_2_mean),
+            difference_db: Some(difference),
+            imbalanced: difference.abs() > IMBALANCE_DB,
+        }
+    }
+
+    /// `age` is the time since we last received link stats.
+    pub fn report(&self, age: Option<Duration>) -> LinkReport {
+        let stale = age.is_none_or(|a| a > STALE_AFTER);
+
+        LinkReport {
+            age_ms: age.map(|a| a.as_millis() as u64),
+            stale,
+            failsafe: !stale && self.latest_lq == Some(0),
+            session: self.session.snapshot(),
+            antennas: self.antenna_balance(),
+            range_test: self.range_test.as_ref().map(|t| RangeTest {
+                running: t.running,
+                summary: t.summary.snapshot(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Compass calibration. We collect raw magnetometer samples while the user rotates the aircraft
+//! through all orientations, then fit an ellipsoid to them. The ellipsoid's center is the
+//! hard-iron offset; its shape gives the soft-iron correction, which maps it back to a sphere.
+//!
+//! Corrected readings are `soft_iron * (raw - hard_iron)`.
+
+use serde::Serialize;
+
+use crate::types::MagCalibration;
+
+/// Samples closer than this to the previous one, as a fraction of the field strength, are
+/// skipped, so holding the aircraft still doesn't skew the fit.
+const MIN_SPACING: f32 = 0.02;
+
+/// Needed before we attempt a fit, and before we accept one.
+const MIN_SAMPLES_FIT: usize = 30;
+const MIN_SAMPLES_SAVE: usize = 100;
+
+/// Fraction of coverage bins that must have a sample before we accept a fit.
+const MIN_COVERAGE: f32 = 0.7;
+
+/// RMS deviation of corrected samples from the fitted sphere, as a fraction of its radius,
+/// above which we reject the fit.
+const MAX_RESIDUAL: f32 = 0.05;
+
+/// Coverage is judged over bins of equal area on the sphere: bands of equal height in z, split
+/// into equal azimuth sectors.
+pub const COVERAGE_BANDS: usize = 6;
+pub const COVERAGE_SECTORS: usize = 12;
+
+type Vec3 = [f64; 3];
+type Mat3 = [[f64; 3]; 3];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MagFit {
+    /// Offset to subtract from raw readings.
+    pub hard_iron: [f32; 3],
+    /// Matrix applied after removing the hard-iron offset, row-major.
+    pub soft_iron: [[f32; 3]; 3],
+    /// Mean field strength, in the magnetometer's units.
+    pub field_strength: f32,
+    /// RMS deviation of corrected samples from the sphere, as a fraction of its radius.
+    pub residual: f32,
+    /// Ratio of the ellipsoid's longest to shortest axis. Near 1 unless there's strong soft-iron
+    /// distortion nearby, eg from batteries or motors.
+    pub axis_ratio: f32,
+}
+
+impl MagFit {
+    /// The part of the fit the FC applies.
+    pub fn calibration(&self) -> MagCalibration {
+        MagCalibration {
+            hard_iron: self.hard_iron,
+            soft_iron: self.soft_iron,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MagCalStatus {
+    pub num_samples: usize,
+    /// Fraction of orientations covered, 0. to 1.
+    pub coverage: f32,
+    /// Which bins have samples. Bands from -z to +z, each with `COVERAGE_SECTORS` sectors.
+    pub coverage_bins: Vec<Vec<bool>>,
+    /// `None` until we have enough samples, or if the samples don't fit an ellipsoid.
+    pub fit: Option<MagFit>,
+    /// True if the fit is good enough to write to the FC.
+    pub acceptable: bool,
+    /// What to do next.
+    pub message: String,
+}
+
+/// A compass calibration in progress.
+#[derive(Default)]
+pub struct MagCalSession {
+    samples: Vec<[f32; 3]>,
+}
+
+impl MagCalSession {
+    pub fn add(&mut self, sample: [f32; 3]) {
+        if let Some(last) = self.samples.last() {
+            let strength = norm(&to_f64(last)).max(f64::EPSILON);
+            let dist = norm(&sub(&to_f64(&sample), &to_f64(last)));
+
+            if dist / strength < MIN_SPACING as f64 {
+                return;
+            }
+        }
+
+        self.samples.push(sample);
+    }
+
+    /// Fit the samples, and report coverage. Coverage is judged around the fitted center if we
+    /// have one, and the samples' mean otherwise.
+    pub fn status(&self) -> MagCalStatus {
+        let fit = if self.samples.len() >= MIN_SAMPLES_FIT {
+            fit_ellipsoid(&self.samples)
+        } else {
+            None
+        };
+
+        let center = match &fit {
+            Some(f) => to_f64(&f.hard_iron),
+            None => self.mean(),
+        };
+        let coverage_bins = self.coverage_bins(&center);
+        let num_bins = (COVERAGE_BANDS * COVERAGE_SECTORS) as f32;
+        let coverage = coverage_bins.iter().flatten().filter(|b| **b).count() as f32 / num_bins;
+
+        let (acceptable, message) = if self.samples.len() < MIN_SAMPLES_SAVE {
+            (
+                false,
+                "Keep rotating the aircraft slowly through every orientation.".to_owned(),
+            )
+        } else if coverage < MIN_COVERAGE {
+            (
+                false,
+                format!(
+                    "{:.0}% of orientations covered; need {:.0}%. Point each face of the aircraft \
+                    down in turn.",
+                    coverage * 100.,
+                    MIN_COVERAGE * 100.
+                ),
+            )
+        } else {
+            match &fit {
+                None => (
+                    false,
+                    "Samples don't fit an ellipsoid. Move away from metal and electronics, and \
+                    start over."
+                        .to_owned(),
+                ),
+                Some(f) if f.residual > MAX_RESIDUAL => (
+                    false,
+                    format!(
+                        "Poor fit ({:.1}% residual). Move away from metal and electronics, and \
+                        start over.",
+                        f.residual * 100.
+                    ),
+                ),
+                Some(f) => (
+                    true,
+                    format!(
+                        "Good fit ({:.1}% residual). Save to write it to the FC.",
+                        f.residual * 100.
+                    ),
+                ),
+            }
+        };
+
+        MagCalStatus {
+            num_samples: self.samples.len(),
+            coverage,
+            coverage_bins,
+            fit,
+            acceptable,
+            message,
+        }
+    }
+
+    fn mean(&self) -> Vec3 {
+        let n = self.samples.len().max(1) as f64;
+        let mut result = [0.; 3];
+
+        for s in &self.samples {
+            for i in 0..3 {
+                result[i] += s[i] as f64 / n;
+            }
+        }
+
+        result
+    }
+
+    fn coverage_bins(&self, center: &Vec3) -> Vec<Vec<bool>> {
+        let mut result = vec![vec![false; COVERAGE_SECTORS]; COVERAGE_BANDS];
+
+        for s in &self.samples {
+            let d = sub(&to_f64(s), center);
+            let len = norm(&d);
+            if len < f64::EPSILON {
+                continue;
+            }
+
+            // Bands of equal height in z have equal area, per Archimedes.
+            let z = (d[2] / len).clamp(-1., 1.);
+            let band = (((z + 1.) / 2. * COVERAGE_BANDS as f64) as usize).min(COVERAGE_BANDS - 1);
+
+            let azimuth = d[1].atan2(d[0]) + std::f64::consts::PI;
+            let sector = ((azimuth / std::f64::consts::TAU * COVERAGE_SECTORS as f64) as usize)
+                .min(COVERAGE_SECTORS - 1);
+
+            result[band][sector] = true;
+        }
+
+        result
+    }
+}
+
+fn to_f64(v: &[f32; 3]) -> Vec3 {
+    [v[0] as f64, v[1] as f64, v[2] as f64]
+}
+
+fn sub(a: &Vec3, b: &Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn norm(v: &Vec3) -> f64 {
+    (v[0].powi(2) + v[1].powi(2) + v[2].powi(2)).sqrt()
+}
+
+fn mat_vec(m: &Mat3, v: &Vec3) -> Vec3 {
+    let mut result = [0.; 3];
+    for (i, row) in m.iter().enumerate() {
+        result[i] = row[0] * v[0] + row[1] * v[1] + row[2] * v[2];
+    }
+    result
+}
+
+/// Solve `a x = b` by Gaussian elimination with partial pivoting. `None` if singular.
+fn solve<const N: usize>(mut a: [[f64; N]; N], mut b: [f64; N]) -> Option<[f64; N]> {
+    for col in 0..N {
+        let pivot = (col..N).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let (pivot_row, pivot_b) = (a[col], b[col]);
+        for (a_row, b_row) in a.iter_mut().zip(b.iter_mut()).skip(col + 1) {
+            let factor = a_row[col] / pivot_row[col];
+            for (v, p) in a_row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                *v -= factor * p;
+            }
+            *b_row -= factor * pivot_b;
+        }
+    }
+
+    let mut x = [0.; N];
+    for row in (0..N).rev() {
+        let sum: f64 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+
+    Some(x)
+}
+
+/// Eigenvalues, and eigenvectors as columns, of a symmetric matrix, by Jacobi rotation.
+fn symmetric_eigen(mut a: Mat3) -> (Vec3, Mat3) {
+    let mut v = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+
+    for _ in 0..50 {
+        // Zero the largest off-diagonal element.
+        let (p, q) = [(0, 1), (0, 2), (1, 2)]
+            .into_iter()
+            .max_by(|(i, j), (k, l)| a[*i][*j].abs().total_cmp(&a[*k][*l].abs()))
+            .unwrap();
+
+        if a[p][q].abs() < 1e-15 {
+            break;
+        }
+
+        let theta = (a[q][q] - a[p][p]) / (2. * a[p][q]);
+        let t = theta.signum() / (theta.abs() + (theta.powi(2) + 1.).sqrt());
+        let t = if theta == 0. { 1. } else { t };
+        let c = 1. / (t.powi(2) + 1.).sqrt();
+        let s = t * c;
+
+        let mut r = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+        r[p][p] = c;
+        r[q][q] = c;
+        r[p][q] = s;
+        r[q][p] = -s;
+
+        a = mat_mul(&transpose(&r), &mat_mul(&a, &r));
+        v = mat_mul(&v, &r);
+    }
+
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+fn transpose(m: &Mat3) -> Mat3 {
+    let mut result = [[0.; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            result[i][j] = m[j][i];
+        }
+    }
+    result
+}
+
+fn mat_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut result = [[0.; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            result[i][j] = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+/// Fit an ellipsoid `x' A x + 2 b' x = 1` by least squares, and derive the hard- and soft-iron
+/// corrections from it. `None` if the samples don't describe an ellipsoid, eg if they lie
+/// near a plane.
+pub fn fit_ellipsoid(samples: &[[f32; 3]]) -> Option<MagFit> {
+    // Normalize, so the normal equations are well-conditioned regardless of the magnetometer's
+    // units.
+    let scale = samples
+        .iter()
+        .map(|s| norm(&to_f64(s)))
+        .fold(0., f64::max)
+        .max(f64::EPSILON);
+
+    let mut ata = [[0.; 9]; 9];
+    let mut atb = [0.; 9];
+
+    for s in samples {
+        let [x, y, z] = to_f64(s).map(|v| v / scale);
+        let row = [
+            x * x,
+            y * y,
+            z * z,
+            2. * x * y,
+            2. * x * z,
+            2. * y * z,
+            2. * x,
+            2. * y,
+            2. * z,
+        ];
+
+        for i in 0..9 {
+            for j in 0..9 {
+                ata[i][j] += row[i] * row[j];
+            }
+            atb[i] += row[i];
+        }
+    }
+
+    let p = solve(ata, atb)?;
+
+    let a = [[p[0], p[3], p[4]], [p[3], p[1], p[5]], [p[4], p[5], p[2]]];
+    let b = [p[6], p[7], p[8]];
+
+    // Center: c = -A^-1 b. Then (x - c)' A (x - c) = 1 + c' A c.
+    let c = solve(a, b.map(|v| -v))?;
+    let k = 1.
+        + c.iter()
+            .zip(mat_vec(&a, &c))
+            .map(|(c, ac)| c * ac)
+            .sum::<f64>();
+    if k <= 0. {
+        return None;
+    }
+
+    let m = a.map(|row| row.map(|v| v / k));
+    let (eigenvalues, eigenvectors) = symmetric_eigen(m);
+    if eigenvalues.iter().any(|e| *e <= 0.) {
+        return None;
+    }
+
+    // Semi-axes are 1 / sqrt(eigenvalue). We map to a sphere of their geometric mean radius.
+    let radius = eigenvalues
+        .iter()
+        .map(|e| e.powf(-1. / 6.))
+        .product::<f64>();
+    let axes = eigenvalues.map(|e| 1. / e.sqrt());
+    let axis_ratio = axes.iter().cloned().fold(f64::MIN, f64::max)
+        / axes.iter().cloned().fold(f64::MAX, f64::min);
+
+    // soft_iron = radius * sqrt(M) = radius * V sqrt(D) V'
+    let mut sqrt_d = [[0.; 3]; 3];
+    for i in 0..3 {
+        sqrt_d[i][i] = eigenvalues[i].sqrt() * radius;
+    }
+    let soft_iron = mat_mul(&eigenvectors, &mat_mul(&sqrt_d, &transpose(&eigenvectors)));
+
+    let residual = (samples
+        .iter()
+        .map(|s| {
+            let corrected = mat_vec(&soft_iron, &sub(&to_f64(s).map(|v| v / scale), &c));
+            ((norm(&corrected) - radius) / radius).powi(2)
+        })
+        .sum::<f64>()
+        / samples.len() as f64)
+        .sqrt();
+
+    Some(MagFit {
+        hard_iron: c.map(|v| (v * scale) as f32),
+        soft_iron: soft_iron.map(|row| row.map(|v| v as f32)),
+        field_strength: (radius * scale) as f32,
+        residual: residual as f32,
+        axis_ratio: axis_ratio as f32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HARD_IRON: [f32; 3] = [12., -20., 5.];
+    const AXES: [f32; 3] = [1.2, 0.9, 1.];
+    const FIELD: f32 = 50.;
+
+    /// Points spread evenly over a sphere of radius `FIELD`, stretched along each axis, then
+    /// offset; what a magnetometer reads with hard, and soft-iron distortion.
+    fn samples(n: usize) -> Vec<[f32; 3]> {
+        let golden_angle = std::f32::consts::PI * (3. - 5_f32.sqrt());
+
+        (0..n)
+            .map(|i| {
+                let z = 1. - 2. * (i as f32 + 0.5) / n as f32;
+                let r = (1. - z * z).sqrt();
+                let theta = golden_angle * i as f32;
+                let unit = [r * theta.cos(), r * theta.sin(), z];
+
+                [0, 1, 2].map(|j| unit[j] * FIELD * AXES[j] + HARD_IRON[j])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fits_offset_scaled_samples() {
+        let samples = samples(300);
+        let fit = fit_ellipsoid(&samples).unwrap();
+
+        for (fitted, actual) in fit.hard_iron.iter().zip(HARD_IRON) {
+            assert!((fitted - actual).abs() < 0.01, "{:?}", fit);
+        }
+        assert!(fit.residual < 1e-3, "{:?}", fit);
+        assert!((fit.axis_ratio - 1.2 / 0.9).abs() < 1e-3, "{:?}", fit);
+
+        // Corrected readings lie on a sphere.
+        let soft_iron = fit.soft_iron.map(|row| row.map(|v| v as f64));
+        for s in &samples {
+            let corrected = mat_vec(&soft_iron, &sub(&to_f64(s), &to_f64(&fit.hard_iron)));
+            let err = (norm(&corrected) as f32 - fit.field_strength).abs() / fit.field_strength;
+            assert!(err < 1e-3, "{:?}", corrected);
+        }
+    }
+
+    #[test]
+    fn rejects_flat_samples() {
+        // Rotating about one axis only.
+        let flat: Vec<_> = samples(300).iter().map(|s| [s[0], s[1], 0.]).collect();
+
+        assert!(fit_ellipsoid(&flat).is_none());
+    }
+
+    #[test]
+    fn accepts_a_full_session() {
+        let mut session = MagCalSession::default();
+        for s in samples(300) {
+            session.add(s);
+        }
+
+        let status = session.status();
+        assert!(status.acceptable, "{}", status.message);
+        assert!(status.coverage >= MIN_COVERAGE);
+    }
+}
@@ -1,4 +1,3 @@
-#![feature(proc_macro_hygiene, decl_macro)]
 #![allow(non_snake_case)]
 
 #[macro_use]
@@ -6,73 +5,138 @@ extern crate rocket;
 
 // todo: So much repeated code etc here!!!
 
+use clap::Parser;
 use rocket::{
-    config::{Config, Environment, LoggingLevel},
-    data::{Data, FromDataSimple, Outcome},
-    Outcome::*,
-    Request,
+    config::{Config, LogLevel},
+    data::{self, Capped, Data, FromData, Limits, Outcome, ToByteUnit},
+    fs::FileServer,
+    http::{ContentType, CookieJar, Status},
+    response::{self, status::BadRequest, Responder, Response},
+    tokio, Request,
 };
 
-use serde::Serialize;
-use serde_json;
-
-use rocket_contrib::serve::StaticFiles;
+use num_enum::TryFromPrimitive;
+use serde::{Deserialize, Serialize};
 
 use std::{
+    collections::BTreeMap,
     convert::TryInto,
     f32::consts::TAU,
-    io::{self, Read},
-    mem, thread,
+    io::{self, Write},
+    process,
+    str::FromStr,
+    sync::Mutex,
+    thread,
     time::{self, Duration, Instant},
 };
 
-use chrono;
-
-use local_ipaddress;
 use serialport::{self, SerialPortType};
 
+mod auth;
+mod backup;
+mod battery;
+mod cli;
+mod dfu;
+mod export;
+mod history;
+mod link;
+mod mag_cal;
+mod mixing;
+mod net;
+mod params;
+mod pid;
+mod preflight;
+mod rc_cal;
+mod tui;
 mod types;
-
+mod version;
+
+use auth::{AuthError, Operator, Role, Viewer};
+use backup::{BoardInfo, ConfigBackup, ConfigDiff, RestoreReport};
+use battery::{Battery, BatteryStatus, PackProfiles};
+use cli::{Cli, Command, ServeArgs};
+use dfu::{Dfu, FlashStage, FlashStatus};
+use history::{History, Recorder, Sample};
+use link::LinkAnalyzer;
+use mag_cal::MagCalSession;
+use params::{Param, ParamTable};
+use pid::PidProfiles;
+use preflight::Thresholds;
+use rc_cal::RcCalProfile;
 use types::*;
+use version::{Compatibility, Handshake};
 
 // pub static mut PARAMS: Option<Params> = None;
-// todo: Don't make this static muts. Find some other way. Probably some combination of mutex and RC.
-static mut ATTITUDE: Quaternion = Quaternion {
-    w: 0.,
-    x: 0.,
-    y: 0.,
-    z: 0.,
-};
-static mut CONTROLS: Option<ChannelData> = None;
-static mut LINK_STATS: Option<LinkStats> = None;
-// static mut WAYPOINTS: [Option<Location>; MAX_WAYPOINTS] = unsafe { mem::zeroed() };
-static mut WAYPOINTS: [Option<Location>; MAX_WAYPOINTS] = [
-    None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-    None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-]; // todo lol
-static mut ALTIMETER: f32 = 0.;
-static mut ALTIMETER_AGL: Option<f32> = None;
-static mut BATT_V: f32 = 0.;
-static mut CURRENT: f32 = 0.;
-
-static mut LAST_PARAMS_UPDATE: Option<Instant> = None;
-static mut LAST_CONTROLS_UPDATE: Option<Instant> = None;
-static mut LAST_LINK_STATS_UPDATE: Option<Instant> = None;
-
-pub static mut AIRCRAFT_TYPE: AircraftType = AircraftType::Quadcopter;
-
-const FC_SERIAL_NUMBER: &'static str = "AN";
+// Readings, and FC state are shared between requests, the background poller, and the command
+// line, so each is behind a mutex. Hold one only briefly, and never while waiting on another,
+// except `FC`, which may be held while taking the others.
 
-const BAUD: u32 = 9_600;
+/// The connection to the FC. Opened on first use, and dropped after an error. Use `with_fc`.
+static FC: Mutex<Option<Fc>> = Mutex::new(None);
+
+/// The latest readings from the FC. `None` until the first.
+static READINGS: Mutex<Option<ReadData>> = Mutex::new(None);
+
+// Left, and right. Cached when read from, or written to the FC.
+static SERVO_CALS: Mutex<Option<[ServoCalibration; 2]>> = Mutex::new(None);
+
+static LAST_PARAMS_UPDATE: Mutex<Option<Instant>> = Mutex::new(None);
+static LAST_CONTROLS_UPDATE: Mutex<Option<Instant>> = Mutex::new(None);
+static LAST_LINK_STATS_UPDATE: Mutex<Option<Instant>> = Mutex::new(None);
+
+// Reported by the FC on connection; `None` until we've heard from it.
+static AIRCRAFT_INFO: Mutex<Option<AircraftInfo>> = Mutex::new(None);
+static HANDSHAKE: Mutex<Option<Handshake>> = Mutex::new(None);
+
+static THRESHOLDS: Mutex<Option<Thresholds>> = Mutex::new(None);
+
+static BATTERY_PROFILES: Mutex<Option<PackProfiles>> = Mutex::new(None);
+static BATTERY: Mutex<Option<Battery>> = Mutex::new(None);
+
+static HISTORY: Mutex<Option<History>> = Mutex::new(None);
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+static LINK_ANALYZER: Mutex<Option<LinkAnalyzer>> = Mutex::new(None);
+
+static MAG_CAL_SESSION: Mutex<Option<MagCalSession>> = Mutex::new(None);
+
+/// The FC's parameter descriptions, enumerated on first use.
+static PARAM_TABLE: Mutex<Option<ParamTable>> = Mutex::new(None);
+
+/// The firmware update in progress, or the latest one.
+static FLASH_STATUS: Mutex<Option<FlashStatus>> = Mutex::new(None);
 
+/// `require_disarmed` refuses if the arm status is older than this.
+const MAX_ARM_STATUS_AGE: Duration = Duration::from_millis(500);
 
+/// Time for the FC to show up after rebooting into the bootloader, or back into firmware.
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A stick calibration in progress, or just completed.
+static RC_CAL_SESSION: Mutex<Option<rc_cal::Session>> = Mutex::new(None);
+
+const FC_SERIAL_NUMBER: &str = "AN";
+
+const BAUD: u32 = 9_600;
+
+// How long to wait for a response from the FC, in ms.
+const SERIAL_TIMEOUT: u64 = 200;
 
 /// Convert radians to degrees
 fn to_degrees(v: f32) -> f32 {
     v * 360. / TAU
 }
 
-#[derive(Serialize, Default)]
+/// Convert an attitude quaternion to roll, pitch, and yaw, in degrees.
+fn to_euler(q: &Quaternion) -> (f32, f32, f32) {
+    let roll = (2. * (q.w * q.x + q.y * q.z)).atan2(1. - 2. * (q.x.powi(2) + q.y.powi(2)));
+    let pitch = (2. * (q.w * q.y - q.z * q.x)).clamp(-1., 1.).asin();
+    let yaw = (2. * (q.w * q.z + q.x * q.y)).atan2(1. - 2. * (q.y.powi(2) + q.z.powi(2)));
+
+    (to_degrees(roll), to_degrees(pitch), to_degrees(yaw))
+}
+
+#[derive(Clone, Serialize, Default)]
 struct ReadData {
     attitude_quat: Quaternion,
     altimeter: f32,
@@ -82,6 +146,8 @@ struct ReadData {
     controls: ChannelData,
     link_stats: LinkStats,
     waypoints: [Option<Location>; MAX_WAYPOINTS],
+    /// Computed locally from `batt_v` and `current`; not part of the FC's readings.
+    battery: Option<BatteryStatus>,
 }
 
 // Code in this section is a reverse of buffer <--> struct conversion in `usb_cfg`.
@@ -132,34 +198,83 @@ impl From<[u8; QUATERNION_SIZE]> for Quaternion {
     }
 }
 
-impl From<[u8; CONTROLS_SIZE]> for ChannelData {
-    /// 19 f32s x 4 = 76. In the order we have defined in the struct.
-    fn from(p: [u8; CONTROLS_SIZE]) -> Self {
-        ChannelData {
+impl TryFrom<[u8; CONTROLS_SIZE]> for ChannelData {
+    type Error = String;
+
+    /// 4 f32s, 6 switch bytes, then 12 u16 aux channels. In the order we have defined in the
+    /// struct. Fails on a switch state we don't know, eg from a firmware version mismatch.
+    fn try_from(p: [u8; CONTROLS_SIZE]) -> Result<Self, Self::Error> {
+        fn switch<T: TryFromPrimitive<Primitive = u8>>(name: &str, v: u8) -> Result<T, String> {
+            T::try_from_primitive(v)
+                .map_err(|_| format!("Unknown {} switch state from the FC: {}", name, v))
+        }
+
+        Ok(ChannelData {
             pitch: bytes_to_float(&p[0..4]),
             roll: bytes_to_float(&p[4..8]),
             yaw: bytes_to_float(&p[8..12]),
             throttle: bytes_to_float(&p[12..16]),
 
-            arm_status: p[16].try_into().unwrap(),
-            input_mode: p[17].try_into().unwrap(),
-        }
+            arm_status: switch("arm", p[16])?,
+            input_mode: switch("input mode", p[17])?,
+            alt_hold: switch("altitude hold", p[18])?,
+            auto_recover: switch("auto-recover", p[19])?,
+            autopilot: switch("autopilot", p[20])?,
+            obstacle_avoid: switch("obstacle avoidance", p[21])?,
+
+            aux: aux_from_buf(&p[22..CONTROLS_SIZE]),
+        })
+    }
+}
+
+/// Raw aux channel values, as u16s.
+fn aux_from_buf(p: &[u8]) -> [u16; AUX_CHANNELS] {
+    let mut result = [0; AUX_CHANNELS];
+
+    for (i, ch) in result.iter_mut().enumerate() {
+        *ch = u16::from_be_bytes([p[i * 2], p[i * 2 + 1]]);
     }
+
+    result
 }
 
 impl From<[u8; LINK_STATS_SIZE]> for LinkStats {
+    /// In CRSF link statistics frame order. RSSI values are sent as positive numbers of -dBm.
+    /// We set the timestamp on receipt.
     fn from(p: [u8; LINK_STATS_SIZE]) -> Self {
         LinkStats {
-            //     uplink_rssi_1: bytes_to_float(&p[0..4]),
-            //     uplink_rssi_2: bytes_to_float(&p[4..8]),
-            //     uplink_link_quality: bytes_to_float(&p[8..12]),
-            //     uplink_snr: bytes_to_float(&p[12..16]),
-            uplink_rssi_1: p[0],
-            uplink_rssi_2: p[1],
+            timestamp: 0,
+            uplink_rssi_1: -(p[0] as i16),
+            uplink_rssi_2: -(p[1] as i16),
             uplink_link_quality: p[2],
             uplink_snr: p[3] as i8,
-            uplink_tx_power: p[4],
-            ..Default::default() // other fields not used.
+            active_antenna: p[4],
+            rf_mode: p[5],
+            uplink_tx_power: p[6],
+            downlink_rssi: -(p[7] as i16),
+            downlink_link_quality: p[8],
+            downlink_snr: p[9] as i8,
+        }
+    }
+}
+
+impl From<[u8; IMU_CAL_STATUS_SIZE]> for ImuCalStatus {
+    /// Kind, state, progress, then gyro and accelerometer bias as 3 f32s each.
+    fn from(p: [u8; IMU_CAL_STATUS_SIZE]) -> Self {
+        ImuCalStatus {
+            kind: p[0].try_into().unwrap_or(ImuCalKind::None),
+            state: p[1].try_into().unwrap_or(ImuCalState::Failed),
+            progress: p[2],
+            gyro_bias: [
+                bytes_to_float(&p[3..7]),
+                bytes_to_float(&p[7..11]),
+                bytes_to_float(&p[11..15]),
+            ],
+            accel_bias: [
+                bytes_to_float(&p[15..19]),
+                bytes_to_float(&p[19..23]),
+                bytes_to_float(&p[23..27]),
+            ],
         }
     }
 }
@@ -170,15 +285,17 @@ fn waypoints_from_buf(w: [u8; WAYPOINTS_SIZE]) -> [Option<Location>; MAX_WAYPOIN
     // let mut result = [None; MAX_WAYPOINTS];
     let mut result = [(); MAX_WAYPOINTS].map(|_| Option::<Location>::default());
 
-    for i in 0..MAX_WAYPOINTS {
+    for (i, wp) in result.iter_mut().enumerate() {
         let wp_start_i = i * WAYPOINT_SIZE;
 
         // First bit per waypoint indicates if the Waypoint is used or not.
         // ie if 0, leave as None.
         if w[wp_start_i] == 1 {
+            // Names are null-padded.
             let name =
-                std::str::from_utf8(&w[wp_start_i + 1..wp_start_i + 1 + WAYPOINT_MAX_NAME_LEN])
-                    .unwrap();
+                String::from_utf8_lossy(&w[wp_start_i + 1..wp_start_i + 1 + WAYPOINT_MAX_NAME_LEN])
+                    .trim_end_matches('\0')
+                    .to_owned();
 
             let coords_start_i = wp_start_i + 1 + WAYPOINT_MAX_NAME_LEN;
 
@@ -186,12 +303,7 @@ fn waypoints_from_buf(w: [u8; WAYPOINTS_SIZE]) -> [Option<Location>; MAX_WAYPOIN
             let y = bytes_to_float(&w[coords_start_i + 4..coords_start_i + 8]);
             let z = bytes_to_float(&w[coords_start_i + 8..coords_start_i + 12]);
 
-            result[i] = Some(Location {
-                name: name.to_owned(),
-                x,
-                y,
-                z,
-            });
+            *wp = Some(Location { name, x, y, z });
         }
     }
 
@@ -199,53 +311,397 @@ fn waypoints_from_buf(w: [u8; WAYPOINTS_SIZE]) -> [Option<Location>; MAX_WAYPOIN
 }
 // }
 
-impl FromDataSimple for RotorPosition {
+/// Standalone fn instead of impl due to a Rust restriction. Names longer than
+/// `WAYPOINT_MAX_NAME_LEN` are truncated.
+fn waypoints_to_buf(waypoints: &[Option<Location>; MAX_WAYPOINTS]) -> [u8; WAYPOINTS_SIZE] {
+    let mut result = [0; WAYPOINTS_SIZE];
+
+    for (i, wp) in waypoints.iter().enumerate() {
+        if let Some(wp) = wp {
+            let wp_start_i = i * WAYPOINT_SIZE;
+            result[wp_start_i] = 1;
+
+            let name = wp.name.as_bytes();
+            let name_len = name.len().min(WAYPOINT_MAX_NAME_LEN);
+            result[wp_start_i + 1..wp_start_i + 1 + name_len].clone_from_slice(&name[..name_len]);
+
+            let coords_start_i = wp_start_i + 1 + WAYPOINT_MAX_NAME_LEN;
+            for (j, v) in [wp.x, wp.y, wp.z].iter().enumerate() {
+                let start = coords_start_i + j * 4;
+                result[start..start + 4].clone_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+
+    result
+}
+
+impl From<u8> for MotorDirections {
+    /// Packed bits: motors 1-4, R-L. True = CW.
+    fn from(p: u8) -> Self {
+        let dir = |motor: RotorPosition| {
+            if p & (1 << motor as u8) != 0 {
+                RotorDirection::Clockwise
+            } else {
+                RotorDirection::CounterClockwise
+            }
+        };
+
+        Self {
+            front_left: dir(RotorPosition::FrontLeft),
+            front_right: dir(RotorPosition::FrontRight),
+            aft_left: dir(RotorPosition::AftLeft),
+            aft_right: dir(RotorPosition::AftRight),
+        }
+    }
+}
+
+impl From<&MotorDirections> for u8 {
+    fn from(dirs: &MotorDirections) -> Self {
+        [
+            (RotorPosition::FrontLeft, dirs.front_left),
+            (RotorPosition::FrontRight, dirs.front_right),
+            (RotorPosition::AftLeft, dirs.aft_left),
+            (RotorPosition::AftRight, dirs.aft_right),
+        ]
+        .iter()
+        .filter(|(_, dir)| *dir == RotorDirection::Clockwise)
+        .fold(0, |acc, (motor, _)| acc | 1 << *motor as u8)
+    }
+}
+
+impl FromStr for RotorPosition {
+    type Err = String;
+
+    /// Parse the names the frontend, and command line use, eg `front-left`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "front-left" => Ok(Self::FrontLeft),
+            "front-right" => Ok(Self::FrontRight),
+            "aft-left" => Ok(Self::AftLeft),
+            "aft-right" => Ok(Self::AftRight),
+            _ => Err(format!(
+                "Unknown motor: {}. Use front-left, front-right, aft-left, or aft-right.",
+                s
+            )),
+        }
+    }
+}
+
+/// Read a request body as text, for the data guards below. `what` names it in errors.
+async fn read_body(
+    req: &Request<'_>,
+    data: Data<'_>,
+    what: &str,
+) -> Result<String, (Status, String)> {
+    let limit = req.limits().get("string").unwrap_or(Limits::STRING);
+
+    match data.open(limit).into_string().await {
+        Ok(s) if s.is_complete() => Ok(s.into_inner()),
+        Ok(_) => Err((
+            Status::PayloadTooLarge,
+            format!("Problem reading {}: it's over the size limit.", what),
+        )),
+        Err(e) => Err((
+            Status::BadRequest,
+            format!("Problem reading {}: {}", what, e),
+        )),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for RotorPosition {
     type Error = String;
 
-    fn from_data(req: &Request, data: Data) -> Outcome<Self, String> {
-        // Ensure the content type is correct before opening the data.
-        // if req.content_type() != Some(&person_ct) {
-        //     return Outcome::Forward(data);
-        // }
-
-        let mut contents = String::new();
-        data.open().read_to_string(&mut contents).unwrap();
-
-        Success(match contents.as_ref() {
-            "front-left" => Self::FrontLeft,
-            "front-right" => Self::FrontRight,
-            "aft-left" => Self::AftLeft,
-            "aft-right" => Self::AftRight,
-            _ => panic!("Invalid motor passed from the frontend."),
-        })
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let contents = match read_body(req, data, "motor").await {
+            Ok(c) => c,
+            Err(e) => return Outcome::Error(e),
+        };
+
+        match contents.parse() {
+            Ok(motor) => Outcome::Success(motor),
+            Err(e) => Outcome::Error((Status::BadRequest, e)),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
 struct SetServoPositionData {
     servo: ServoWingPosition,
-    value: f32
+    /// PWM high time, in ms.
+    value: f32,
 }
 
-impl FromDataSimple for SetServoPositionData{
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SetServoPositionData {
     type Error = String;
 
-    fn from_data(req: &Request, data: Data) -> Outcome<Self, String> {
+    /// Parses JSON of the form `{"servo": "left", "value": 1.5}`.
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let contents = match read_body(req, data, "servo data").await {
+            Ok(c) => c,
+            Err(e) => return Outcome::Error(e),
+        };
+
+        let result: Self = match serde_json::from_str(&contents) {
+            Ok(r) => r,
+            Err(e) => {
+                return Outcome::Error((Status::BadRequest, format!("Invalid servo data: {}", e)));
+            }
+        };
+
+        if !(SERVO_PWM_MIN..=SERVO_PWM_MAX).contains(&result.value) {
+            return Outcome::Error((
+                Status::BadRequest,
+                format!(
+                    "Servo position {}ms is outside the PWM limits of {}ms to {}ms.",
+                    result.value, SERVO_PWM_MIN, SERVO_PWM_MAX
+                ),
+            ));
+        }
+
+        Outcome::Success(result)
+    }
+}
+
+/// Standalone fn instead of impl due to a Rust restriction. Servo position, then 3 f32s, then
+/// the reversed flag.
+fn servo_cal_from_buf(
+    p: [u8; SERVO_CAL_SIZE],
+) -> Result<(ServoWingPosition, ServoCalibration), io::Error> {
+    let servo = p[0].try_into().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The FC sent calibration for an unknown servo: {}", p[0]),
+        )
+    })?;
+
+    Ok((
+        servo,
+        ServoCalibration {
+            min: bytes_to_float(&p[1..5]),
+            center: bytes_to_float(&p[5..9]),
+            max: bytes_to_float(&p[9..13]),
+            reversed: p[13] != 0,
+        },
+    ))
+}
+
+fn servo_cal_to_buf(servo: ServoWingPosition, cal: &ServoCalibration) -> [u8; SERVO_CAL_SIZE] {
+    let mut result = [0; SERVO_CAL_SIZE];
+
+    result[0] = servo as u8;
+    result[1..5].clone_from_slice(&cal.min.to_be_bytes());
+    result[5..9].clone_from_slice(&cal.center.to_be_bytes());
+    result[9..13].clone_from_slice(&cal.max.to_be_bytes());
+    result[13] = cal.reversed as u8;
+
+    result
+}
+
+/// Standalone fn instead of impl due to a Rust restriction. Stick axis, then 4 f32s, then the
+/// reversed flag.
+fn rc_cal_from_buf(p: [u8; RC_CAL_SIZE]) -> Result<(RcAxis, StickCalibration), io::Error> {
+    let axis = p[0].try_into().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The FC sent calibration for an unknown stick axis: {}",
+                p[0]
+            ),
+        )
+    })?;
+
+    Ok((
+        axis,
+        StickCalibration {
+            min: bytes_to_float(&p[1..5]),
+            center: bytes_to_float(&p[5..9]),
+            max: bytes_to_float(&p[9..13]),
+            deadband: bytes_to_float(&p[13..17]),
+            reversed: p[17] != 0,
+        },
+    ))
+}
+
+fn rc_cal_to_buf(axis: RcAxis, cal: &StickCalibration) -> [u8; RC_CAL_SIZE] {
+    let mut result = [0; RC_CAL_SIZE];
+
+    result[0] = axis as u8;
+    result[1..5].clone_from_slice(&cal.min.to_be_bytes());
+    result[5..9].clone_from_slice(&cal.center.to_be_bytes());
+    result[9..13].clone_from_slice(&cal.max.to_be_bytes());
+    result[13..17].clone_from_slice(&cal.deadband.to_be_bytes());
+    result[17] = cal.reversed as u8;
+
+    result
+}
+
+/// Standalone fn instead of impl due to a Rust restriction. For each of roll, pitch, and yaw:
+/// rate, then attitude gains as kp, ki, kd, kff. Then gyro, and D-term filter cutoffs.
+fn pid_from_buf(p: [u8; PID_SIZE]) -> PidConfig {
+    let f = |i: usize| bytes_to_float(&p[i * 4..i * 4 + 4]);
+    let gains = |i: usize| PidGains {
+        kp: f(i),
+        ki: f(i + 1),
+        kd: f(i + 2),
+        kff: f(i + 3),
+    };
+    let axis = |i: usize| AxisPid {
+        rate: gains(i),
+        attitude: gains(i + 4),
+    };
+
+    PidConfig {
+        roll: axis(0),
+        pitch: axis(8),
+        yaw: axis(16),
+        gyro_lpf_hz: f(24),
+        d_term_lpf_hz: f(25),
+    }
+}
+
+fn pid_to_buf(pid: &PidConfig) -> [u8; PID_SIZE] {
+    let mut values = Vec::with_capacity(PID_SIZE / 4);
+    for axis in [&pid.roll, &pid.pitch, &pid.yaw] {
+        for g in [&axis.rate, &axis.attitude] {
+            values.extend_from_slice(&[g.kp, g.ki, g.kd, g.kff]);
+        }
+    }
+    values.extend_from_slice(&[pid.gyro_lpf_hz, pid.d_term_lpf_hz]);
+
+    let mut result = [0; PID_SIZE];
+    for (i, v) in values.iter().enumerate() {
+        result[i * 4..i * 4 + 4].clone_from_slice(&v.to_be_bytes());
+    }
+
+    result
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for PidConfig {
+    type Error = String;
 
-        let mut contents = String::new();
-        data.open().read_to_string(&mut contents).unwrap();
+    /// Parses JSON of the form `{"roll": {"rate": {"kp": 0.1, ...}, "attitude": {...}}, ...,
+    /// "gyro_lpf_hz": 100., "d_term_lpf_hz": 100.}`, and checks ranges.
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let contents = match read_body(req, data, "PID gains").await {
+            Ok(c) => c,
+            Err(e) => return Outcome::Error(e),
+        };
 
-        let servo = match contents.as_ref() {
-            "left" => Self::Left,
-            "right" => Self::Right,
-            _ => panic!("Invalid servo passed from the frontend."),
+        let result: Self = match serde_json::from_str(&contents) {
+            Ok(r) => r,
+            Err(e) => {
+                return Outcome::Error((Status::BadRequest, format!("Invalid PID gains: {}", e)));
+            }
         };
 
-        // todo: How do we do this??
-        Success(Self {
-            servo,
+        if let Err(e) = result.validate() {
+            return Outcome::Error((Status::BadRequest, e));
+        }
+
+        Outcome::Success(result)
+    }
+}
 
-        })
+/// Standalone fn instead of impl, since the type may be one we don't know. Index, type, name,
+/// then min, max, and default values.
+fn param_info_from_buf(p: [u8; PARAM_INFO_SIZE]) -> Result<ParamInfo, io::Error> {
+    let index = u16::from_be_bytes([p[0], p[1]]);
+
+    let param_type: ParamType = p[2].try_into().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Parameter {} has an unknown type: {}", index, p[2]),
+        )
+    })?;
+
+    let name_end = 3 + PARAM_NAME_LEN;
+    let name = String::from_utf8_lossy(&p[3..name_end])
+        .trim_end_matches('\0')
+        .to_owned();
+
+    let value = |i: usize| {
+        let start = name_end + i * PARAM_VALUE_SIZE;
+        ParamValue::from_bytes(
+            param_type,
+            p[start..start + PARAM_VALUE_SIZE].try_into().unwrap(),
+        )
+    };
+
+    Ok(ParamInfo {
+        index,
+        name,
+        param_type,
+        min: value(0),
+        max: value(1),
+        default: value(2),
+    })
+}
+
+impl ServoCalibration {
+    /// Check that the endpoints are ordered, and within the servo PWM limits. We run this before
+    /// sending a calibration to the FC, since bad endpoints can drive a surface into its stops.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, v) in [
+            ("min", self.min),
+            ("center", self.center),
+            ("max", self.max),
+        ] {
+            if !(SERVO_PWM_MIN..=SERVO_PWM_MAX).contains(&v) {
+                return Err(format!(
+                    "Servo {} of {}ms is outside the PWM limits of {}ms to {}ms.",
+                    name, v, SERVO_PWM_MIN, SERVO_PWM_MAX
+                ));
+            }
+        }
+
+        if !(self.min < self.center && self.center < self.max) {
+            return Err(format!(
+                "Servo endpoints must satisfy min < center < max; got {}ms, {}ms, {}ms.",
+                self.min, self.center, self.max
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetServoCalibrationData {
+    servo: ServoWingPosition,
+    calibration: ServoCalibration,
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SetServoCalibrationData {
+    type Error = String;
+
+    /// Parses JSON of the form
+    /// `{"servo": "left", "calibration": {"min": 1., "center": 1.5, "max": 2., "reversed": false}}`.
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let contents = match read_body(req, data, "servo calibration").await {
+            Ok(c) => c,
+            Err(e) => return Outcome::Error(e),
+        };
+
+        let result: Self = match serde_json::from_str(&contents) {
+            Ok(r) => r,
+            Err(e) => {
+                return Outcome::Error((
+                    Status::BadRequest,
+                    format!("Invalid servo calibration: {}", e),
+                ));
+            }
+        };
+
+        if let Err(e) = result.calibration.validate() {
+            return Outcome::Error((Status::BadRequest, e));
+        }
+
+        Outcome::Success(result)
     }
 }
 
@@ -267,6 +723,8 @@ struct Fc {
 }
 
 impl Fc {
+    /// Connect to the FC. On first connection, negotiate the protocol version; refuse to
+    /// connect if the FC's message layouts are incompatible with ours.
     pub fn new() -> Result<Self, io::Error> {
         if let Ok(ports) = serialport::available_ports() {
             for port_info in &ports {
@@ -274,20 +732,41 @@ impl Fc {
                     if let Some(sn) = &info.serial_number {
                         if sn == FC_SERIAL_NUMBER {
                             let port = serialport::new(&port_info.port_name, BAUD)
+                                .timeout(Duration::from_millis(SERIAL_TIMEOUT))
                                 .open()
-                                // todo: Why is the console being spammed with this error?
-                                .unwrap();
-                            // .expect("Failed to open serial port");
-
-                            return Ok(Self { ser: port });
+                                .map_err(|e| {
+                                    io::Error::other(format!(
+                                        "Problem opening the flight controller on {}: {}",
+                                        port_info.port_name, e
+                                    ))
+                                })?;
+
+                            let mut result = Self { ser: port };
+
+                            if HANDSHAKE.lock().unwrap().is_none() {
+                                let firmware = result.read_firmware_info()?;
+                                *HANDSHAKE.lock().unwrap() = Some(Handshake::new(firmware));
+                            }
+
+                            let handshake = HANDSHAKE.lock().unwrap().clone();
+                            if let Some(Compatibility::Incompatible(msg)) =
+                                handshake.map(|h| h.compatibility)
+                            {
+                                return Err(io::Error::other(msg));
+                            }
+
+                            return Ok(result);
                         }
                     }
                 }
             }
         }
 
-        Err(io::Error::new(
-            io::ErrorKind::Other,
+        // The FC may have been unplugged; a different board, or firmware may show up next.
+        *HANDSHAKE.lock().unwrap() = None;
+        *AIRCRAFT_INFO.lock().unwrap() = None;
+
+        Err(io::Error::other(
             "Unable to connect to the flight controller.",
         ))
     }
@@ -297,19 +776,15 @@ impl Fc {
     pub fn read_all(&mut self) -> Result<ReadData, io::Error> {
         let mut result = ReadData::default();
 
-        let crc_tx_params = calc_crc(
-            &CRC_LUT,
-            &[MsgType::ReqParams as u8],
-            MsgType::ReqParams.payload_size() as u8 + 1,
-        );
+        let crc_tx_params = calc_crc(&CRC_LUT, &[MsgType::ReqParams as u8]);
         let xmit_buf_params = &[MsgType::ReqParams as u8, crc_tx_params];
 
         // Write the buffer requesting params from the FC.
-        self.ser.write(xmit_buf_params)?;
+        self.ser.write_all(xmit_buf_params)?;
 
         // Read the params passed by the FC in response.
         let mut rx_buf = [0; PARAMS_SIZE + 2];
-        self.ser.read(&mut rx_buf)?;
+        self.ser.read_exact(&mut rx_buf)?;
 
         // The order (or equivalently indices) of params here must match the FC firmware. Use it
         // as a reference.
@@ -335,338 +810,2425 @@ impl Fc {
         i += F32_BYTES;
 
         result.current = f32::from_be_bytes(rx_buf[i..F32_BYTES + i].try_into().unwrap());
-        i += F32_BYTES;
 
-        let crc_tx_controls = calc_crc(
-            &CRC_LUT,
-            &[MsgType::ReqControls as u8],
-            MsgType::ReqControls.payload_size() as u8 + 1,
-        );
+        let crc_tx_controls = calc_crc(&CRC_LUT, &[MsgType::ReqControls as u8]);
         let xmit_buf_controls = &[MsgType::ReqControls as u8, crc_tx_controls];
 
-        self.ser.write(xmit_buf_controls)?;
+        self.ser.write_all(xmit_buf_controls)?;
 
         // let mut rx_buf = [0; CONTROLS_SIZE + 2]; // todo: Bogus leading 1?
         let mut rx_buf = [0; CONTROLS_SIZE + 2];
-        self.ser.read(&mut rx_buf)?;
+        self.ser.read_exact(&mut rx_buf)?;
 
         thread::sleep(time::Duration::from_millis(5)); // todo TS
 
         let controls_data: [u8; CONTROLS_SIZE] = rx_buf[1..CONTROLS_SIZE + 1].try_into().unwrap();
-        result.controls = controls_data.into();
+        result.controls = controls_data
+            .try_into()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-        let crc_tx_link_stats = calc_crc(
-            &CRC_LUT,
-            &[MsgType::ReqLinkStats as u8],
-            MsgType::ReqLinkStats.payload_size() as u8 + 1,
-        );
-        let xmit_buf_link_stats = &[MsgType::ReqLinkStats as u8, crc_tx_link_stats];
+        let crc_tx_link_stats = calc_crc(&CRC_LUT, &[MsgType::ReqLinkStats as u8]);
+        let _xmit_buf_link_stats = &[MsgType::ReqLinkStats as u8, crc_tx_link_stats];
 
         // todo: DRY between these calls
-        // self.ser.write(xmit_buf_link_stats)?;  // todo put back
+        // self.ser.write_all(_xmit_buf_link_stats)?; // todo put back
 
         let mut rx_buf = [0; LINK_STATS_SIZE + 2];
-        self.ser.read(&mut rx_buf)?;
+        self.ser.read_exact(&mut rx_buf)?;
 
         thread::sleep(time::Duration::from_millis(5)); // todo TS
 
         let link_stats_data: [u8; LINK_STATS_SIZE] =
             rx_buf[1..LINK_STATS_SIZE + 1].try_into().unwrap();
         result.link_stats = link_stats_data.into();
+        result.link_stats.timestamp = chrono::Utc::now().timestamp_millis();
 
-        let crc_waypoints = calc_crc(
-            &CRC_LUT,
-            &[MsgType::ReqWaypoints as u8],
-            MsgType::ReqWaypoints.payload_size() as u8 + 1,
-        );
-        let xmit_buf_waypoints = &[MsgType::ReqWaypoints as u8, crc_waypoints];
+        let crc_waypoints = calc_crc(&CRC_LUT, &[MsgType::ReqWaypoints as u8]);
+        let _xmit_buf_waypoints = &[MsgType::ReqWaypoints as u8, crc_waypoints];
 
-        // self.ser.write(xmit_buf_waypoints)?; // todo put back
+        // self.ser.write_all(_xmit_buf_waypoints)?; // todo put back
 
         let mut rx_buf = [0; WAYPOINTS_SIZE + 2];
-        self.ser.read(&mut rx_buf)?;
+        self.ser.read_exact(&mut rx_buf)?;
 
         thread::sleep(time::Duration::from_millis(5)); // todo TS
 
         let mut wp_buf = [0; WAYPOINTS_SIZE];
         wp_buf.clone_from_slice(&rx_buf[1..WAYPOINTS_SIZE + 1]);
 
-        println!("WP BUF: {:?}", wp_buf);
-
         let waypoints_data = waypoints_from_buf(wp_buf);
 
         result.waypoints = waypoints_data;
 
-        let payload_size = MsgType::ReqParams.payload_size();
-        // let crc_rx_expected = calc_crc(
-        //     &CRC_LUT,
-        //     &rx_buf[..payload_size + 1],
-        //     payload_size as u8 + 1,
-        // );
-
         Ok(result)
     }
 
     pub fn send_arm_command(&mut self) -> Result<(), io::Error> {
         let msg_type = MsgType::ArmMotors;
-        let crc = calc_crc(
-            &CRC_LUT,
-            &[msg_type as u8],
-            msg_type.payload_size() as u8 + 1,
-        );
+        let crc = calc_crc(&CRC_LUT, &[msg_type as u8]);
         let xmit_buf = &[msg_type as u8, crc];
-        self.ser.write(xmit_buf)?;
+        self.ser.write_all(xmit_buf)?;
 
         Ok(())
     }
 
     pub fn send_disarm_command(&mut self) -> Result<(), io::Error> {
         let msg_type = MsgType::DisarmMotors;
-        let crc = calc_crc(
-            &CRC_LUT,
-            &[msg_type as u8],
-            msg_type.payload_size() as u8 + 1,
-        );
+        let crc = calc_crc(&CRC_LUT, &[msg_type as u8]);
         let xmit_buf = &[msg_type as u8, crc];
-        self.ser.write(xmit_buf)?;
+        self.ser.write_all(xmit_buf)?;
 
         Ok(())
     }
 
-    // todo: These are incomplete. you need to pass which motor etc.
     pub fn send_start_motor_command(&mut self, motor: RotorPosition) -> Result<(), io::Error> {
-        let msg_type = MsgType::StartMotor;
-        let crc = calc_crc(
-            &CRC_LUT,
-            &[msg_type as u8],
-            msg_type.payload_size() as u8 + 1,
-        );
-        let xmit_buf = &[msg_type as u8, motor as u8, crc];
-        self.ser.write(xmit_buf)?;
-
-        Ok(())
+        self.send_cmd(MsgType::StartMotor, &[motor as u8])
     }
 
     pub fn send_stop_motor_command(&mut self, motor: RotorPosition) -> Result<(), io::Error> {
-        let msg_type = MsgType::StopMotor;
-        let crc = calc_crc(
-            &CRC_LUT,
-            &[msg_type as u8],
-            msg_type.payload_size() as u8 + 1,
-        );
-        let xmit_buf = &[msg_type as u8, motor as u8, crc];
-        self.ser.write(xmit_buf)?;
+        self.send_cmd(MsgType::StopMotor, &[motor as u8])
+    }
 
-        Ok(())
+    pub fn send_set_servo_posit_command(
+        &mut self,
+        servo_posit: ServoWingPosition,
+        value: f32,
+    ) -> Result<(), io::Error> {
+        let mut payload = [0; SET_SERVO_POSIT_SIZE];
+        payload[0] = servo_posit as u8;
+        payload[1..5].clone_from_slice(&value.to_be_bytes());
+
+        self.send_cmd(MsgType::SetServoPosit, &payload)
     }
 
-    pub fn send_set_servo_posit_command(&mut self, servo_posit: ServoWingPosition, value: f32) -> Result<(), io::Error> {
-        let msg_type = MsgType::SetServoPosit;
-        let crc = calc_crc(
-            &CRC_LUT,
-            &[msg_type as u8],
-            msg_type.payload_size() as u8 + 1,
-        );
+    /// Read a servo's endpoint calibration from the FC.
+    pub fn read_servo_cal(
+        &mut self,
+        servo: ServoWingPosition,
+    ) -> Result<ServoCalibration, io::Error> {
+        self.send_cmd(MsgType::ReqServoCal, &[servo as u8])?;
 
-        let v = value.to_be_bytes();
-        let xmit_buf = &[msg_type as u8, servo_posit as u8, v[0], v[1], v[2], v[3], crc];
-        self.ser.write(xmit_buf)?;
+        let mut rx_buf = [0; SERVO_CAL_PACKET_SIZE];
+        self.read_msg(MsgType::ServoCal, &mut rx_buf)?;
 
-        Ok(())
+        let cal_data: [u8; SERVO_CAL_SIZE] = rx_buf[1..SERVO_CAL_SIZE + 1].try_into().unwrap();
+        let (servo_rx, cal) = servo_cal_from_buf(cal_data)?;
+
+        if servo_rx != servo {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The FC sent calibration for the wrong servo.",
+            ));
+        }
+
+        Ok(cal)
     }
 
-    /// Close the serial port
-    pub fn close(&mut self) {}
-}
+    /// Write a servo's endpoint calibration to the FC. Validate it first.
+    pub fn send_servo_cal(
+        &mut self,
+        servo: ServoWingPosition,
+        cal: &ServoCalibration,
+    ) -> Result<(), io::Error> {
+        cal.validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
 
-/// Get readings over JSON upon request from the browser, which we've cached.
-#[get("/data")]
-fn send_data() -> String {
-    let last_update = unsafe { LAST_PARAMS_UPDATE.as_ref().unwrap() };
+        self.send_cmd(MsgType::SetServoCal, &servo_cal_to_buf(servo, cal))
+    }
 
-    // Only update the readings from the FC if we're past the last updated thresh.
-    if (Instant::now() - *last_update) > Duration::new(0, REFRESH_INTERVAL * 1_000_000) {
-        if let Err(_) = get_data() {
-            // todo: Is this normal? Seems harmless, but I'd like to
-            // todo get to the bottom of it.
-            // println!("Problem getting readings; sending old.")
+    /// Read a stick axis's calibration from the FC.
+    pub fn read_rc_cal(&mut self, axis: RcAxis) -> Result<StickCalibration, io::Error> {
+        self.send_cmd(MsgType::ReqRcCal, &[axis as u8])?;
+
+        let mut rx_buf = [0; RC_CAL_PACKET_SIZE];
+        self.read_msg(MsgType::RcCal, &mut rx_buf)?;
+
+        let cal_data: [u8; RC_CAL_SIZE] = rx_buf[1..RC_CAL_SIZE + 1].try_into().unwrap();
+        let (axis_rx, cal) = rc_cal_from_buf(cal_data)?;
+
+        if axis_rx != axis {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The FC sent calibration for the wrong stick axis.",
+            ));
         }
 
-        unsafe { LAST_PARAMS_UPDATE = Some(Instant::now()) };
+        Ok(cal)
+    }
+
+    /// Write a stick axis's calibration to the FC. Validate it first.
+    pub fn send_rc_cal(&mut self, axis: RcAxis, cal: &StickCalibration) -> Result<(), io::Error> {
+        cal.validate(axis)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        self.send_cmd(MsgType::SetRcCal, &rc_cal_to_buf(axis, cal))
+    }
+
+    /// Command the FC to start a gyro bias, or level calibration.
+    pub fn start_imu_cal(&mut self, kind: ImuCalKind) -> Result<(), io::Error> {
+        self.send_cmd(MsgType::StartImuCal, &[kind as u8])
     }
 
-    // let params = unsafe { &PARAMS.as_ref().unwrap() };
-    // let controls = unsafe { &CONTROLS.as_ref().unwrap() };
+    /// Read the progress and results of the IMU calibration in progress, or the latest one.
+    pub fn read_imu_cal_status(&mut self) -> Result<ImuCalStatus, io::Error> {
+        self.send_cmd(MsgType::ReqImuCalStatus, &[])?;
+
+        let mut rx_buf = [0; IMU_CAL_STATUS_PACKET_SIZE];
+        self.read_msg(MsgType::ImuCalStatus, &mut rx_buf)?;
+
+        let status: [u8; IMU_CAL_STATUS_SIZE] =
+            rx_buf[1..IMU_CAL_STATUS_SIZE + 1].try_into().unwrap();
+
+        Ok(status.into())
+    }
+
+    /// Read an uncalibrated magnetometer reading.
+    pub fn read_mag_raw(&mut self) -> Result<[f32; 3], io::Error> {
+        self.send_cmd(MsgType::ReqMagRaw, &[])?;
+
+        let mut rx_buf = [0; MAG_RAW_PACKET_SIZE];
+        self.read_msg(MsgType::MagRaw, &mut rx_buf)?;
+
+        Ok([
+            bytes_to_float(&rx_buf[1..5]),
+            bytes_to_float(&rx_buf[5..9]),
+            bytes_to_float(&rx_buf[9..13]),
+        ])
+    }
 
-    // unsafe {
-    //     println!("Attitude: {} {}", ATTITUDE.w, ATTITUDE.x);
-    // }
+    /// Write hard-iron and soft-iron compass corrections to the FC.
+    pub fn send_mag_cal(&mut self, cal: &MagCalibration) -> Result<(), io::Error> {
+        let values = cal.hard_iron.iter().chain(cal.soft_iron.iter().flatten());
 
-    // todo: Better way than these globals?
-    let data = unsafe {
-        ReadData {
-            // params: PARAMS.clone().unwrap(),
-            attitude_quat: ATTITUDE,
-            altimeter: ALTIMETER,
-            altimeter_agl: ALTIMETER_AGL,
-            batt_v: BATT_V,
-            current: CURRENT,
-            controls: CONTROLS.clone().unwrap(),
-            link_stats: LINK_STATS.clone().unwrap(),
-            waypoints: WAYPOINTS.clone(),
+        let mut payload = [0; MAG_CAL_SIZE];
+        for (i, v) in values.enumerate() {
+            payload[i * 4..i * 4 + 4].clone_from_slice(&v.to_be_bytes());
         }
-    };
 
-    return serde_json::to_string(&data).unwrap_or("Problem serializing data".into());
-}
+        self.send_cmd(MsgType::SetMagCal, &payload)
+    }
 
-/// Arm all motors, for testing.
-#[post("/arm_motors")]
-fn arm_motors() -> Result<(), io::Error> {
-    println!("Arming motors...");
+    /// Read the compass corrections the FC is applying.
+    pub fn read_mag_cal(&mut self) -> Result<MagCalibration, io::Error> {
+        self.send_cmd(MsgType::ReqMagCal, &[])?;
 
-    // todo: fc_ should probably be a global of some sort.
-    // todo: DRY!
-    let fc_ = Fc::new();
+        let mut rx_buf = [0; MAG_CAL_PACKET_SIZE];
+        self.read_msg(MsgType::MagCal, &mut rx_buf)?;
 
-    if let Ok(mut fc) = fc_ {
-        fc.send_arm_command();
+        let v = |i: usize| bytes_to_float(&rx_buf[1 + i * 4..5 + i * 4]);
 
-        fc.close();
+        Ok(MagCalibration {
+            hard_iron: [v(0), v(1), v(2)],
+            soft_iron: [[v(3), v(4), v(5)], [v(6), v(7), v(8)], [v(9), v(10), v(11)]],
+        })
+    }
 
-        Ok(())
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Can't find the flight controller.",
-        ))
+    /// Write gyro and accelerometer biases to the FC, eg from a backup.
+    pub fn send_imu_bias(&mut self, bias: &ImuBias) -> Result<(), io::Error> {
+        let mut payload = [0; IMU_BIAS_SIZE];
+        for (i, v) in bias.gyro.iter().chain(bias.accel.iter()).enumerate() {
+            payload[i * 4..i * 4 + 4].clone_from_slice(&v.to_be_bytes());
+        }
+
+        self.send_cmd(MsgType::SetImuBias, &payload)
     }
-}
 
-/// Start a motor.
-#[post("/start_motor", data = "<data>")]
-fn start_motor(data: RotorPosition) -> Result<(), io::Error> {
-    println!("Starting motor {:?}", data);
+    pub fn read_motor_dirs(&mut self) -> Result<MotorDirections, io::Error> {
+        self.send_cmd(MsgType::ReqMotorDirs, &[])?;
+
+        let mut rx_buf = [0; MOTOR_DIRS_PACKET_SIZE];
+        self.read_msg(MsgType::MotorDirs, &mut rx_buf)?;
 
-    // todo: fc_ should probably be a global of some sort.
-    // todo: DRY!
-    let fc_ = Fc::new();
+        Ok(rx_buf[1].into())
+    }
 
-    if let Ok(mut fc) = fc_ {
-        fc.send_start_motor_command(data);
+    pub fn send_motor_dirs(&mut self, dirs: &MotorDirections) -> Result<(), io::Error> {
+        self.send_cmd(MsgType::SetMotorDirs, &[dirs.into()])
+    }
 
-        fc.close();
+    pub fn read_waypoints(&mut self) -> Result<[Option<Location>; MAX_WAYPOINTS], io::Error> {
+        self.send_cmd(MsgType::ReqWaypoints, &[])?;
 
-        Ok(())
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Can't find the flight controller.",
+        let mut rx_buf = [0; WAYPOINTS_PACKET_SIZE];
+        self.read_msg(MsgType::Waypoints, &mut rx_buf)?;
+
+        Ok(waypoints_from_buf(
+            rx_buf[1..WAYPOINTS_SIZE + 1].try_into().unwrap(),
         ))
     }
-}
 
-/// Start a motor.
-#[post("/stop_motor", data = "<data>")]
-fn stop_motor(data: RotorPosition) -> Result<(), io::Error> {
-    println!("Stopping motor {:?}", data);
+    /// Replace the FC's whole waypoint table.
+    pub fn send_waypoints(
+        &mut self,
+        waypoints: &[Option<Location>; MAX_WAYPOINTS],
+    ) -> Result<(), io::Error> {
+        self.send_cmd(MsgType::SetWaypoints, &waypoints_to_buf(waypoints))
+    }
 
-    let fc_ = Fc::new();
+    /// Read the PID gains, and filter cutoffs the FC is using.
+    pub fn read_pid(&mut self) -> Result<PidConfig, io::Error> {
+        self.send_cmd(MsgType::ReqPid, &[])?;
 
-    if let Ok(mut fc) = fc_ {
-        fc.send_stop_motor_command(data);
+        let mut rx_buf = [0; PID_PACKET_SIZE];
+        self.read_msg(MsgType::Pid, &mut rx_buf)?;
 
-        fc.close();
+        Ok(pid_from_buf(rx_buf[1..PID_SIZE + 1].try_into().unwrap()))
+    }
 
-        Ok(())
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Can't find the flight controller.",
-        ))
+    /// Write PID gains, and filter cutoffs to the FC. Validate them first.
+    pub fn send_pid(&mut self, pid: &PidConfig) -> Result<(), io::Error> {
+        pid.validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        self.send_cmd(MsgType::SetPid, &pid_to_buf(pid))
     }
-}
 
-/// Start a motor.
-#[post("/set_servo_position", data = "<data>")]
-fn set_servo_position(data: SetServoPositionData) -> Result<(), io::Error> {
-    println!("Set servo{:?}", data);
+    /// Read the number of parameters in the FC's table.
+    pub fn read_param_count(&mut self) -> Result<u16, io::Error> {
+        self.send_cmd(MsgType::ReqParamCount, &[])?;
 
-    let fc_ = Fc::new();
+        let mut rx_buf = [0; PARAM_COUNT_PACKET_SIZE];
+        self.read_msg(MsgType::ParamCount, &mut rx_buf)?;
 
-    if let Ok(mut fc) = fc_ {
-        fc.send_set_servo_posit_command(data);
+        Ok(u16::from_be_bytes([rx_buf[1], rx_buf[2]]))
+    }
 
-        fc.close();
+    /// Read a parameter's name, type, limits, and default.
+    pub fn read_param_info(&mut self, index: u16) -> Result<ParamInfo, io::Error> {
+        self.send_cmd(MsgType::ReqParamInfo, &index.to_be_bytes())?;
 
-        Ok(())
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Can't find the flight controller.",
-        ))
+        let mut rx_buf = [0; PARAM_INFO_PACKET_SIZE];
+        self.read_msg(MsgType::ParamInfo, &mut rx_buf)?;
+
+        let info = param_info_from_buf(rx_buf[1..PARAM_INFO_SIZE + 1].try_into().unwrap())?;
+
+        if info.index != index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The FC described the wrong parameter.",
+            ));
+        }
+
+        Ok(info)
     }
-}
 
-/// Request readings from the FC over USB/serial. Cache them as a
-/// global variable. Requesting the readings directly from the frontend could result in
-/// conflicts, where multiple frontends are requesting readings from the WM directly
-/// in too short an interval.
-fn get_data() -> Result<(), io::Error> {
-    let fc_ = Fc::new();
-    if let Ok(mut fc) = fc_ {
-        let data = fc.read_all().unwrap_or_default();
-
-        fc.close();
-
-        unsafe {
-            ATTITUDE = data.attitude_quat;
-            ALTIMETER = data.altimeter;
-            ALTIMETER_AGL = data.altimeter_agl;
-            BATT_V = data.batt_v;
-            CURRENT = data.current;
-            CONTROLS = Some(data.controls);
-            LINK_STATS = Some(data.link_stats);
-        };
+    /// Read a `ParamValue` reply for a parameter.
+    fn read_param_value_msg(&mut self, info: &ParamInfo) -> Result<ParamValue, io::Error> {
+        let mut rx_buf = [0; PARAM_VALUE_PACKET_SIZE];
+        self.read_msg(MsgType::ParamValue, &mut rx_buf)?;
 
-        Ok(())
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Can't find the flight controller.",
+        if u16::from_be_bytes([rx_buf[1], rx_buf[2]]) != info.index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The FC sent the value of the wrong parameter.",
+            ));
+        }
+
+        Ok(ParamValue::from_bytes(
+            info.param_type,
+            rx_buf[3..3 + PARAM_VALUE_SIZE].try_into().unwrap(),
         ))
     }
-}
 
-fn main() {
-    // unsafe { PARAMS = Some(Default::default()) };
-    unsafe {
-        CONTROLS = Some(Default::default());
-        LAST_PARAMS_UPDATE = Some(Instant::now());
-        LAST_CONTROLS_UPDATE = Some(Instant::now());
-        CONTROLS = Some(Default::default());
-        LINK_STATS = Some(Default::default());
-        WAYPOINTS = [(); MAX_WAYPOINTS].map(|_| Option::<Location>::default());
+    pub fn read_param_value(&mut self, info: &ParamInfo) -> Result<ParamValue, io::Error> {
+        self.send_cmd(MsgType::ReqParamValue, &info.index.to_be_bytes())?;
+        self.read_param_value_msg(info)
     }
 
-    crc_init(unsafe { &mut CRC_LUT }, CRC_POLY);
+    /// Write a parameter's value, and return the value the FC applied.
+    pub fn send_param_value(
+        &mut self,
+        info: &ParamInfo,
+        value: ParamValue,
+    ) -> Result<ParamValue, io::Error> {
+        let mut payload = [0; PARAM_VALUE_MSG_SIZE];
+        payload[0..2].clone_from_slice(&info.index.to_be_bytes());
+        payload[2..].clone_from_slice(&value.to_bytes(info.param_type));
+
+        self.send_cmd(MsgType::SetParamValue, &payload)?;
+        self.read_param_value_msg(info)
+    }
 
-    println!(
-        "AnyLeaf Preflight has launched. You can connect by opening `localhost` in a \
-    web browser on this computer, or by navigating to `{}` on another device on this network, \
-    like your phone.\n",
-        local_ipaddress::get().unwrap_or("(Problem finding IP address)".into())
-    );
+    /// Read the left and right servo pulse widths the FC is currently commanding, in ms.
+    pub fn read_servo_outputs(&mut self) -> Result<(f32, f32), io::Error> {
+        self.send_cmd(MsgType::ReqServoOutputs, &[])?;
 
-    let config = Config::build(Environment::Staging)
-        // .address("1.2.3.4")
-        .port(30)
-        .log_level(LoggingLevel::Critical) // Don't show the user the connections.
-        .finalize()
-        .expect("Problem setting up our custom config");
+        let mut rx_buf = [0; SERVO_OUTPUTS_PACKET_SIZE];
+        self.read_msg(MsgType::ServoOutputs, &mut rx_buf)?;
 
-    rocket::custom(config)
-        .mount("/", StaticFiles::from("static"))
-        .mount("/api", routes![send_data, arm_motors, start_motor])
-        .launch();
+        Ok((bytes_to_float(&rx_buf[1..5]), bytes_to_float(&rx_buf[5..9])))
+    }
+
+    /// Request firmware version, protocol version, board ID, and feature bits from the FC.
+    pub fn read_firmware_info(&mut self) -> Result<FirmwareInfo, io::Error> {
+        self.send_cmd(MsgType::ReqFirmwareInfo, &[])?;
+
+        let mut rx_buf = [0; FIRMWARE_INFO_PACKET_SIZE];
+        self.read_msg(MsgType::FirmwareInfo, &mut rx_buf)
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "No valid response to the version handshake ({}). The firmware may \
+                        predate protocol versioning; update it.",
+                        e
+                    ),
+                )
+            })?;
+
+        Ok(FirmwareInfo {
+            firmware_version: FirmwareVersion {
+                major: rx_buf[1],
+                minor: rx_buf[2],
+                patch: rx_buf[3],
+            },
+            protocol_version: ProtocolVersion {
+                major: rx_buf[4],
+                minor: rx_buf[5],
+            },
+            board_id: u16::from_be_bytes(rx_buf[6..8].try_into().unwrap()),
+            features: u32::from_be_bytes(rx_buf[8..12].try_into().unwrap()),
+        })
+    }
+
+    /// Reboot into the system bootloader, for a firmware update.
+    pub fn send_reboot_to_bootloader(&mut self) -> Result<(), io::Error> {
+        self.send_cmd(MsgType::RebootToBootloader, &[])
+    }
+
+    /// Read the airframe type, and firmware build from the FC.
+    pub fn read_aircraft_info(&mut self) -> Result<AircraftInfo, io::Error> {
+        self.send_cmd(MsgType::ReqAircraftInfo, &[])?;
+
+        let mut rx_buf = [0; AIRCRAFT_INFO_PACKET_SIZE];
+        self.read_msg(MsgType::AircraftInfo, &mut rx_buf)?;
+
+        let aircraft_type = AircraftType::try_from(rx_buf[1]).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown aircraft type reported by the FC: {}", rx_buf[1]),
+            )
+        })?;
+
+        Ok(AircraftInfo {
+            aircraft_type,
+            firmware_build: u32::from_be_bytes(rx_buf[2..6].try_into().unwrap()),
+        })
+    }
+
+    /// Read a full packet from the FC into `buf`, and check its message type and CRC.
+    fn read_msg(&mut self, msg_type: MsgType, buf: &mut [u8]) -> Result<(), io::Error> {
+        self.ser.read_exact(buf)?;
+
+        if buf[0] != msg_type as u8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected message type {}; received {}.",
+                    msg_type as u8, buf[0]
+                ),
+            ));
+        }
+
+        let crc_i = buf.len() - 1;
+        if calc_crc(&CRC_LUT, &buf[..crc_i]) != buf[crc_i] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "CRC mismatch on a message from the FC.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Send a message to the FC, with a CRC computed over the message type and full payload.
+    fn send_cmd(&mut self, msg_type: MsgType, payload: &[u8]) -> Result<(), io::Error> {
+        self.ser.write_all(&frame_msg(msg_type, payload))
+    }
+}
+
+/// Talk to the FC, connecting first if we aren't. The connection is held for all of `f`, so
+/// exchanges from the poller, routes, and command line don't interleave on the wire. `f` must not
+/// call anything that uses `with_fc` itself, eg the `require_` checks; it would deadlock.
+fn with_fc<T>(f: impl FnOnce(&mut Fc) -> Result<T, io::Error>) -> Result<T, io::Error> {
+    let mut fc = FC.lock().unwrap();
+
+    if fc.is_none() {
+        *fc = Some(Fc::new()?);
+    }
+
+    let result = f(fc.as_mut().unwrap());
+
+    // The FC may have been unplugged, or left partway through a response; reconnect next time.
+    if result.is_err() {
+        *fc = None;
+    }
+
+    result
+}
+
+/// Get the airframe type and firmware build, from the FC if we haven't yet.
+fn aircraft_info() -> Result<AircraftInfo, io::Error> {
+    let cached = *AIRCRAFT_INFO.lock().unwrap();
+    if let Some(info) = cached {
+        return Ok(info);
+    }
+
+    let info = with_fc(|fc| fc.read_aircraft_info())?;
+    *AIRCRAFT_INFO.lock().unwrap() = Some(info);
+
+    Ok(info)
+}
+
+/// Return an error if the connected aircraft isn't of the type required by a route. This keeps
+/// motor commands away from wings, and servo commands away from quads.
+fn require_aircraft_type(required: AircraftType) -> Result<(), io::Error> {
+    let info = aircraft_info()?;
+
+    if info.aircraft_type != required {
+        return Err(io::Error::other(format!(
+            "This command requires a {:?}, but the FC reports a {:?}.",
+            required, info.aircraft_type
+        )));
+    }
+
+    Ok(())
+}
+
+/// Return an error unless a fresh reading shows the aircraft disarmed. For commands that are
+/// unsafe with spinning props, or that need the aircraft still. If we can't get a reading, we
+/// refuse; the cached one may be stale, or the default, which reads as disarmed.
+fn require_disarmed() -> Result<(), io::Error> {
+    take_reading()
+        .map_err(|e| io::Error::other(format!("Can't confirm the motors are disarmed: {}", e)))?;
+
+    let fresh = LAST_CONTROLS_UPDATE
+        .lock()
+        .unwrap()
+        .is_some_and(|t| t.elapsed() < MAX_ARM_STATUS_AGE);
+    if !fresh {
+        return Err(io::Error::other(
+            "Can't confirm the motors are disarmed: No recent reading from the FC.",
+        ));
+    }
+
+    if cached_data().controls.arm_status != ArmStatus::Disarmed {
+        return Err(io::Error::other("Disarm the motors first."));
+    }
+
+    Ok(())
+}
+
+/// Return an error if the FC doesn't report a feature.
+fn require_feature(feature: u32, name: &str) -> Result<(), io::Error> {
+    let supported = HANDSHAKE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|h| h.has_feature(feature))
+        .unwrap_or(false);
+
+    if !supported {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("The FC firmware doesn't support {}.", name),
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Info {
+    /// `None` if we refused to talk to the FC after the handshake.
+    aircraft: Option<AircraftInfo>,
+    handshake: Handshake,
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    role: Role,
+}
+
+/// Start a session, or continue one, setting the session, and CSRF cookies. The page calls this
+/// first.
+#[get("/session")]
+fn session(cookies: &CookieJar<'_>) -> String {
+    let info = SessionInfo {
+        role: auth::start_session(cookies),
+    };
+
+    serde_json::to_string(&info).unwrap_or("Problem serializing data".into())
+}
+
+/// Pair this session using the PIN printed in the console, giving it control of the aircraft.
+#[post("/pair", data = "<pin>")]
+fn pair(viewer: Viewer, pin: String) -> Result<(), BadRequest<String>> {
+    auth::pair(&viewer, &pin).map_err(BadRequest)
+}
+
+/// Return this session to read-only.
+#[post("/unpair")]
+fn unpair(viewer: Viewer) {
+    auth::unpair(&viewer);
+}
+
+/// Send the reason a session or pairing check failed, instead of Rocket's error page.
+#[catch(401)]
+fn unauthorized(req: &Request) -> String {
+    auth_error(req)
+}
+
+#[catch(403)]
+fn forbidden(req: &Request) -> String {
+    auth_error(req)
+}
+
+fn auth_error(req: &Request) -> String {
+    let AuthError(msg) = req.local_cache(|| AuthError("Not allowed.".into()));
+    msg.clone()
+}
+
+/// Get the airframe type, firmware and protocol versions, and features, as reported by the FC.
+#[get("/info")]
+async fn info() -> Result<String, io::Error> {
+    blocking(move || {
+        let aircraft = aircraft_info();
+
+        // If the handshake succeeded, report it even if we're refusing to talk to the FC.
+        let handshake = HANDSHAKE.lock().unwrap().clone();
+        let handshake = match handshake {
+            Some(h) => h,
+            None => {
+                return Err(aircraft
+                    .err()
+                    .unwrap_or_else(|| io::Error::other("No handshake with the FC yet.")))
+            }
+        };
+
+        let aircraft = aircraft.ok();
+
+        Ok(serde_json::to_string(&Info {
+            aircraft,
+            handshake,
+        })
+        .unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Run blocking work, eg talking to the FC, or the disk, on Tokio's blocking thread pool, so it
+/// doesn't hold up the async workers serving other requests.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .expect("Blocking task panicked")
+}
+
+/// Poll the FC for readings in the background, caching them for `/data`, and the history buffer.
+async fn serial_worker() {
+    let mut interval = tokio::time::interval(Duration::from_millis(REFRESH_INTERVAL as u64));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        // A panic here, eg from the serial port, shouldn't stop polling.
+        let _ = tokio::task::spawn_blocking(|| {
+            let _ = get_data();
+            *LAST_PARAMS_UPDATE.lock().unwrap() = Some(Instant::now());
+        })
+        .await;
+    }
+}
+
+/// Update the cached readings from the FC now, regardless of the refresh interval.
+fn take_reading() -> Result<(), io::Error> {
+    *LAST_PARAMS_UPDATE.lock().unwrap() = Some(Instant::now());
+    get_data()
+}
+
+/// Update the cached readings from the FC, if we're past the refresh interval.
+fn refresh_data() {
+    let last_update = *LAST_PARAMS_UPDATE.lock().unwrap();
+
+    // Only update the readings from the FC if we're past the last updated thresh.
+    if last_update.is_none_or(|t| t.elapsed() > Duration::new(0, REFRESH_INTERVAL * 1_000_000)) {
+        if get_data().is_err() {
+            // todo: Is this normal? Seems harmless, but I'd like to
+            // todo get to the bottom of it.
+            // println!("Problem getting readings; sending old.")
+        }
+
+        *LAST_PARAMS_UPDATE.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// Assemble the latest cached readings.
+fn cached_data() -> ReadData {
+    let readings = READINGS.lock().unwrap().clone().unwrap_or_default();
+
+    ReadData {
+        battery: BATTERY.lock().unwrap().as_ref().map(|b| b.status()),
+        ..readings
+    }
+}
+
+/// How long ago the cached readings were taken, or `None` if we've never had any.
+fn readings_age() -> Option<Duration> {
+    LAST_CONTROLS_UPDATE.lock().unwrap().map(|t| t.elapsed())
+}
+
+/// Get readings over JSON upon request from the browser, which we've cached. `serial_worker`
+/// keeps them fresh, so this doesn't wait on the serial port.
+#[get("/data")]
+fn send_data() -> String {
+    let data = cached_data();
+
+    serde_json::to_string(&data).unwrap_or("Problem serializing data".into())
+}
+
+/// Get the battery pack profiles, and which is active.
+#[get("/battery/profiles")]
+async fn battery_profiles() -> String {
+    blocking(move || {
+        let profiles = BATTERY_PROFILES.lock().unwrap().clone().unwrap_or_default();
+
+        serde_json::to_string(&profiles).unwrap_or("Problem serializing data".into())
+    })
+    .await
+}
+
+/// Select the active battery pack profile by name. This resets capacity tracking, so do it when
+/// plugging in a fresh pack.
+#[post("/battery/profile", data = "<name>")]
+async fn set_battery_profile(_operator: Operator, name: String) -> Result<(), io::Error> {
+    blocking(move || {
+        let mut profiles = BATTERY_PROFILES.lock().unwrap();
+        let profiles = profiles.as_mut().unwrap();
+
+        let profile = match profiles.get(&name) {
+            Some(p) => p.clone(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No battery profile named {}", name),
+                ))
+            }
+        };
+
+        println!("Using battery profile {}", name);
+
+        profiles.active = name;
+        *BATTERY.lock().unwrap() = Some(Battery::new(profile));
+
+        Ok(())
+    })
+    .await
+}
+
+/// Run the preflight checklist against the latest readings, and return a go/no-go report.
+#[get("/preflight")]
+async fn preflight_report() -> String {
+    blocking(move || {
+        refresh_data();
+
+        let thresholds = THRESHOLDS.lock().unwrap().clone().unwrap_or_default();
+        let report = preflight::run_checks(&cached_data(), readings_age(), &thresholds);
+
+        serde_json::to_string(&report).unwrap_or("Problem serializing data".into())
+    })
+    .await
+}
+
+/// Get link quality and RSSI stats, dropouts and failsafes, antenna balance, whether the link
+/// data is stale, and the range test summary, if any.
+#[get("/link")]
+async fn link_report() -> String {
+    blocking(move || {
+        refresh_data();
+
+        let age = LAST_LINK_STATS_UPDATE.lock().unwrap().map(|t| t.elapsed());
+        let report = LINK_ANALYZER.lock().unwrap().as_ref().unwrap().report(age);
+
+        serde_json::to_string(&report).unwrap_or("Problem serializing data".into())
+    })
+    .await
+}
+
+/// Start a range test. Walk the aircraft away until the link degrades, then stop the test.
+#[post("/link/range_test/start")]
+async fn start_range_test(_viewer: Viewer) {
+    blocking(move || {
+        println!("Starting a range test");
+        LINK_ANALYZER
+            .lock()
+            .unwrap()
+            .as_mut()
+            .unwrap()
+            .start_range_test();
+    })
+    .await
+}
+
+/// Stop the range test in progress, and return its summary.
+#[post("/link/range_test/stop")]
+async fn stop_range_test(_viewer: Viewer) -> Result<String, io::Error> {
+    blocking(move || {
+        let summary = LINK_ANALYZER
+            .lock()
+            .unwrap()
+            .as_mut()
+            .unwrap()
+            .stop_range_test();
+
+        match summary {
+            Some(summary) => {
+                Ok(serde_json::to_string(&summary).unwrap_or("Problem serializing data".into()))
+            }
+            None => Err(io::Error::other("No range test is running.")),
+        }
+    })
+    .await
+}
+
+/// Arm all motors, for testing.
+#[post("/arm_motors")]
+async fn arm_motors(_operator: Operator) -> Result<(), io::Error> {
+    blocking(move || {
+        println!("Arming motors...");
+
+        with_fc(|fc| fc.send_arm_command())
+    })
+    .await
+}
+
+/// Start a motor.
+#[post("/start_motor", data = "<data>")]
+async fn start_motor(_operator: Operator, data: RotorPosition) -> Result<(), io::Error> {
+    blocking(move || {
+        require_aircraft_type(AircraftType::Quadcopter)?;
+
+        println!("Starting motor {:?}", data);
+
+        with_fc(|fc| fc.send_start_motor_command(data))
+    })
+    .await
+}
+
+/// Stop a motor. Any session may do this, so a read-only device can still stop a test.
+#[post("/stop_motor", data = "<data>")]
+async fn stop_motor(_viewer: Viewer, data: RotorPosition) -> Result<(), io::Error> {
+    blocking(move || {
+        require_aircraft_type(AircraftType::Quadcopter)?;
+
+        println!("Stopping motor {:?}", data);
+
+        with_fc(|fc| fc.send_stop_motor_command(data))
+    })
+    .await
+}
+
+/// Set a flying-wing servo to a specific position.
+#[post("/set_servo_position", data = "<data>")]
+async fn set_servo_position(
+    _operator: Operator,
+    data: SetServoPositionData,
+) -> Result<(), io::Error> {
+    blocking(move || {
+        require_aircraft_type(AircraftType::FlyingWing)?;
+
+        println!("Setting servo {:?}", data);
+
+        with_fc(|fc| fc.send_set_servo_posit_command(data.servo, data.value))
+    })
+    .await
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ServoCalibrations {
+    left: ServoCalibration,
+    right: ServoCalibration,
+}
+
+/// Read the endpoint calibration of both servos from the FC.
+#[get("/servo_calibration")]
+async fn get_servo_calibration() -> Result<String, io::Error> {
+    blocking(move || {
+        require_aircraft_type(AircraftType::FlyingWing)?;
+
+        let cals = with_fc(|fc| {
+            Ok(ServoCalibrations {
+                left: fc.read_servo_cal(ServoWingPosition::Left)?,
+                right: fc.read_servo_cal(ServoWingPosition::Right)?,
+            })
+        })?;
+
+        *SERVO_CALS.lock().unwrap() = Some([cals.left, cals.right]);
+
+        Ok(serde_json::to_string(&cals).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Write a servo's endpoint calibration to the FC.
+#[post("/set_servo_calibration", data = "<data>")]
+async fn set_servo_calibration(
+    _operator: Operator,
+    data: SetServoCalibrationData,
+) -> Result<(), io::Error> {
+    blocking(move || {
+        require_aircraft_type(AircraftType::FlyingWing)?;
+
+        println!("Setting servo calibration {:?}", data);
+
+        with_fc(|fc| fc.send_servo_cal(data.servo, &data.calibration))?;
+
+        if let Some(cals) = SERVO_CALS.lock().unwrap().as_mut() {
+            cals[data.servo as usize] = data.calibration;
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+/// Apply the elevon mix to the latest stick inputs, and compare the result against the servo
+/// positions the FC is commanding. Flags elevons that move opposite to the stick.
+#[get("/elevon_preview")]
+async fn elevon_preview() -> Result<String, io::Error> {
+    blocking(move || {
+        require_aircraft_type(AircraftType::FlyingWing)?;
+
+        // Read the calibration once; after that, each poll only reads the servo outputs.
+        let cached = *SERVO_CALS.lock().unwrap();
+        let cals = match cached {
+            Some(c) => c,
+            None => {
+                let c = with_fc(|fc| {
+                    Ok([
+                        fc.read_servo_cal(ServoWingPosition::Left)?,
+                        fc.read_servo_cal(ServoWingPosition::Right)?,
+                    ])
+                })?;
+                *SERVO_CALS.lock().unwrap() = Some(c);
+                c
+            }
+        };
+
+        // Without these, we only show the expected positions. A failed read still resets the
+        // connection, so the poller doesn't pick up the rest of the response.
+        let commanded = if require_feature(FEATURE_SERVO_OUTPUTS, "servo outputs").is_ok() {
+            with_fc(|fc| fc.read_servo_outputs()).ok()
+        } else {
+            None
+        };
+
+        let controls = cached_data().controls;
+        let preview = mixing::preview(&controls, &cals[0], &cals[1], commanded);
+
+        Ok(serde_json::to_string(&preview).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct ImuCalReport {
+    status: ImuCalStatus,
+    /// Tilt from level of the latest attitude, in degrees. After a level calibration on flat
+    /// ground, this should be near 0.
+    residual_tilt: f32,
+    residual_roll: f32,
+    residual_pitch: f32,
+}
+
+/// Start a gyro bias calibration (`kind` = `gyro`), with the aircraft still, or a level
+/// calibration (`kind` = `level`), with it on flat ground. Refused unless disarmed.
+#[post("/imu_cal/<kind>")]
+async fn start_imu_cal(_operator: Operator, kind: String) -> Result<(), io::Error> {
+    blocking(move || {
+        let kind = match kind.as_ref() {
+            "gyro" => ImuCalKind::Gyro,
+            "level" => ImuCalKind::Level,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown calibration: {}", kind),
+                ))
+            }
+        };
+
+        require_feature(FEATURE_IMU_CAL, "IMU calibration")?;
+        require_disarmed()?;
+
+        println!("Starting {:?} calibration", kind);
+
+        with_fc(|fc| fc.start_imu_cal(kind))?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Get the progress and results of the IMU calibration in progress, or the latest one, and the
+/// residual tilt of the current attitude.
+#[get("/imu_cal")]
+async fn imu_cal_status() -> Result<String, io::Error> {
+    blocking(move || {
+        require_feature(FEATURE_IMU_CAL, "IMU calibration")?;
+
+        let status = with_fc(|fc| fc.read_imu_cal_status())?;
+
+        refresh_data();
+        let attitude = cached_data().attitude_quat;
+        let (residual_roll, residual_pitch, _) = to_euler(&attitude);
+
+        let report = ImuCalReport {
+            status,
+            residual_tilt: preflight::tilt(&attitude),
+            residual_roll,
+            residual_pitch,
+        };
+
+        Ok(serde_json::to_string(&report).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Raw magnetometer readings to take per status request. The browser polls a few times a second;
+/// this gives a usable sample rate as the aircraft is rotated.
+const MAG_SAMPLES_PER_POLL: usize = 10;
+
+/// Run `f` on the compass calibration in progress.
+fn with_mag_cal_session<T>(f: impl FnOnce(&mut MagCalSession) -> T) -> Result<T, io::Error> {
+    MAG_CAL_SESSION
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(f)
+        .ok_or_else(|| io::Error::other("No compass calibration in progress."))
+}
+
+/// Start a compass calibration. Refused unless disarmed, since it involves handling the
+/// aircraft.
+#[post("/mag_cal/start")]
+async fn start_mag_cal(_operator: Operator) -> Result<(), io::Error> {
+    blocking(move || {
+        require_feature(FEATURE_COMPASS, "a compass")?;
+        require_feature(FEATURE_MAG_CAL, "compass calibration")?;
+        require_disarmed()?;
+
+        println!("Starting compass calibration");
+        *MAG_CAL_SESSION.lock().unwrap() = Some(MagCalSession::default());
+
+        Ok(())
+    })
+    .await
+}
+
+/// Take raw magnetometer readings, and report the number of samples, coverage of orientations,
+/// and the ellipsoid fit so far.
+#[get("/mag_cal")]
+async fn mag_cal_status() -> Result<String, io::Error> {
+    blocking(move || {
+        // Check first, so we don't take readings with no calibration to add them to.
+        with_mag_cal_session(|_| ())?;
+
+        let samples = with_fc(|fc| {
+            (0..MAG_SAMPLES_PER_POLL)
+                .map(|_| fc.read_mag_raw())
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        let status = with_mag_cal_session(|session| {
+            for sample in samples {
+                session.add(sample);
+            }
+            session.status()
+        })?;
+
+        Ok(serde_json::to_string(&status).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+#[post("/mag_cal/cancel")]
+async fn cancel_mag_cal(_operator: Operator) {
+    blocking(move || {
+        *MAG_CAL_SESSION.lock().unwrap() = None;
+    })
+    .await
+}
+
+/// Write the fitted compass calibration to the FC, if it's good enough.
+#[post("/mag_cal/save")]
+async fn save_mag_cal(_operator: Operator) -> Result<(), io::Error> {
+    blocking(move || {
+        let status = with_mag_cal_session(|session| session.status())?;
+
+        let fit = match status.fit {
+            Some(f) if status.acceptable => f,
+            _ => return Err(io::Error::other(status.message)),
+        };
+
+        println!("Setting compass calibration {:?}", fit);
+
+        with_fc(|fc| fc.send_mag_cal(&fit.calibration()))?;
+
+        *MAG_CAL_SESSION.lock().unwrap() = None;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Read the FC's PID gains.
+fn read_fc_pid() -> Result<PidConfig, io::Error> {
+    require_feature(FEATURE_PID, "PID tuning")?;
+
+    with_fc(|fc| fc.read_pid())
+}
+
+/// Write PID gains to the FC, and read them back to confirm.
+fn write_fc_pid(pid: &PidConfig) -> Result<(), io::Error> {
+    require_feature(FEATURE_PID, "PID tuning")?;
+
+    println!("Setting PID gains {:?}", pid);
+
+    let read_back = with_fc(|fc| {
+        fc.send_pid(pid)?;
+        fc.read_pid()
+    })?;
+
+    if read_back != *pid {
+        return Err(io::Error::other("The FC didn't apply the PID gains."));
+    }
+
+    Ok(())
+}
+
+/// Get the PID gains, and filter cutoffs the FC is using.
+#[get("/pid")]
+async fn get_pid() -> Result<String, io::Error> {
+    blocking(move || {
+        Ok(serde_json::to_string(&read_fc_pid()?).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Set the FC's PID gains, and filter cutoffs. Gains are range-checked first. Refused unless
+/// disarmed.
+#[put("/pid", data = "<data>")]
+async fn set_pid(_operator: Operator, data: PidConfig) -> Result<(), io::Error> {
+    blocking(move || {
+        require_disarmed()?;
+        write_fc_pid(&data)
+    })
+    .await
+}
+
+/// Get the saved tuning profiles.
+#[get("/pid/profiles")]
+async fn pid_profiles() -> Result<String, io::Error> {
+    blocking(move || {
+        Ok(serde_json::to_string(&PidProfiles::load()?)
+            .unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Save a tuning profile, replacing any with the same name.
+#[put("/pid/profiles/<name>", data = "<data>")]
+async fn save_pid_profile(
+    _operator: Operator,
+    name: String,
+    data: PidConfig,
+) -> Result<(), io::Error> {
+    blocking(move || {
+        let mut profiles = PidProfiles::load()?;
+        profiles.insert(&name, data);
+        profiles.save()?;
+
+        println!("Saved PID profile {}", name);
+
+        Ok(())
+    })
+    .await
+}
+
+#[delete("/pid/profiles/<name>")]
+async fn delete_pid_profile(_operator: Operator, name: String) -> Result<(), io::Error> {
+    blocking(move || {
+        let mut profiles = PidProfiles::load()?;
+
+        if !profiles.remove(&name) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No PID profile named {}", name),
+            ));
+        }
+
+        profiles.save()
+    })
+    .await
+}
+
+fn load_pid_profile(name: &str) -> Result<PidConfig, io::Error> {
+    PidProfiles::load()?.get(name).copied().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No PID profile named {}", name),
+        )
+    })
+}
+
+/// Write a saved tuning profile to the FC. Refused unless disarmed.
+#[post("/pid/profiles/<name>/apply")]
+async fn apply_pid_profile(_operator: Operator, name: String) -> Result<(), io::Error> {
+    blocking(move || {
+        let profile = load_pid_profile(&name)?;
+
+        // The profile file may have been hand-edited.
+        profile
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        require_disarmed()?;
+        write_fc_pid(&profile)
+    })
+    .await
+}
+
+/// List the values that differ between a saved tuning profile, and the FC's gains.
+#[get("/pid/profiles/<name>/diff")]
+async fn diff_pid_profile(name: String) -> Result<String, io::Error> {
+    blocking(move || {
+        let profile = load_pid_profile(&name)?;
+        let diff = pid::diff(&profile, &read_fc_pid()?);
+
+        Ok(serde_json::to_string(&diff).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Enumerate the FC's parameters.
+fn load_param_table() -> Result<ParamTable, io::Error> {
+    require_feature(FEATURE_PARAMS, "parameters")?;
+
+    let params = with_fc(|fc| {
+        let count = fc.read_param_count()?;
+        (0..count).map(|i| fc.read_param_info(i)).collect()
+    })?;
+
+    Ok(ParamTable { params })
+}
+
+/// Get the FC's parameter descriptions, enumerating them if we haven't yet.
+fn param_table() -> Result<ParamTable, io::Error> {
+    let cached = PARAM_TABLE.lock().unwrap().clone();
+    if let Some(table) = cached {
+        return Ok(table);
+    }
+
+    let table = load_param_table()?;
+    *PARAM_TABLE.lock().unwrap() = Some(table.clone());
+
+    Ok(table)
+}
+
+/// Enumerate the FC's parameters again, eg after a firmware update.
+fn reload_param_table() -> Result<ParamTable, io::Error> {
+    *PARAM_TABLE.lock().unwrap() = None;
+    param_table()
+}
+
+fn find_param(key: &str) -> Result<ParamInfo, io::Error> {
+    param_table()?.find(key).cloned().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No parameter named {}", key),
+        )
+    })
+}
+
+/// List the FC's parameters, with their current values. If `search` is set, only those whose
+/// names contain it.
+#[get("/params?<search>")]
+async fn get_params(search: Option<String>) -> Result<String, io::Error> {
+    blocking(move || {
+        let table = param_table()?;
+        let infos = table.search(search.as_deref().unwrap_or(""));
+
+        let result = with_fc(|fc| {
+            infos
+                .into_iter()
+                .map(|info| {
+                    Ok(Param {
+                        info: info.clone(),
+                        value: fc.read_param_value(info)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, io::Error>>()
+        })?;
+
+        Ok(serde_json::to_string(&result).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Re-enumerate the FC's parameters, eg after a firmware update.
+#[post("/params/refresh")]
+async fn refresh_params(_viewer: Viewer) -> Result<(), io::Error> {
+    blocking(move || {
+        reload_param_table()?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Get a parameter, by name or index.
+#[get("/params/<key>")]
+async fn get_param(key: String) -> Result<String, io::Error> {
+    blocking(move || {
+        let info = find_param(&key)?;
+        let value = with_fc(|fc| fc.read_param_value(&info))?;
+
+        Ok(serde_json::to_string(&Param { info, value })
+            .unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Why setting a parameter failed: a bad request, or a problem talking to the FC.
+#[derive(Responder)]
+enum SetParamError {
+    #[response(status = 400)]
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for SetParamError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Set a parameter, by name or index. The body is a JSON value, eg `1.5` or `true`; it's checked
+/// against the parameter's type and limits. The motors must be disarmed. Returns the value the FC applied.
+#[put("/params/<key>", data = "<value>")]
+async fn set_param(
+    _operator: Operator,
+    key: String,
+    value: String,
+) -> Result<String, SetParamError> {
+    blocking(move || {
+        require_disarmed()?;
+
+        let info = param_table()?
+            .find(&key)
+            .cloned()
+            .ok_or_else(|| SetParamError::Invalid(format!("No parameter named {}", key)))?;
+        let json: serde_json::Value = serde_json::from_str(&value)
+            .map_err(|e| SetParamError::Invalid(format!("Invalid value: {}", e)))?;
+        let value = info.parse(&json).map_err(SetParamError::Invalid)?;
+
+        println!("Setting parameter {} to {:?}", info.name, value);
+
+        let applied = with_fc(|fc| fc.send_param_value(&info, value))?;
+
+        if applied != value {
+            return Err(io::Error::other(format!(
+                "The FC applied {:?} instead of {:?}.",
+                applied, value
+            ))
+            .into());
+        }
+
+        Ok(serde_json::to_string(&applied).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Run `f` on the stick calibration in progress.
+fn with_rc_cal_session<T>(f: impl FnOnce(&mut rc_cal::Session) -> T) -> Result<T, io::Error> {
+    RC_CAL_SESSION
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(f)
+        .ok_or_else(|| io::Error::other("No stick calibration in progress."))
+}
+
+/// Get the stage of the stick calibration in progress, instructions for the pilot, the sticks'
+/// positions and recorded endpoints, and once done, the results.
+#[get("/rc_cal")]
+async fn rc_cal_status() -> Result<String, io::Error> {
+    blocking(move || {
+        refresh_data();
+
+        let status = with_rc_cal_session(|session| session.status())?;
+
+        Ok(serde_json::to_string(&status).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Start a guided stick calibration. We refuse while armed, since it calls for full throttle.
+#[post("/rc_cal/start")]
+async fn start_rc_cal(_operator: Operator) -> Result<(), io::Error> {
+    blocking(move || {
+        require_disarmed()?;
+
+        println!("Starting stick calibration");
+        *RC_CAL_SESSION.lock().unwrap() = Some(rc_cal::Session::default());
+
+        Ok(())
+    })
+    .await
+}
+
+/// Move the stick calibration to its next stage, once the pilot has followed the instructions.
+#[post("/rc_cal/next")]
+async fn advance_rc_cal(_operator: Operator) -> Result<(), BadRequest<String>> {
+    blocking(move || {
+        refresh_data();
+
+        with_rc_cal_session(|session| session.advance())
+            .map_err(|e| BadRequest(e.to_string()))?
+            .map_err(BadRequest)
+    })
+    .await
+}
+
+#[post("/rc_cal/cancel")]
+async fn cancel_rc_cal(_operator: Operator) {
+    blocking(move || {
+        *RC_CAL_SESSION.lock().unwrap() = None;
+    })
+    .await
+}
+
+/// Save the completed stick calibration to `rc_cal.toml`, and to the FC if it supports it.
+#[post("/rc_cal/save")]
+async fn save_rc_cal(_operator: Operator) -> Result<String, io::Error> {
+    blocking(move || {
+        let profile = with_rc_cal_session(|session| session.report().map(|r| r.profile()))?;
+        let profile = match profile {
+            Some(profile) => profile,
+            None => {
+                return Err(io::Error::other(
+                    "Finish the stick calibration before saving it.",
+                ))
+            }
+        };
+
+        for axis in rc_cal::AXES {
+            profile
+                .get(axis)
+                .validate(axis)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+
+        profile.save()?;
+        println!("Saved stick calibration to {}", rc_cal::PROFILE_FILE);
+
+        let result = if require_feature(FEATURE_RC_CAL, "stick calibration").is_ok() {
+            with_fc(|fc| {
+                for axis in rc_cal::AXES {
+                    fc.send_rc_cal(axis, profile.get(axis))?;
+                }
+                Ok(())
+            })?;
+
+            format!("Saved to the FC, and {}.", rc_cal::PROFILE_FILE)
+        } else {
+            format!(
+                "Saved to {}. The FC firmware doesn't store stick calibration.",
+                rc_cal::PROFILE_FILE
+            )
+        };
+
+        *RC_CAL_SESSION.lock().unwrap() = None;
+
+        Ok(result)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct RcCalProfiles {
+    /// From `rc_cal.toml`. `None` if not saved.
+    local: Option<RcCalProfile>,
+    /// `None` if the FC doesn't store stick calibration.
+    fc: Option<RcCalProfile>,
+}
+
+/// Get the saved stick calibration, locally and from the FC.
+#[get("/rc_cal/profile")]
+async fn rc_cal_profile() -> Result<String, io::Error> {
+    blocking(move || {
+        let local = RcCalProfile::load()?;
+
+        let fc = if require_feature(FEATURE_RC_CAL, "stick calibration").is_ok() {
+            Some(with_fc(|fc| {
+                Ok(RcCalProfile {
+                    roll: fc.read_rc_cal(RcAxis::Roll)?,
+                    pitch: fc.read_rc_cal(RcAxis::Pitch)?,
+                    yaw: fc.read_rc_cal(RcAxis::Yaw)?,
+                    throttle: fc.read_rc_cal(RcAxis::Throttle)?,
+                })
+            })?)
+        } else {
+            None
+        };
+
+        Ok(serde_json::to_string(&RcCalProfiles { local, fc })
+            .unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// Check that the board supports a backup section, so it can be both read, and restored.
+fn require_config_section(section: &str, aircraft_type: AircraftType) -> Result<(), io::Error> {
+    let required_type = match section {
+        "motor_directions" => Some(AircraftType::Quadcopter),
+        "servo_calibration" => Some(AircraftType::FlyingWing),
+        _ => None,
+    };
+
+    if let Some(required) = required_type {
+        if aircraft_type != required {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Only applies to a {:?}.", required),
+            ));
+        }
+    }
+
+    let features: &[(u32, &str)] = match section {
+        "motor_directions" | "waypoints" => &[(FEATURE_CONFIG_BACKUP, "configuration backup")],
+        "rc_calibration" => &[(FEATURE_RC_CAL, "stick calibration")],
+        "pid" => &[(FEATURE_PID, "PID tuning")],
+        "imu_bias" => &[
+            (FEATURE_IMU_CAL, "IMU calibration"),
+            (FEATURE_CONFIG_BACKUP, "configuration backup"),
+        ],
+        "mag_calibration" => &[
+            (FEATURE_MAG_CAL, "compass calibration"),
+            (FEATURE_CONFIG_BACKUP, "configuration backup"),
+        ],
+        "params" => &[(FEATURE_PARAMS, "parameters")],
+        _ => &[],
+    };
+
+    for (feature, name) in features {
+        require_feature(*feature, name)?;
+    }
+
+    Ok(())
+}
+
+/// Read everything configurable from the FC. Sections it doesn't support are left out.
+fn read_config() -> Result<ConfigBackup, io::Error> {
+    let aircraft = aircraft_info()?;
+    let handshake = HANDSHAKE
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| io::Error::other("No handshake with the FC yet."))?;
+
+    let supported = |section| require_config_section(section, aircraft.aircraft_type).is_ok();
+
+    // Re-enumerate, in case the firmware changed since we last did.
+    let param_table = if supported("params") {
+        Some(reload_param_table()?)
+    } else {
+        None
+    };
+
+    with_fc(|fc| {
+        let motor_directions = if supported("motor_directions") {
+            Some(fc.read_motor_dirs()?)
+        } else {
+            None
+        };
+
+        let servo_calibration = if supported("servo_calibration") {
+            Some(ServoCalibrations {
+                left: fc.read_servo_cal(ServoWingPosition::Left)?,
+                right: fc.read_servo_cal(ServoWingPosition::Right)?,
+            })
+        } else {
+            None
+        };
+
+        let rc_calibration = if supported("rc_calibration") {
+            Some(RcCalProfile {
+                roll: fc.read_rc_cal(RcAxis::Roll)?,
+                pitch: fc.read_rc_cal(RcAxis::Pitch)?,
+                yaw: fc.read_rc_cal(RcAxis::Yaw)?,
+                throttle: fc.read_rc_cal(RcAxis::Throttle)?,
+            })
+        } else {
+            None
+        };
+
+        let pid = if supported("pid") {
+            Some(fc.read_pid()?)
+        } else {
+            None
+        };
+
+        let imu_bias = if supported("imu_bias") {
+            let status = fc.read_imu_cal_status()?;
+            Some(ImuBias {
+                gyro: status.gyro_bias,
+                accel: status.accel_bias,
+            })
+        } else {
+            None
+        };
+
+        let mag_calibration = if supported("mag_calibration") {
+            Some(fc.read_mag_cal()?)
+        } else {
+            None
+        };
+
+        let waypoints = if supported("waypoints") {
+            Some(backup::waypoint_slots(&fc.read_waypoints()?))
+        } else {
+            None
+        };
+
+        let params = match param_table {
+            Some(table) => {
+                let mut values = BTreeMap::new();
+                for info in &table.params {
+                    values.insert(info.name.clone(), fc.read_param_value(info)?);
+                }
+                Some(values)
+            }
+            None => None,
+        };
+
+        Ok(ConfigBackup {
+            format_version: backup::FORMAT_VERSION,
+            created: chrono::Utc::now().to_rfc3339(),
+            board: BoardInfo {
+                aircraft_type: aircraft.aircraft_type,
+                board_id: handshake.firmware.board_id,
+                firmware_version: handshake.firmware.firmware_version,
+                firmware_build: aircraft.firmware_build,
+                protocol_version: handshake.negotiated_protocol,
+            },
+            motor_directions,
+            servo_calibration,
+            rc_calibration,
+            pid,
+            imu_bias,
+            mag_calibration,
+            waypoints,
+            params,
+        })
+    })
+}
+
+/// Write a backup to the FC, then read it back to confirm. Refused unless disarmed. Everything
+/// is checked before we write anything, so a bad backup doesn't leave the board half-restored.
+/// Sections, and parameters this board doesn't have are skipped, and reported.
+fn write_config(config: &ConfigBackup) -> Result<RestoreReport, io::Error> {
+    require_disarmed()?;
+
+    config
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let aircraft = aircraft_info()?;
+    if config.board.aircraft_type != aircraft.aircraft_type {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "This backup is from a {:?}, but the FC reports a {:?}.",
+                config.board.aircraft_type, aircraft.aircraft_type
+            ),
+        ));
+    }
+
+    let mut report = RestoreReport::default();
+
+    for (section, present) in [
+        ("motor_directions", config.motor_directions.is_some()),
+        ("servo_calibration", config.servo_calibration.is_some()),
+        ("rc_calibration", config.rc_calibration.is_some()),
+        ("pid", config.pid.is_some()),
+        ("imu_bias", config.imu_bias.is_some()),
+        ("mag_calibration", config.mag_calibration.is_some()),
+        ("waypoints", config.waypoints.is_some()),
+        ("params", config.params.is_some()),
+    ] {
+        if !present {
+            continue;
+        }
+
+        match require_config_section(section, aircraft.aircraft_type) {
+            Ok(()) => report.restored.push(section.to_owned()),
+            Err(e) => report.skipped.push(format!("{}: {}", section, e)),
+        }
+    }
+
+    let restore = |section: &str| report.restored.iter().any(|s| s == section);
+
+    // Match parameters by name, since indices may change between firmware versions.
+    let mut params = Vec::new();
+    let mut skipped_params = Vec::new();
+    if let (true, Some(values)) = (restore("params"), &config.params) {
+        let table = reload_param_table()?;
+
+        for (name, value) in values {
+            match table.find(name).cloned() {
+                Some(info) => {
+                    let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+                    let value = info
+                        .parse(&value)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                    params.push((info, value));
+                }
+                None => skipped_params.push(name.clone()),
+            }
+        }
+    }
+
+    with_fc(|fc| {
+        if let (true, Some(dirs)) = (restore("motor_directions"), &config.motor_directions) {
+            fc.send_motor_dirs(dirs)?;
+        }
+
+        if let (true, Some(cals)) = (restore("servo_calibration"), &config.servo_calibration) {
+            fc.send_servo_cal(ServoWingPosition::Left, &cals.left)?;
+            fc.send_servo_cal(ServoWingPosition::Right, &cals.right)?;
+            *SERVO_CALS.lock().unwrap() = Some([cals.left, cals.right]);
+        }
+
+        if let (true, Some(profile)) = (restore("rc_calibration"), &config.rc_calibration) {
+            for axis in rc_cal::AXES {
+                fc.send_rc_cal(axis, profile.get(axis))?;
+            }
+        }
+
+        if let (true, Some(pid)) = (restore("pid"), &config.pid) {
+            fc.send_pid(pid)?;
+        }
+
+        if let (true, Some(bias)) = (restore("imu_bias"), &config.imu_bias) {
+            fc.send_imu_bias(bias)?;
+        }
+
+        if let (true, Some(cal)) = (restore("mag_calibration"), &config.mag_calibration) {
+            fc.send_mag_cal(cal)?;
+        }
+
+        if let (true, Some(slots)) = (restore("waypoints"), &config.waypoints) {
+            let waypoints = backup::waypoint_table(slots);
+
+            fc.send_waypoints(&waypoints)?;
+            set_cached_waypoints(waypoints);
+        }
+
+        for (info, value) in params {
+            fc.send_param_value(&info, value)?;
+        }
+
+        Ok(())
+    })?;
+
+    // Only sections we restored count. For parameters, ignore those on only one side; they're
+    // new, or removed in this firmware.
+    let live = read_config()?;
+    let mismatches: Vec<ConfigDiff> = backup::diff(config, &live)
+        .into_iter()
+        .filter(|d| {
+            let section = d.field.split('.').next().unwrap_or_default();
+            restore(section) && (section != "params" || (d.a.is_some() && d.b.is_some()))
+        })
+        .collect();
+
+    if !mismatches.is_empty() {
+        let fields: Vec<&str> = mismatches.iter().map(|d| d.field.as_str()).collect();
+        return Err(io::Error::other(format!(
+            "The FC didn't apply these settings: {}",
+            fields.join(", ")
+        )));
+    }
+
+    for name in skipped_params {
+        report
+            .skipped
+            .push(format!("params.{}: Not on this board.", name));
+    }
+
+    Ok(report)
+}
+
+/// Download everything configurable on the FC, as a versioned backup file. `format` is `json`
+/// (the default), or `toml`.
+#[get("/config/backup?<format>")]
+async fn backup_config(format: Option<String>) -> Result<Download, BadRequest<String>> {
+    blocking(move || {
+        let format_name = format.unwrap_or_else(|| "json".into());
+        let format = backup::Format::from_name(&format_name)
+            .ok_or_else(|| BadRequest(format!("Unknown backup format: {}", format_name)))?;
+
+        let config = read_config().map_err(|e| BadRequest(e.to_string()))?;
+        let body = config
+            .to_string(format)
+            .map_err(|e| BadRequest(e.to_string()))?;
+
+        Ok(Download {
+            filename: format!(
+                "config-board{}-{}.{}",
+                config.board.board_id,
+                chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+                format.extension()
+            ),
+            content_type: ContentType::parse_flexible(format.content_type())
+                .unwrap_or(ContentType::Plain),
+            body: body.into_bytes(),
+        })
+    })
+    .await
+}
+
+/// Restore a backup, in either format, onto the FC.
+#[post("/config/restore", data = "<contents>")]
+async fn restore_config(
+    _operator: Operator,
+    contents: String,
+) -> Result<String, BadRequest<String>> {
+    blocking(move || {
+        let bad_request = |e: io::Error| BadRequest(e.to_string());
+
+        let config = ConfigBackup::parse(&contents).map_err(bad_request)?;
+        let report = write_config(&config).map_err(bad_request)?;
+
+        println!("Restored configuration: {}", report.restored.join(", "));
+
+        Ok(serde_json::to_string(&report).unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct ConfigDiffRequest {
+    /// Contents of a backup file.
+    a: String,
+    /// Contents of another backup file. If `None`, compare `a` with the FC.
+    b: Option<String>,
+}
+
+/// Compare two backups, or a backup and the FC, field by field.
+#[post("/config/diff", data = "<data>")]
+async fn diff_config(_viewer: Viewer, data: String) -> Result<String, BadRequest<String>> {
+    blocking(move || {
+        let bad_request = |e: io::Error| BadRequest(e.to_string());
+
+        let req: ConfigDiffRequest = serde_json::from_str(&data)
+            .map_err(|e| BadRequest(format!("Invalid diff request: {}", e)))?;
+
+        let a = ConfigBackup::parse(&req.a).map_err(bad_request)?;
+        let b = match req.b {
+            Some(b) => ConfigBackup::parse(&b),
+            None => read_config(),
+        }
+        .map_err(bad_request)?;
+
+        Ok(serde_json::to_string(&backup::diff(&a, &b))
+            .unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+fn set_flash_status(status: FlashStatus) {
+    *FLASH_STATUS.lock().unwrap() = Some(status);
+}
+
+fn flashing(status: Option<&FlashStatus>) -> bool {
+    status.is_some_and(|s| !matches!(s.stage, FlashStage::Done | FlashStage::Failed))
+}
+
+fn flash_in_progress() -> bool {
+    flashing(FLASH_STATUS.lock().unwrap().as_ref())
+}
+
+/// Mark a firmware update as started, unless one already is. Checked and set under one lock, so
+/// two concurrent requests can't both start one.
+fn claim_flash() -> bool {
+    let mut status = FLASH_STATUS.lock().unwrap();
+    if flashing(status.as_ref()) {
+        return false;
+    }
+
+    *status = Some(FlashStatus::new(FlashStage::WaitingForBootloader));
+    true
+}
+
+/// If the FC is running firmware, have it reboot into the bootloader. Refused unless disarmed.
+/// If we can't find it, it may already be there, eg with its boot button held.
+fn reboot_to_bootloader() -> Result<(), io::Error> {
+    if with_fc(|_| Ok(())).is_err() {
+        return Ok(());
+    }
+
+    require_disarmed()?;
+    require_feature(
+        FEATURE_BOOTLOADER_REBOOT,
+        "rebooting into the bootloader. Hold its boot button while plugging it in instead",
+    )?;
+
+    println!("Rebooting the FC into its bootloader");
+
+    with_fc(|fc| fc.send_reboot_to_bootloader())?;
+
+    // The serial port goes away with the firmware.
+    *FC.lock().unwrap() = None;
+
+    Ok(())
+}
+
+/// Flash an image over DFU once the FC is in its bootloader, then wait for the new firmware to
+/// start, and check its version. `image` must have passed `dfu::check_image`. If `expected` is
+/// set, the FC must report that version.
+fn update_firmware(
+    image: &[u8],
+    expected: Option<FirmwareVersion>,
+    mut update: impl FnMut(FlashStatus),
+) -> Result<FirmwareVersion, io::Error> {
+    let image = dfu::pad_image(image);
+
+    let mut progress = |stage, done, total| {
+        update(FlashStatus {
+            done,
+            total,
+            ..FlashStatus::new(stage)
+        })
+    };
+
+    progress(FlashStage::WaitingForBootloader, 0, 0);
+    let mut dfu = Dfu::open(REBOOT_TIMEOUT)?;
+
+    dfu.erase(image.len(), |d, t| progress(FlashStage::Erasing, d, t))?;
+    dfu.write(&image, |d, t| progress(FlashStage::Writing, d, t))?;
+    dfu.verify(&image, |d, t| progress(FlashStage::Verifying, d, t))?;
+
+    progress(FlashStage::Restarting, 0, 0);
+    dfu.start_firmware()?;
+
+    // The firmware, and what it supports, may have changed.
+    progress(FlashStage::CheckingVersion, 0, 0);
+    *FC.lock().unwrap() = None;
+    *HANDSHAKE.lock().unwrap() = None;
+    *AIRCRAFT_INFO.lock().unwrap() = None;
+    *PARAM_TABLE.lock().unwrap() = None;
+
+    let start = Instant::now();
+    let firmware = loop {
+        // This sets the handshake even if it refuses to connect.
+        let _ = with_fc(|_| Ok(()));
+
+        let handshake = HANDSHAKE.lock().unwrap().clone();
+        if let Some(h) = handshake {
+            if let Compatibility::Incompatible(msg) = h.compatibility {
+                return Err(io::Error::other(format!(
+                    "Flashed, but the new firmware is incompatible: {}",
+                    msg
+                )));
+            }
+            break h.firmware.firmware_version;
+        }
+
+        if start.elapsed() > REBOOT_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Flashed, but the FC didn't come back. Unplug it, and plug it back in.",
+            ));
+        }
+        thread::sleep(Duration::from_millis(200));
+    };
+
+    if let Some(expected) = expected {
+        if firmware != expected {
+            return Err(io::Error::other(format!(
+                "Flashed, but the FC reports firmware v{}.{}.{}, not v{}.{}.{}.",
+                firmware.major,
+                firmware.minor,
+                firmware.patch,
+                expected.major,
+                expected.minor,
+                expected.patch
+            )));
+        }
+    }
+
+    Ok(firmware)
+}
+
+/// Start a firmware update, from a raw binary image in the body. If `version` is set, eg `1.2.3`,
+/// the FC must report it after flashing. Poll `/firmware/status` for progress. The `bytes` limit
+/// caps the body one past `dfu::MAX_IMAGE_SIZE`, so `check_image` can reject oversized images.
+#[post("/firmware/flash?<version>", data = "<image>")]
+async fn start_firmware_update(
+    _operator: Operator,
+    version: Option<String>,
+    image: Capped<Vec<u8>>,
+) -> Result<(), BadRequest<String>> {
+    let image = image.into_inner();
+
+    blocking(move || {
+        let bad_request = |e: String| BadRequest(e);
+
+        let expected = match version.filter(|v| !v.is_empty()) {
+            Some(v) => Some(
+                dfu::parse_version(&v)
+                    .ok_or_else(|| bad_request(format!("Invalid version: {}", v)))?,
+            ),
+            None => None,
+        };
+
+        dfu::check_image(&image).map_err(bad_request)?;
+
+        if !claim_flash() {
+            return Err(bad_request(
+                "A firmware update is already in progress.".into(),
+            ));
+        }
+
+        if let Err(e) = reboot_to_bootloader() {
+            *FLASH_STATUS.lock().unwrap() = None;
+            return Err(bad_request(e.to_string()));
+        }
+
+        println!(
+            "Starting a firmware update, with a {} byte image",
+            image.len()
+        );
+
+        thread::spawn(move || {
+            let result = update_firmware(&image, expected, set_flash_status);
+
+            let status = match result {
+                Ok(version) => FlashStatus {
+                    message: Some(format!(
+                        "Updated to firmware v{}.{}.{}.",
+                        version.major, version.minor, version.patch
+                    )),
+                    firmware_version: Some(version),
+                    ..FlashStatus::new(FlashStage::Done)
+                },
+                Err(e) => FlashStatus {
+                    message: Some(e.to_string()),
+                    ..FlashStatus::new(FlashStage::Failed)
+                },
+            };
+
+            println!("Firmware update: {:?}", status.message);
+            set_flash_status(status);
+        });
+
+        Ok(())
+    })
+    .await
+}
+
+/// Progress of the firmware update in progress, or the result of the latest one.
+#[get("/firmware/status")]
+async fn firmware_update_status() -> String {
+    blocking(move || {
+        serde_json::to_string(&*FLASH_STATUS.lock().unwrap())
+            .unwrap_or("Problem serializing data".into())
+    })
+    .await
+}
+
+/// Add the latest cached readings to the history buffer. We leave out waypoints; they don't
+/// change during a session, and would dominate the buffer's memory use.
+fn record_history() {
+    let mut sample = match serde_json::to_value(cached_data()) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+
+    if let Some(obj) = sample.as_object_mut() {
+        obj.remove("waypoints");
+    }
+
+    let sample = Sample {
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        data: sample,
+    };
+
+    let mut recorder = RECORDER.lock().unwrap();
+    if let Some(r) = recorder.as_mut() {
+        if let Err(e) = r.write(&sample) {
+            println!("Problem writing to the recording; stopping it: {}", e);
+            *recorder = None;
+        }
+    }
+    drop(recorder);
+
+    if let Some(history) = HISTORY.lock().unwrap().as_mut() {
+        history.push(&sample);
+    }
+}
+
+/// Start recording readings to a file in `recordings`, for later export.
+#[post("/record/start")]
+async fn start_recording(_viewer: Viewer) -> Result<String, io::Error> {
+    blocking(move || {
+        let recorder = Recorder::start()?;
+        let name = recorder.path.display().to_string();
+
+        println!("Recording to {}", name);
+        *RECORDER.lock().unwrap() = Some(recorder);
+
+        Ok(name)
+    })
+    .await
+}
+
+/// Stop the current recording, if any.
+#[post("/record/stop")]
+async fn stop_recording(_viewer: Viewer) -> Result<String, io::Error> {
+    blocking(move || {
+        let recorder = RECORDER.lock().unwrap().take();
+
+        match recorder {
+            Some(r) => {
+                let num_samples = r.num_samples;
+                let path = r.finish()?;
+
+                println!("Saved {} readings to {}", num_samples, path.display());
+                Ok(path.display().to_string())
+            }
+            None => Err(io::Error::other("Not recording.")),
+        }
+    })
+    .await
+}
+
+/// List saved recordings.
+#[get("/recordings")]
+async fn recordings() -> Result<String, io::Error> {
+    blocking(move || {
+        Ok(serde_json::to_string(&history::list_recordings()?)
+            .unwrap_or("Problem serializing data".into()))
+    })
+    .await
+}
+
+/// A file download, eg exported telemetry.
+struct Download {
+    filename: String,
+    content_type: ContentType,
+    body: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(self.content_type)
+            .raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", self.filename),
+            )
+            .sized_body(self.body.len(), io::Cursor::new(self.body))
+            .ok()
+    }
+}
+
+/// Export telemetry as CSV or Parquet. `source` is `history` for the live history buffer, or the
+/// name of a recording. `format` is `csv` or `parquet`.
+#[get("/export?<source>&<format>")]
+async fn export_telemetry(source: String, format: String) -> Result<Download, BadRequest<String>> {
+    blocking(move || {
+        let format = export::Format::from_name(&format)
+            .ok_or_else(|| BadRequest(format!("Unknown export format: {}", format)))?;
+
+        let body = if source == "history" {
+            let history = HISTORY.lock().unwrap();
+            export::export_rows(history.as_ref().unwrap().rows(), format)
+        } else {
+            history::load_named_recording(&source)
+                .and_then(|samples| export::export(&samples, format))
+        }
+        .map_err(|e| BadRequest(e.to_string()))?;
+
+        let stem = source.trim_end_matches(".jsonl");
+
+        Ok(Download {
+            filename: format!("{}.{}", stem, format.extension()),
+            content_type: ContentType::parse_flexible(format.content_type())
+                .unwrap_or(ContentType::Binary),
+            body,
+        })
+    })
+    .await
+}
+
+/// Get a time series of one or more fields from the history buffer. `fields` is a
+/// comma-separated list of dotted paths, eg `batt_v,link_stats.uplink_link_quality`. `since` is
+/// in ms since the UNIX epoch. If `bucket` (ms) is set, we downsample to min, max, and mean
+/// per bucket.
+#[get("/history?<fields>&<since>&<bucket>")]
+async fn get_history(
+    fields: String,
+    since: Option<i64>,
+    bucket: Option<i64>,
+) -> Result<String, BadRequest<String>> {
+    blocking(move || {
+        let fields: Vec<&str> = fields
+            .split(',')
+            .map(|f| f.trim())
+            .filter(|f| !f.is_empty())
+            .collect();
+
+        let history = HISTORY.lock().unwrap();
+
+        match history.as_ref().unwrap().query(&fields, since, bucket) {
+            Ok(series) => {
+                Ok(serde_json::to_string(&series).unwrap_or("Problem serializing data".into()))
+            }
+            Err(e) => Err(BadRequest(e)),
+        }
+    })
+    .await
+}
+
+/// Request readings from the FC over USB/serial. Cache them as a
+/// global variable. Requesting the readings directly from the frontend could result in
+/// conflicts, where multiple frontends are requesting readings from the WM directly
+/// in too short an interval.
+fn get_data() -> Result<(), io::Error> {
+    // The FC is in its bootloader, or restarting; leave the USB link to the update.
+    if flash_in_progress() {
+        return Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "A firmware update is in progress",
+        ));
+    }
+
+    // Keep the previous readings if this one fails, so a dropped packet doesn't reset the
+    // battery's capacity tracking.
+    let data = with_fc(|fc| fc.read_all())?;
+
+    if let Some(analyzer) = LINK_ANALYZER.lock().unwrap().as_mut() {
+        analyzer.update(&data.link_stats);
+    }
+    if let Some(session) = RC_CAL_SESSION.lock().unwrap().as_mut() {
+        session.update(&data.controls);
+    }
+    if let Some(battery) = BATTERY.lock().unwrap().as_mut() {
+        battery.update(data.batt_v, data.current);
+    }
+
+    {
+        // Waypoints are only changed by uploading, or restoring them.
+        let mut readings = READINGS.lock().unwrap();
+        let readings = readings.get_or_insert_with(Default::default);
+
+        readings.attitude_quat = data.attitude_quat;
+        readings.altimeter = data.altimeter;
+        readings.altimeter_agl = data.altimeter_agl;
+        readings.batt_v = data.batt_v;
+        readings.current = data.current;
+        readings.controls = data.controls;
+        readings.link_stats = data.link_stats;
+    }
+    *LAST_CONTROLS_UPDATE.lock().unwrap() = Some(Instant::now());
+    *LAST_LINK_STATS_UPDATE.lock().unwrap() = Some(Instant::now());
+
+    record_history();
+
+    Ok(())
+}
+
+/// Show waypoints we've written to the FC in the readings.
+fn set_cached_waypoints(waypoints: [Option<Location>; MAX_WAYPOINTS]) {
+    READINGS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(Default::default)
+        .waypoints = waypoints;
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    *HISTORY.lock().unwrap() = Some(History::default());
+    *LINK_ANALYZER.lock().unwrap() = Some(LinkAnalyzer::default());
+
+    // Warnings go to stderr, so they don't mix with JSON from the command line.
+    match Thresholds::load() {
+        Ok(t) => *THRESHOLDS.lock().unwrap() = Some(t),
+        Err(e) => eprintln!("{}. Using default preflight thresholds.", e),
+    }
+
+    let profiles = PackProfiles::load().unwrap_or_else(|e| {
+        eprintln!("{}. Using the default battery profile.", e);
+        PackProfiles::default()
+    });
+    *BATTERY.lock().unwrap() = Some(Battery::new(profiles.active()));
+    *BATTERY_PROFILES.lock().unwrap() = Some(profiles);
+
+    let result = match cli.command {
+        None => serve(cli.serve),
+        Some(Command::Serve(args)) => serve(args),
+        Some(command) => cli::run(command),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Launch the web interface, and poll the FC in the background.
+fn serve(args: ServeArgs) -> Result<(), io::Error> {
+    match aircraft_info() {
+        Ok(info) => {
+            let h = HANDSHAKE.lock().unwrap().clone().unwrap();
+            let fw = h.firmware.firmware_version;
+
+            println!(
+                "Connected to a {:?} (board {}), running firmware v{}.{}.{} build {}. \
+                Using protocol v{}.{}.",
+                info.aircraft_type,
+                h.firmware.board_id,
+                fw.major,
+                fw.minor,
+                fw.patch,
+                info.firmware_build,
+                h.negotiated_protocol.major,
+                h.negotiated_protocol.minor,
+            );
+
+            if let Compatibility::Degraded(msg) = &h.compatibility {
+                println!("Warning: {}", msg);
+            }
+        }
+        Err(e) => match HANDSHAKE.lock().unwrap().as_ref() {
+            Some(_) => println!("Problem connecting to the flight controller: {}", e),
+            None => println!(
+                "No flight controller found yet ({}). Plug it in over USB; we'll identify it on \
+                first use.",
+                e
+            ),
+        },
+    }
+
+    let addr = args.socket_addr()?;
+    net::check_bind(addr)?;
+
+    let urls = net::urls(addr);
+    println!(
+        "AnyLeaf Preflight has launched. You can connect by opening {} in a web browser on this \
+        computer.",
+        urls[0]
+    );
+    if urls.len() > 1 {
+        println!("From another device on this network, like your phone, open one of:");
+        for url in &urls[1..] {
+            println!("    {}", url);
+        }
+    } else if addr.ip().is_loopback() {
+        println!(
+            "Only this computer can connect. To connect from your phone or another device, \
+            launch with `--lan`."
+        );
+    }
+    println!(
+        "To arm, test motors, or change settings from a browser, pair it with PIN {}. Without \
+        pairing, it's read-only.\n",
+        auth::new_pin()
+    );
+
+    let config = Config {
+        address: addr.ip(),
+        port: addr.port(),
+        log_level: LogLevel::Critical, // Don't show the user the connections.
+        // Backups, and firmware images are larger than the defaults allow.
+        limits: Limits::default()
+            .limit("string", 1.mebibytes())
+            .limit("bytes", (dfu::MAX_IMAGE_SIZE + 1).bytes()),
+        ..Config::release_default()
+    };
+
+    let server = rocket::custom(config)
+        .mount("/", FileServer::from("static"))
+        .mount(
+            "/api",
+            routes![
+                session,
+                pair,
+                unpair,
+                info,
+                send_data,
+                preflight_report,
+                link_report,
+                start_range_test,
+                stop_range_test,
+                battery_profiles,
+                set_battery_profile,
+                get_history,
+                start_recording,
+                stop_recording,
+                recordings,
+                export_telemetry,
+                arm_motors,
+                start_motor,
+                stop_motor,
+                set_servo_position,
+                get_servo_calibration,
+                set_servo_calibration,
+                elevon_preview,
+                start_imu_cal,
+                imu_cal_status,
+                start_mag_cal,
+                mag_cal_status,
+                cancel_mag_cal,
+                save_mag_cal,
+                get_pid,
+                set_pid,
+                pid_profiles,
+                save_pid_profile,
+                delete_pid_profile,
+                apply_pid_profile,
+                diff_pid_profile,
+                get_params,
+                refresh_params,
+                get_param,
+                set_param,
+                backup_config,
+                restore_config,
+                diff_config,
+                start_firmware_update,
+                firmware_update_status,
+                rc_cal_status,
+                start_rc_cal,
+                advance_rc_cal,
+                cancel_rc_cal,
+                save_rc_cal,
+                rc_cal_profile,
+            ],
+        )
+        .register("/api", catchers![unauthorized, forbidden])
+        .launch();
+
+    rocket::execute(async {
+        tokio::spawn(serial_worker());
+        server.await
+    })
+    .map_err(|e| io::Error::other(format!("Problem running the server: {}", e)))?;
+
+    Ok(())
 }
@@ -0,0 +1,173 @@
+//! Elevon mixing for flying wings. This mirrors the mixer in the firmware, so we can show
+//! expected control-surface positions on the bench, and catch reversed elevons before flight.
+
+use serde::Serialize;
+
+use crate::types::{ChannelData, ServoCalibration};
+
+/// Stick or mixed-command magnitude below which we don't judge servo direction; near center,
+/// noise and trim make the sign meaningless.
+const DIRECTION_CHECK_THRESH: f32 = 0.2;
+
+/// Output deflection from center, in ms, below which we consider a servo not to have moved.
+const SERVO_MOVED_THRESH: f32 = 0.02;
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+pub enum DirectionCheck {
+    /// Sticks are near center, so we can't tell.
+    Indeterminate,
+    /// The servo moved the way the mix says it should.
+    Correct,
+    /// The servo moved opposite to the mix. Check the reverse setting, or servo mapping.
+    Reversed,
+    /// The mix commands a deflection, but the servo didn't move.
+    NoResponse,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ElevonState {
+    /// Mixed command, -1. to 1. Positive is trailing edge up.
+    pub command: f32,
+    /// Pulse width we expect the FC to output, in ms, after applying calibration.
+    pub expected_pulse: f32,
+    /// Pulse width the FC reports it's commanding, in ms.
+    pub commanded_pulse: Option<f32>,
+    pub direction: DirectionCheck,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ElevonPreview {
+    pub left: ElevonState,
+    pub right: ElevonState,
+}
+
+/// Mix roll and pitch into elevon commands, -1. to 1. Positive is trailing edge up. Pitch up
+/// raises both surfaces; roll right raises the right surface, and lowers the left one.
+pub fn elevon_mix(controls: &ChannelData) -> (f32, f32) {
+    let left = (controls.pitch - controls.roll).clamp(-1., 1.);
+    let right = (controls.pitch + controls.roll).clamp(-1., 1.);
+
+    (left, right)
+}
+
+/// Convert a mixed command to a PWM high time, in ms, using a servo's endpoint calibration.
+pub fn command_to_pulse(command: f32, cal: &ServoCalibration) -> f32 {
+    let command = if cal.reversed { -command } else { command };
+
+    if command >= 0. {
+        cal.center + command * (cal.max - cal.center)
+    } else {
+        cal.center + command * (cal.center - cal.min)
+    }
+}
+
+/// Compare the direction the FC moved a servo with the direction the mix calls for.
+fn check_direction(command: f32, cal: &ServoCalibration, commanded_pulse: f32) -> DirectionCheck {
+    if command.abs() < DIRECTION_CHECK_THRESH {
+        return DirectionCheck::Indeterminate;
+    }
+
+    let expected = command_to_pulse(command, cal) - cal.center;
+    let actual = commanded_pulse - cal.center;
+
+    if actual.abs() < SERVO_MOVED_THRESH {
+        DirectionCheck::NoResponse
+    } else if expected.signum() == actual.signum() {
+        DirectionCheck::Correct
+    } else {
+        DirectionCheck::Reversed
+    }
+}
+
+fn elevon_state(command: f32, cal: &ServoCalibration, commanded_pulse: Option<f32>) -> ElevonState {
+    ElevonState {
+        command,
+        expected_pulse: command_to_pulse(command, cal),
+        commanded_pulse,
+        direction: match commanded_pulse {
+            Some(p) => check_direction(command, cal, p),
+            None => DirectionCheck::Indeterminate,
+        },
+    }
+}
+
+/// Apply the elevon mix to live stick inputs, and compare against what the FC is commanding.
+/// `commanded` are left and right pulse widths reported by the FC, in ms, if available.
+pub fn preview(
+    controls: &ChannelData,
+    cal_left: &ServoCalibration,
+    cal_right: &ServoCalibration,
+    commanded: Option<(f32, f32)>,
+) -> ElevonPreview {
+    let (left, right) = elevon_mix(controls);
+
+    ElevonPreview {
+        left: elevon_state(left, cal_left, commanded.map(|c| c.0)),
+        right: elevon_state(right, cal_right, commanded.map(|c| c.1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sticks(roll: f32, pitch: f32) -> ChannelData {
+        ChannelData {
+            roll,
+            pitch,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mixes_pitch_and_roll() {
+        // Pitch up raises both.
+        assert_eq!(elevon_mix(&sticks(0., 0.5)), (0.5, 0.5));
+        // Roll right raises the right, and lowers the left.
+        assert_eq!(elevon_mix(&sticks(0.5, 0.)), (-0.5, 0.5));
+        // Full stick in both saturates.
+        assert_eq!(elevon_mix(&sticks(1., 1.)), (0., 1.));
+        assert_eq!(elevon_mix(&sticks(-1., -1.)), (0., -1.));
+    }
+
+    #[test]
+    fn applies_calibration() {
+        let cal = ServoCalibration {
+            min: 1.1,
+            center: 1.5,
+            max: 1.8,
+            reversed: false,
+        };
+
+        assert_eq!(command_to_pulse(0., &cal), 1.5);
+        assert_eq!(command_to_pulse(1., &cal), 1.8);
+        assert_eq!(command_to_pulse(-1., &cal), 1.1);
+
+        let reversed = ServoCalibration {
+            reversed: true,
+            ..cal
+        };
+        assert_eq!(command_to_pulse(1., &reversed), 1.1);
+    }
+
+    #[test]
+    fn flags_reversed_elevons() {
+        let cal = ServoCalibration::default();
+        // Pitch up: both should move above center.
+        let controls = sticks(0., 0.8);
+
+        let result = preview(&controls, &cal, &cal, Some((1.9, 1.1)));
+        assert_eq!(result.left.direction, DirectionCheck::Correct);
+        assert_eq!(result.right.direction, DirectionCheck::Reversed);
+
+        let result = preview(&controls, &cal, &cal, Some((1.5, 1.5)));
+        assert_eq!(result.left.direction, DirectionCheck::NoResponse);
+
+        // Near center, we can't tell.
+        let result = preview(&sticks(0., 0.1), &cal, &cal, Some((1.1, 1.1)));
+        assert_eq!(result.left.direction, DirectionCheck::Indeterminate);
+
+        let result = preview(&controls, &cal, &cal, None);
+        assert_eq!(result.left.direction, DirectionCheck::Indeterminate);
+    }
+}
@@ -0,0 +1,105 @@
+//! Where the web interface listens. The address and port come from the command line, the
+//! `PREFLIGHT_ADDRESS` and `PREFLIGHT_PORT` environment variables, or `server.toml`, in that
+//! order. By default we only listen on this computer; other devices on the network, like a phone,
+//! can connect only if asked for, with `--lan`, or an address of `0.0.0.0`.
+
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_FILE: &str = "server.toml";
+
+pub const DEFAULT_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+/// Above 1024, so we don't need root on Linux.
+pub const DEFAULT_PORT: u16 = 8030;
+
+/// The contents of `CONFIG_FILE`. Either may be left out.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+}
+
+impl ServerConfig {
+    /// Load from `CONFIG_FILE`, or leave everything unset if it doesn't exist.
+    pub fn load() -> Result<Self, io::Error> {
+        match fs::read_to_string(CONFIG_FILE) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Problem parsing {}: {}", CONFIG_FILE, e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Check we can listen on `addr` before launching, so we can explain why not. Rocket only reports
+/// the OS error.
+pub fn check_bind(addr: SocketAddr) -> Result<(), io::Error> {
+    match TcpListener::bind(addr) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && addr.port() < 1024 => {
+            Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "Can't listen on {}: Ports below 1024 need root on Linux. Choose another with \
+                    `--port`, eg the default of {}, or give this program the \
+                    CAP_NET_BIND_SERVICE capability.",
+                    addr, DEFAULT_PORT
+                ),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => Err(io::Error::new(
+            e.kind(),
+            format!(
+                "Can't listen on {}: Something else is using port {}; is Preflight already \
+                running? Choose another port with `--port`.",
+                addr,
+                addr.port()
+            ),
+        )),
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Can't listen on {}: {}", addr, e),
+        )),
+    }
+}
+
+/// The URLs the web interface is reachable at, when listening on `addr`. This computer's comes
+/// first. If listening on all interfaces, includes one for each interface's address.
+pub fn urls(addr: SocketAddr) -> Vec<String> {
+    let url = |ip: IpAddr| format!("http://{}", SocketAddr::new(ip, addr.port()));
+
+    if addr.ip().is_loopback() {
+        return vec![format!("http://localhost:{}", addr.port())];
+    }
+    if !addr.ip().is_unspecified() {
+        return vec![url(addr.ip())];
+    }
+
+    let mut result = vec![format!("http://localhost:{}", addr.port())];
+
+    let interfaces = if_addrs::get_if_addrs().unwrap_or_default();
+    for iface in interfaces {
+        let ip = iface.ip();
+        // An IPv4 socket doesn't accept IPv6 connections. Link-local IPv6 addresses need a
+        // zone ID, which browsers don't accept.
+        let reachable = match ip {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(v6) => addr.is_ipv6() && (v6.segments()[0] & 0xffc0) != 0xfe80,
+        };
+
+        if reachable && !ip.is_loopback() {
+            result.push(url(ip));
+        }
+    }
+
+    result
+}
@@ -0,0 +1,120 @@
+//! The FC's parameter table. Instead of a message type per setting, the FC describes each of its
+//! parameters (name, type, limits, and default), and we read and write them by index. New
+//! firmware settings show up here without changes to `types`.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::types::{ParamInfo, ParamType, ParamValue, PARAM_VALUE_SIZE};
+
+impl ParamValue {
+    /// Decode from the 4 bytes the FC sends.
+    pub fn from_bytes(param_type: ParamType, b: [u8; PARAM_VALUE_SIZE]) -> Self {
+        match param_type {
+            ParamType::Bool => Self::Bool(u32::from_be_bytes(b) != 0),
+            ParamType::U8 | ParamType::U32 => Self::Int(u32::from_be_bytes(b) as i64),
+            ParamType::I32 => Self::Int(i32::from_be_bytes(b) as i64),
+            ParamType::F32 => Self::Float(f32::from_be_bytes(b)),
+        }
+    }
+
+    /// Encode for the FC. Assumes the value has been checked against the type with `parse`.
+    pub fn to_bytes(self, param_type: ParamType) -> [u8; PARAM_VALUE_SIZE] {
+        let v = self.as_f64().unwrap_or(0.);
+
+        match (param_type, self) {
+            (ParamType::F32, _) => (v as f32).to_be_bytes(),
+            (ParamType::I32, _) => (v as i32).to_be_bytes(),
+            (_, Self::Bool(b)) => (b as u32).to_be_bytes(),
+            _ => (v as u32).to_be_bytes(),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Bool(_) => None,
+            Self::Int(v) => Some(*v as f64),
+            Self::Float(v) => Some(*v as f64),
+        }
+    }
+}
+
+impl ParamInfo {
+    /// Parse a JSON value for this parameter, checking its type and limits.
+    pub fn parse(&self, v: &Value) -> Result<ParamValue, String> {
+        let result = match (self.param_type, v) {
+            (ParamType::Bool, Value::Bool(b)) => ParamValue::Bool(*b),
+            (ParamType::F32, Value::Number(n)) => {
+                ParamValue::Float(n.as_f64().unwrap_or(0.) as f32)
+            }
+            (_, Value::Number(n)) if n.is_i64() || n.is_u64() => {
+                ParamValue::Int(n.as_i64().ok_or("Value is out of range.")?)
+            }
+            _ => {
+                return Err(format!(
+                    "{} takes a {:?} value; received {}.",
+                    self.name, self.param_type, v
+                ))
+            }
+        };
+
+        let type_range = match self.param_type {
+            ParamType::U8 => Some((0., u8::MAX as f64)),
+            ParamType::U32 => Some((0., u32::MAX as f64)),
+            ParamType::I32 => Some((i32::MIN as f64, i32::MAX as f64)),
+            _ => None,
+        };
+
+        if let (Some(v), Some(min), Some(max)) =
+            (result.as_f64(), self.min.as_f64(), self.max.as_f64())
+        {
+            let (min, max) = match type_range {
+                Some((t_min, t_max)) => (min.max(t_min), max.min(t_max)),
+                None => (min, max),
+            };
+
+            if !(min..=max).contains(&v) {
+                return Err(format!(
+                    "{} must be from {} to {}; received {}.",
+                    self.name, min, max, v
+                ));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// A parameter's description, and current value.
+#[derive(Clone, Debug, Serialize)]
+pub struct Param {
+    #[serde(flatten)]
+    pub info: ParamInfo,
+    pub value: ParamValue,
+}
+
+/// The FC's parameter descriptions, as enumerated on first use.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ParamTable {
+    pub params: Vec<ParamInfo>,
+}
+
+impl ParamTable {
+    /// Find a parameter by name, or index.
+    pub fn find(&self, key: &str) -> Option<&ParamInfo> {
+        match key.parse::<u16>() {
+            Ok(i) => self.params.iter().find(|p| p.index == i),
+            Err(_) => self.params.iter().find(|p| p.name == key),
+        }
+    }
+
+    /// Parameters whose name contains `query`, ignoring case. All of them if `query` is empty.
+    pub fn search(&self, query: &str) -> Vec<&ParamInfo> {
+        let query = query.to_lowercase();
+
+        self.params
+            .iter()
+            .filter(|p| p.name.to_lowercase().contains(&query))
+            .collect()
+    }
+}
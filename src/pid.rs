@@ -0,0 +1,165 @@
+//! PID tuning: Range checks on gains before they're sent to the FC, named tuning profiles stored
+//! in `pid_profiles.toml`, and diffs between a profile and the FC's gains.
+
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::types::{PidConfig, PidGains};
+
+pub const PROFILES_FILE: &str = "pid_profiles.toml";
+
+/// Upper limit for any gain. Anything higher is almost certainly a typo, and could make the
+/// aircraft violently unstable.
+pub const GAIN_MAX: f32 = 10.;
+
+/// Filter cutoff limits, in Hz. Below the minimum, the filter's lag destabilizes the loop; above
+/// the maximum, it doesn't filter anything useful.
+pub const LPF_MIN_HZ: f32 = 10.;
+pub const LPF_MAX_HZ: f32 = 500.;
+
+fn validate_gains(name: &str, g: &PidGains) -> Result<(), String> {
+    for (term, v) in [("kp", g.kp), ("ki", g.ki), ("kd", g.kd), ("kff", g.kff)] {
+        if !(0. ..=GAIN_MAX).contains(&v) {
+            return Err(format!(
+                "{}.{} is {}; it must be from 0 to {}.",
+                name, term, v, GAIN_MAX
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+impl PidConfig {
+    /// Check that all gains and filter cutoffs are in range. We run this before sending gains
+    /// to the FC, or saving a profile.
+    pub fn validate(&self) -> Result<(), String> {
+        for (axis, pid) in [
+            ("roll", &self.roll),
+            ("pitch", &self.pitch),
+            ("yaw", &self.yaw),
+        ] {
+            validate_gains(&format!("{}.rate", axis), &pid.rate)?;
+            validate_gains(&format!("{}.attitude", axis), &pid.attitude)?;
+        }
+
+        for (name, v) in [
+            ("gyro_lpf_hz", self.gyro_lpf_hz),
+            ("d_term_lpf_hz", self.d_term_lpf_hz),
+        ] {
+            if !(LPF_MIN_HZ..=LPF_MAX_HZ).contains(&v) {
+                return Err(format!(
+                    "{} is {}Hz; it must be from {}Hz to {}Hz.",
+                    name, v, LPF_MIN_HZ, LPF_MAX_HZ
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PidProfile {
+    pub name: String,
+    pub config: PidConfig,
+}
+
+/// The contents of `PROFILES_FILE`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PidProfiles {
+    pub profiles: Vec<PidProfile>,
+}
+
+impl PidProfiles {
+    /// Load profiles from `PROFILES_FILE`, or none if it doesn't exist.
+    pub fn load() -> Result<Self, io::Error> {
+        match fs::read_to_string(PROFILES_FILE) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Problem parsing {}: {}", PROFILES_FILE, e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        let contents = toml::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        fs::write(PROFILES_FILE, contents)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PidConfig> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| &p.config)
+    }
+
+    /// Add a profile, or replace the one with the same name.
+    pub fn insert(&mut self, name: &str, config: PidConfig) {
+        match self.profiles.iter_mut().find(|p| p.name == name) {
+            Some(p) => p.config = config,
+            None => self.profiles.push(PidProfile {
+                name: name.to_owned(),
+                config,
+            }),
+        }
+    }
+
+    /// Returns false if there was no profile with this name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.profiles.len();
+        self.profiles.retain(|p| p.name != name);
+        self.profiles.len() != len
+    }
+}
+
+/// A value that differs between a profile and the FC.
+#[derive(Clone, Debug, Serialize)]
+pub struct PidDiff {
+    /// Dotted path, eg `roll.rate.kp`.
+    pub field: String,
+    pub profile: f32,
+    pub fc: f32,
+}
+
+fn flatten(prefix: &str, v: &Value, result: &mut Vec<(String, f32)>) {
+    match v {
+        Value::Object(map) => {
+            for (k, v) in map {
+                let name = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten(&name, v, result);
+            }
+        }
+        v => result.push((prefix.to_owned(), v.as_f64().unwrap_or(f64::NAN) as f32)),
+    }
+}
+
+fn fields(config: &PidConfig) -> Vec<(String, f32)> {
+    let mut result = Vec::new();
+    if let Ok(v) = serde_json::to_value(config) {
+        flatten("", &v, &mut result);
+    }
+    result
+}
+
+/// Values that differ between a profile and the FC's gains.
+pub fn diff(profile: &PidConfig, fc: &PidConfig) -> Vec<PidDiff> {
+    fields(profile)
+        .into_iter()
+        .zip(fields(fc))
+        .filter(|((_, p), (_, f))| p != f)
+        .map(|((field, profile), (_, fc))| PidDiff { field, profile, fc })
+        .collect()
+}
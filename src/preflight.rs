@@ -0,0 +1,427 @@
+//! Preflight checks: Go/no-go rules over the decoded telemetry. Thresholds are loaded from
+//! `preflight.toml` if present; any values not specified there use the defaults below.
+
+use std::{fs, io, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    to_degrees,
+    types::{AltHoldSwitch, ArmStatus, AutopilotSwitch, ChannelData, Quaternion},
+    ReadData,
+};
+
+pub const THRESHOLDS_FILE: &str = "preflight.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, message: String) -> Self {
+        Self {
+            name,
+            status,
+            message,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PreflightReport {
+    /// True if no check failed. Warnings don't block a go.
+    pub go: bool,
+    /// The worst status of any check.
+    pub status: CheckStatus,
+    pub results: Vec<CheckResult>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Thresholds {
+    /// Per-cell voltage required for takeoff, in V. Cell count comes from the battery profile.
+    pub cell_v_fail: f32,
+    pub cell_v_warn: f32,
+    /// Per-cell voltage above full charge we allow before suspecting a wrong cell count, in V.
+    pub cell_v_overcharge_margin: f32,
+    /// Allowed deviation of the attitude quaternion's norm from 1.
+    pub quat_norm_tolerance: f32,
+    /// Tilt from level, in degrees.
+    pub tilt_warn: f32,
+    pub tilt_fail: f32,
+    /// Uplink link quality, in %.
+    pub link_quality_warn: u8,
+    pub link_quality_fail: u8,
+    /// RSSI, in dBm, below which we consider an antenna dead.
+    pub antenna_rssi_min: i16,
+    /// Throttle stick position, 0. to 1., above which we don't consider it idle.
+    pub throttle_idle_max: f32,
+    /// Barometric altitude, in m MSL.
+    pub altimeter_min: f32,
+    pub altimeter_max: f32,
+    /// Time-of-flight altitude, in m AGL. On the ground, this should be near 0.
+    pub altimeter_agl_max: f32,
+    /// Age of the latest reading from the FC, in s, beyond which we don't trust it.
+    pub telemetry_age_max: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            cell_v_fail: 3.5,
+            cell_v_warn: 3.7,
+            cell_v_overcharge_margin: 0.05,
+            quat_norm_tolerance: 0.02,
+            tilt_warn: 5.,
+            tilt_fail: 20.,
+            link_quality_warn: 90,
+            link_quality_fail: 70,
+            antenna_rssi_min: -115,
+            throttle_idle_max: 0.05,
+            altimeter_min: -500.,
+            altimeter_max: 9_000.,
+            altimeter_agl_max: 1.,
+            telemetry_age_max: 1.,
+        }
+    }
+}
+
+impl Thresholds {
+    /// Load thresholds from `THRESHOLDS_FILE`, or use defaults if it doesn't exist.
+    pub fn load() -> Result<Self, io::Error> {
+        match fs::read_to_string(THRESHOLDS_FILE) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Problem parsing {}: {}", THRESHOLDS_FILE, e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Pass if `v` is on the good side of `warn`, warn if between `warn` and `fail`, else fail. For
+/// thresholds where lower is better, pass negated values.
+fn grade(v: f32, warn: f32, fail: f32) -> CheckStatus {
+    if v >= warn {
+        CheckStatus::Pass
+    } else if v >= fail {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Fail
+    }
+}
+
+fn check_battery(data: &ReadData, t: &Thresholds) -> CheckResult {
+    let name = "Battery voltage";
+
+    let battery = match &data.battery {
+        Some(b) => b,
+        None => return CheckResult::new(name, CheckStatus::Fail, "No battery status.".into()),
+    };
+
+    let (cells, cell_v) = match (battery.cell_count, battery.cell_v) {
+        (Some(c), Some(v)) => (c, v),
+        _ => return CheckResult::new(name, CheckStatus::Fail, "No battery voltage.".into()),
+    };
+
+    let cell_v_max = battery.chemistry.cell_v_full() + t.cell_v_overcharge_margin;
+    if cell_v > cell_v_max {
+        return CheckResult::new(
+            name,
+            CheckStatus::Fail,
+            format!(
+                "{:.2}V per cell ({}S) is above the {:.2}V maximum. Check the cell count in the \
+                {} profile.",
+                cell_v, cells, cell_v_max, battery.profile
+            ),
+        );
+    }
+
+    CheckResult::new(
+        name,
+        grade(cell_v, t.cell_v_warn, t.cell_v_fail),
+        format!(
+            "{:.2}V per cell ({}S, {:.1}V, {} profile)",
+            cell_v, cells, data.batt_v, battery.profile
+        ),
+    )
+}
+
+fn quat_norm(q: &Quaternion) -> f32 {
+    (q.w.powi(2) + q.x.powi(2) + q.y.powi(2) + q.z.powi(2)).sqrt()
+}
+
+/// Angle between the aircraft's up axis and vertical, in degrees.
+pub fn tilt(q: &Quaternion) -> f32 {
+    let norm = quat_norm(q);
+    let (x, y) = (q.x / norm, q.y / norm);
+
+    to_degrees((1. - 2. * (x.powi(2) + y.powi(2))).clamp(-1., 1.).acos())
+}
+
+fn check_attitude(data: &ReadData, t: &Thresholds) -> Vec<CheckResult> {
+    let q = &data.attitude_quat;
+    let norm = quat_norm(q);
+
+    if (norm - 1.).abs() > t.quat_norm_tolerance {
+        return vec![CheckResult::new(
+            "Attitude",
+            CheckStatus::Fail,
+            format!(
+                "Attitude quaternion isn't normalized (norm {:.3}). The AHRS may not be running.",
+                norm
+            ),
+        )];
+    }
+
+    let tilt = tilt(q);
+
+    vec![
+        CheckResult::new(
+            "Attitude",
+            CheckStatus::Pass,
+            format!("Quaternion norm {:.3}", norm),
+        ),
+        CheckResult::new(
+            "Level",
+            grade(-tilt, -t.tilt_warn, -t.tilt_fail),
+            format!("{:.1}° from level", tilt),
+        ),
+    ]
+}
+
+fn check_link(data: &ReadData, t: &Thresholds) -> Vec<CheckResult> {
+    let ls = &data.link_stats;
+    let mut result = vec![CheckResult::new(
+        "Link quality",
+        grade(
+            ls.uplink_link_quality as f32,
+            t.link_quality_warn as f32,
+            t.link_quality_fail as f32,
+        ),
+        format!("{}%", ls.uplink_link_quality),
+    )];
+
+    // An RSSI of 0dBm means not reported.
+    let mut dead = Vec::new();
+    for (i, rssi) in [ls.uplink_rssi_1, ls.uplink_rssi_2].iter().enumerate() {
+        if *rssi == 0 || *rssi < t.antenna_rssi_min {
+            dead.push(format!("antenna {}", i + 1));
+        }
+    }
+
+    result.push(if dead.is_empty() {
+        CheckResult::new(
+            "Antennas",
+            CheckStatus::Pass,
+            format!("{}dBm, {}dBm", ls.uplink_rssi_1, ls.uplink_rssi_2),
+        )
+    } else {
+        CheckResult::new(
+            "Antennas",
+            CheckStatus::Fail,
+            format!("No signal on {}.", dead.join(" and ")),
+        )
+    });
+
+    result
+}
+
+/// Mode switches should be where a normal takeoff expects them. An autopilot mode would take
+/// over on arming, and altitude hold would ignore the throttle.
+fn check_switches(c: &ChannelData) -> CheckResult {
+    let name = "Mode switches";
+
+    if c.autopilot != AutopilotSwitch::Disabled {
+        return CheckResult::new(
+            name,
+            CheckStatus::Fail,
+            format!(
+                "Autopilot switch is in {:?}. Disable it before arming.",
+                c.autopilot
+            ),
+        );
+    }
+
+    if c.alt_hold != AltHoldSwitch::Disabled {
+        return CheckResult::new(
+            name,
+            CheckStatus::Warn,
+            format!(
+                "Altitude hold is {:?}; the throttle won't command climb directly.",
+                c.alt_hold
+            ),
+        );
+    }
+
+    CheckResult::new(
+        name,
+        CheckStatus::Pass,
+        format!(
+            "Input mode {:?}, auto-recover {:?}, obstacle avoidance {:?}",
+            c.input_mode, c.auto_recover, c.obstacle_avoid
+        ),
+    )
+}
+
+fn check_controls(data: &ReadData, t: &Thresholds) -> Vec<CheckResult> {
+    let c = &data.controls;
+
+    vec![
+        match c.arm_status {
+            ArmStatus::Disarmed => {
+                CheckResult::new("Arm switch", CheckStatus::Pass, "Disarmed".into())
+            }
+            ArmStatus::Armed => CheckResult::new(
+                "Arm switch",
+                CheckStatus::Fail,
+                "Armed. Disarm before approaching the aircraft.".into(),
+            ),
+        },
+        if c.throttle <= t.throttle_idle_max {
+            CheckResult::new(
+                "Throttle",
+                CheckStatus::Pass,
+                format!("At idle ({:.2})", c.throttle),
+            )
+        } else {
+            CheckResult::new(
+                "Throttle",
+                CheckStatus::Fail,
+                format!("Not at idle ({:.2})", c.throttle),
+            )
+        },
+        check_switches(c),
+    ]
+}
+
+fn check_altimeters(data: &ReadData, t: &Thresholds) -> Vec<CheckResult> {
+    let mut result = vec![
+        if (t.altimeter_min..=t.altimeter_max).contains(&data.altimeter) {
+            CheckResult::new(
+                "Barometric altimeter",
+                CheckStatus::Pass,
+                format!("{:.0}m MSL", data.altimeter),
+            )
+        } else {
+            CheckResult::new(
+                "Barometric altimeter",
+                CheckStatus::Fail,
+                format!(
+                    "{:.0}m MSL is outside {:.0}m to {:.0}m.",
+                    data.altimeter, t.altimeter_min, t.altimeter_max
+                ),
+            )
+        },
+    ];
+
+    // The time-of-flight sensor is optional, and reads nothing when out of range.
+    result.push(match data.altimeter_agl {
+        Some(agl) if (0.0..=t.altimeter_agl_max).contains(&agl) => CheckResult::new(
+            "AGL altimeter",
+            CheckStatus::Pass,
+            format!("{:.2}m AGL", agl),
+        ),
+        Some(agl) => CheckResult::new(
+            "AGL altimeter",
+            CheckStatus::Warn,
+            format!(
+                "{:.2}m AGL; expected 0m to {:.1}m on the ground.",
+                agl, t.altimeter_agl_max
+            ),
+        ),
+        None => CheckResult::new("AGL altimeter", CheckStatus::Warn, "No reading.".into()),
+    });
+
+    result
+}
+
+/// The other checks grade the cached readings; make sure they're recent. `age` is `None` if we've
+/// never had a reading from the FC.
+fn check_telemetry(age: Option<Duration>, t: &Thresholds) -> CheckResult {
+    match age {
+        Some(age) if age.as_secs_f32() <= t.telemetry_age_max => CheckResult::new(
+            "Telemetry",
+            CheckStatus::Pass,
+            format!("{}ms old", age.as_millis()),
+        ),
+        Some(age) => CheckResult::new(
+            "Telemetry",
+            CheckStatus::Fail,
+            format!(
+                "Last reading from the FC was {:.1}s ago. Check the connection.",
+                age.as_secs_f32()
+            ),
+        ),
+        None => CheckResult::new(
+            "Telemetry",
+            CheckStatus::Fail,
+            "No readings from the FC.".into(),
+        ),
+    }
+}
+
+/// Run all checks against a telemetry snapshot, taken `age` ago.
+pub fn run_checks(data: &ReadData, age: Option<Duration>, t: &Thresholds) -> PreflightReport {
+    let mut results = vec![check_telemetry(age, t), check_battery(data, t)];
+    results.append(&mut check_attitude(data, t));
+    results.append(&mut check_link(data, t));
+    results.append(&mut check_controls(data, t));
+    results.append(&mut check_altimeters(data, t));
+
+    let status = results
+        .iter()
+        .map(|r| r.status)
+        .max()
+        .unwrap_or(CheckStatus::Pass);
+
+    PreflightReport {
+        go: status != CheckStatus::Fail,
+        status,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn telemetry(report: &PreflightReport) -> CheckStatus {
+        report
+            .results
+            .iter()
+            .find(|r| r.name == "Telemetry")
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn stale_telemetry_fails() {
+        let t = Thresholds::default();
+        let data = ReadData::default();
+
+        let fresh = run_checks(&data, Some(Duration::from_millis(100)), &t);
+        assert_eq!(telemetry(&fresh), CheckStatus::Pass);
+
+        let stale = run_checks(&data, Some(Duration::from_secs(5)), &t);
+        assert_eq!(telemetry(&stale), CheckStatus::Fail);
+        assert!(!stale.go);
+
+        let never = run_checks(&data, None, &t);
+        assert_eq!(telemetry(&never), CheckStatus::Fail);
+        assert!(!never.go);
+    }
+}
@@ -0,0 +1,409 @@
+//! Guided stick calibration. The pilot centers the sticks, moves them through their full travel,
+//! then holds both in the top-right corner, so we can tell which axes are reversed. We report
+//! endpoints, centering offset, deadband, reversed axes, and throttle range. Results are saved to
+//! `rc_cal.toml`, and to the FC if it supports it.
+//!
+//! We assume a radio that reports stick up and right as positive, before any reversal.
+
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{ChannelData, RcAxis, StickCalibration};
+
+pub const PROFILE_FILE: &str = "rc_cal.toml";
+
+pub const AXES: [RcAxis; 4] = [RcAxis::Roll, RcAxis::Pitch, RcAxis::Yaw, RcAxis::Throttle];
+
+/// Readings needed to judge where the sticks rest.
+const MIN_CENTER_SAMPLES: usize = 10;
+
+/// Added to the noise seen at rest, to set the deadband.
+const DEADBAND_MARGIN: f32 = 0.01;
+
+/// Above these, we suggest looking at the radio or gimbals.
+const DEADBAND_WARN: f32 = 0.05;
+const CENTER_OFFSET_WARN: f32 = 0.05;
+
+/// Fraction of the nominal travel each axis should reach.
+const MIN_TRAVEL: f32 = 0.9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Stage {
+    Center,
+    Endpoints,
+    Direction,
+    Done,
+}
+
+impl Stage {
+    pub fn instructions(&self) -> &'static str {
+        match self {
+            Self::Center => {
+                "Let roll, pitch, and yaw rest at center, and put the throttle at idle. Then \
+                continue."
+            }
+            Self::Endpoints => {
+                "Move both sticks through their full travel, including the corners. Then continue."
+            }
+            Self::Direction => {
+                "Hold both sticks in the top-right corner (roll right, pitch forward, yaw right, \
+                full throttle), and continue while holding them."
+            }
+            Self::Done => "Calibration complete. Review the results, and save.",
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct AxisTrack {
+    /// Readings while at rest.
+    rest: Vec<f32>,
+    min: f32,
+    max: f32,
+    latest: f32,
+}
+
+impl AxisTrack {
+    fn center(&self) -> f32 {
+        self.rest.iter().sum::<f32>() / self.rest.len().max(1) as f32
+    }
+
+    /// The furthest a reading at rest strayed from center.
+    fn noise(&self) -> f32 {
+        let center = self.center();
+        self.rest
+            .iter()
+            .map(|v| (v - center).abs())
+            .fold(0., f32::max)
+    }
+}
+
+fn axis_value(controls: &ChannelData, axis: RcAxis) -> f32 {
+    match axis {
+        RcAxis::Roll => controls.roll,
+        RcAxis::Pitch => controls.pitch,
+        RcAxis::Yaw => controls.yaw,
+        RcAxis::Throttle => controls.throttle,
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AxisReport {
+    pub axis: RcAxis,
+    pub calibration: StickCalibration,
+    /// For centering axes, center's offset from the middle of the travel. For throttle, idle's
+    /// offset from the bottom.
+    pub center_offset: f32,
+    /// `max - min`, as a fraction of the nominal travel.
+    pub travel: f32,
+    pub issues: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ThrottleRange {
+    pub idle: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RcCalReport {
+    pub axes: Vec<AxisReport>,
+    pub throttle: ThrottleRange,
+}
+
+impl RcCalReport {
+    pub fn profile(&self) -> RcCalProfile {
+        let mut result = RcCalProfile::default();
+        for a in &self.axes {
+            *result.get_mut(a.axis) = a.calibration;
+        }
+        result
+    }
+}
+
+/// An axis's live position, and what we've recorded so far.
+#[derive(Clone, Debug, Serialize)]
+pub struct AxisProgress {
+    pub axis: RcAxis,
+    pub value: f32,
+    pub center: Option<f32>,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionStatus {
+    pub stage: Stage,
+    pub instructions: &'static str,
+    pub axes: Vec<AxisProgress>,
+    /// Set once the session is done.
+    pub report: Option<RcCalReport>,
+}
+
+/// A calibration in progress. Feed it every controls reading.
+pub struct Session {
+    stage: Stage,
+    axes: [AxisTrack; 4],
+    report: Option<RcCalReport>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            stage: Stage::Center,
+            axes: Default::default(),
+            report: None,
+        }
+    }
+}
+
+impl Session {
+    pub fn report(&self) -> Option<&RcCalReport> {
+        self.report.as_ref()
+    }
+
+    pub fn update(&mut self, controls: &ChannelData) {
+        for (axis, track) in AXES.iter().zip(self.axes.iter_mut()) {
+            let v = axis_value(controls, *axis);
+            track.latest = v;
+
+            match self.stage {
+                Stage::Center => track.rest.push(v),
+                Stage::Endpoints => {
+                    track.min = track.min.min(v);
+                    track.max = track.max.max(v);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Move to the next stage, if the readings for this one are usable.
+    pub fn advance(&mut self) -> Result<(), String> {
+        match self.stage {
+            Stage::Center => {
+                if self.axes[0].rest.len() < MIN_CENTER_SAMPLES {
+                    return Err("Hold the sticks at rest a moment longer.".into());
+                }
+
+                for track in self.axes.iter_mut() {
+                    track.min = track.center();
+                    track.max = track.center();
+                }
+                self.stage = Stage::Endpoints;
+            }
+            Stage::Endpoints => {
+                for (axis, track) in AXES.iter().zip(self.axes.iter()) {
+                    let center = track.center();
+                    let reached = if *axis == RcAxis::Throttle {
+                        track.max - track.min > 0.
+                    } else {
+                        track.min < center && track.max > center
+                    };
+
+                    if !reached {
+                        return Err(format!(
+                            "The {:?} stick hasn't moved through its travel.",
+                            axis
+                        ));
+                    }
+                }
+                self.stage = Stage::Direction;
+            }
+            Stage::Direction => {
+                let mut axes = Vec::new();
+
+                for (axis, track) in AXES.iter().zip(self.axes.iter()) {
+                    // Require a clear deflection, so a stick left at rest isn't judged reversed.
+                    let center = track.center();
+                    let deflection = track.latest - center;
+                    let needed = if *axis == RcAxis::Throttle {
+                        (track.max - track.min) / 2.
+                    } else {
+                        (center - track.min).min(track.max - center) / 2.
+                    };
+
+                    if deflection.abs() < needed {
+                        return Err(format!(
+                            "The {:?} stick isn't deflected. Hold both sticks in the top-right \
+                            corner.",
+                            axis
+                        ));
+                    }
+
+                    axes.push(axis_report(*axis, track, deflection < 0.));
+                }
+
+                let t = &self.axes[3];
+                self.report = Some(RcCalReport {
+                    axes,
+                    throttle: ThrottleRange {
+                        idle: t.center(),
+                        min: t.min,
+                        max: t.max,
+                    },
+                });
+                self.stage = Stage::Done;
+            }
+            Stage::Done => return Err("Calibration is already complete.".into()),
+        }
+
+        Ok(())
+    }
+
+    pub fn status(&self) -> SessionStatus {
+        let recorded_endpoints = self.stage != Stage::Center;
+
+        SessionStatus {
+            stage: self.stage,
+            instructions: self.stage.instructions(),
+            axes: AXES
+                .iter()
+                .zip(self.axes.iter())
+                .map(|(axis, t)| AxisProgress {
+                    axis: *axis,
+                    value: t.latest,
+                    center: recorded_endpoints.then(|| t.center()),
+                    min: recorded_endpoints.then_some(t.min),
+                    max: recorded_endpoints.then_some(t.max),
+                })
+                .collect(),
+            report: self.report.clone(),
+        }
+    }
+}
+
+fn axis_report(axis: RcAxis, track: &AxisTrack, reversed: bool) -> AxisReport {
+    let center = track.center();
+    let (min, max) = (track.min, track.max);
+    let mut issues = Vec::new();
+
+    let (center_offset, nominal_travel) = if axis == RcAxis::Throttle {
+        // A throttle may run 0 to 1, or -1 to 1. Idle sits at the bottom, or at the top if
+        // reversed.
+        let idle_offset = if reversed { max - center } else { center - min };
+        (idle_offset, if min.min(max) < -0.5 { 2. } else { 1. })
+    } else {
+        (center - (min + max) / 2., 2.)
+    };
+
+    let travel = (max - min) / nominal_travel;
+    let deadband = track.noise() + DEADBAND_MARGIN;
+
+    if travel < MIN_TRAVEL {
+        issues.push(format!(
+            "Only reaches {:.0}% of full travel. Check the radio's endpoints.",
+            travel * 100.
+        ));
+    }
+    if center_offset.abs() > CENTER_OFFSET_WARN {
+        issues.push(if axis == RcAxis::Throttle {
+            format!(
+                "Idle is {:.2} above the bottom of the travel. Check the radio's throttle trim.",
+                center_offset
+            )
+        } else {
+            format!(
+                "Rests {:.2} off center. Check the radio's trims and subtrims.",
+                center_offset
+            )
+        });
+    }
+    if deadband > DEADBAND_WARN {
+        issues.push(format!(
+            "Noisy at rest; needs a {:.2} deadband. Check the gimbal.",
+            deadband
+        ));
+    }
+
+    AxisReport {
+        axis,
+        calibration: StickCalibration {
+            min,
+            center,
+            max,
+            deadband,
+            reversed,
+        },
+        center_offset,
+        travel,
+        issues,
+    }
+}
+
+impl StickCalibration {
+    /// Check that the endpoints are ordered, and the deadband is sane. Throttle's center (idle)
+    /// may sit at an endpoint.
+    pub fn validate(&self, axis: RcAxis) -> Result<(), String> {
+        let ordered = if axis == RcAxis::Throttle {
+            self.min <= self.center && self.center <= self.max && self.min < self.max
+        } else {
+            self.min < self.center && self.center < self.max
+        };
+
+        if !ordered {
+            return Err(format!(
+                "{:?} calibration must satisfy min < center < max.",
+                axis
+            ));
+        }
+
+        if !(0. ..0.5).contains(&self.deadband) {
+            return Err(format!("{:?} deadband must be from 0 to 0.5.", axis));
+        }
+
+        Ok(())
+    }
+}
+
+/// The contents of `PROFILE_FILE`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RcCalProfile {
+    pub roll: StickCalibration,
+    pub pitch: StickCalibration,
+    pub yaw: StickCalibration,
+    pub throttle: StickCalibration,
+}
+
+impl RcCalProfile {
+    /// Load the saved profile, or `None` if we haven't saved one.
+    pub fn load() -> Result<Option<Self>, io::Error> {
+        match fs::read_to_string(PROFILE_FILE) {
+            Ok(contents) => toml::from_str(&contents).map(Some).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Problem parsing {}: {}", PROFILE_FILE, e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        let contents = toml::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        fs::write(PROFILE_FILE, contents)
+    }
+
+    pub fn get(&self, axis: RcAxis) -> &StickCalibration {
+        match axis {
+            RcAxis::Roll => &self.roll,
+            RcAxis::Pitch => &self.pitch,
+            RcAxis::Yaw => &self.yaw,
+            RcAxis::Throttle => &self.throttle,
+        }
+    }
+
+    fn get_mut(&mut self, axis: RcAxis) -> &mut StickCalibration {
+        match axis {
+            RcAxis::Roll => &mut self.roll,
+            RcAxis::Pitch => &mut self.pitch,
+            RcAxis::Yaw => &mut self.yaw,
+            RcAxis::Throttle => &mut self.throttle,
+        }
+    }
+}
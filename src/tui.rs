@@ -0,0 +1,536 @@
+//! A terminal dashboard, for working over SSH next to the aircraft, eg from a Raspberry Pi. Shows
+//! the same readings as the web interface, runs the preflight checklist, and has a guarded motor
+//! test.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Cell, LineGauge, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    battery::BatteryAlert,
+    cached_data,
+    preflight::{self, CheckStatus, PreflightReport},
+    readings_age, require_aircraft_type, require_disarmed, take_reading, to_euler,
+    types::{AircraftType, ArmStatus, ElrsTxPower, RfMode, RotorPosition, REFRESH_INTERVAL},
+    with_fc, ReadData, THRESHOLDS,
+};
+
+/// How long the motor test spins a motor.
+const MOTOR_TEST_DURATION: Duration = Duration::from_secs(2);
+
+/// The horizon's vertical span, in degrees of pitch.
+const HORIZON_PITCH_SPAN: f32 = 60.;
+
+/// Time to wait for a key press each frame.
+const FRAME_TIME: Duration = Duration::from_millis(50);
+
+const MOTORS: [(char, RotorPosition); 4] = [
+    ('1', RotorPosition::FrontLeft),
+    ('2', RotorPosition::FrontRight),
+    ('3', RotorPosition::AftLeft),
+    ('4', RotorPosition::AftRight),
+];
+
+enum Mode {
+    Normal,
+    ChooseMotor,
+    Confirm(RotorPosition),
+    Spinning {
+        motor: RotorPosition,
+        started: Instant,
+    },
+}
+
+struct App {
+    mode: Mode,
+    /// The result of the latest checklist run, from the `p` key.
+    preflight: Option<PreflightReport>,
+    /// Feedback from the latest action.
+    status: String,
+    /// Why the latest reading failed, if it did.
+    connection_error: Option<String>,
+    last_reading: Option<Instant>,
+}
+
+/// Run the dashboard until `q`.
+pub fn run() -> Result<(), io::Error> {
+    // This restores the terminal on panic too.
+    let mut terminal = ratatui::init();
+
+    let mut app = App {
+        mode: Mode::Normal,
+        preflight: None,
+        status: String::new(),
+        connection_error: Some("Connecting...".into()),
+        last_reading: None,
+    };
+    let result = app.run(&mut terminal);
+
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), io::Error> {
+        loop {
+            let due = self
+                .last_reading
+                .is_none_or(|t| t.elapsed() >= Duration::from_millis(REFRESH_INTERVAL as u64));
+            if due {
+                self.take_reading();
+            }
+
+            if let Mode::Spinning { motor, started } = self.mode {
+                if started.elapsed() >= MOTOR_TEST_DURATION {
+                    self.stop_motor(motor);
+                }
+            }
+
+            let data = cached_data();
+            terminal.draw(|f| self.draw(f, &data))?;
+
+            if event::poll(FRAME_TIME)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn take_reading(&mut self) {
+        self.last_reading = Some(Instant::now());
+
+        self.connection_error = take_reading().err().map(|e| e.to_string());
+    }
+
+    /// Returns false to quit.
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        match self.mode {
+            // Any key stops the motor.
+            Mode::Spinning { motor, .. } => {
+                self.stop_motor(motor);
+                if key == KeyCode::Char('q') {
+                    return false;
+                }
+            }
+            Mode::Normal => match key {
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Char('p') => self.run_preflight(),
+                KeyCode::Char('m') => {
+                    self.mode = Mode::ChooseMotor;
+                    self.status =
+                        "Propellers off! 1 front left, 2 front right, 3 aft left, 4 aft right."
+                            .into();
+                }
+                _ => (),
+            },
+            Mode::ChooseMotor => {
+                self.mode = Mode::Normal;
+                self.status = "Motor test cancelled.".into();
+
+                if let KeyCode::Char(c) = key {
+                    if let Some((_, motor)) = MOTORS.iter().find(|(k, _)| *k == c) {
+                        self.mode = Mode::Confirm(*motor);
+                        self.status = format!(
+                            "Spin the {:?} motor for {}s? Propellers off? y to start; any other \
+                            key cancels.",
+                            motor,
+                            MOTOR_TEST_DURATION.as_secs()
+                        );
+                    }
+                }
+            }
+            Mode::Confirm(motor) => {
+                self.mode = Mode::Normal;
+                self.status = "Motor test cancelled.".into();
+
+                if key == KeyCode::Char('y') {
+                    self.start_motor(motor);
+                }
+            }
+        }
+
+        true
+    }
+
+    fn run_preflight(&mut self) {
+        self.take_reading();
+
+        let thresholds = THRESHOLDS.lock().unwrap().clone().unwrap_or_default();
+        let report = preflight::run_checks(&cached_data(), readings_age(), &thresholds);
+
+        self.status = if report.go {
+            "Preflight: GO.".into()
+        } else {
+            "Preflight: NO GO. See the failed checks.".into()
+        };
+        self.preflight = Some(report);
+    }
+
+    fn start_motor(&mut self, motor: RotorPosition) {
+        let result = require_aircraft_type(AircraftType::Quadcopter)
+            .and_then(|_| require_disarmed())
+            .and_then(|_| with_fc(|fc| fc.send_start_motor_command(motor)));
+
+        match result {
+            Ok(()) => {
+                self.mode = Mode::Spinning {
+                    motor,
+                    started: Instant::now(),
+                };
+                self.status = format!("Spinning the {:?} motor. Any key stops it.", motor);
+            }
+            Err(e) => self.status = format!("Can't start the motor test: {}", e),
+        }
+    }
+
+    fn stop_motor(&mut self, motor: RotorPosition) {
+        let result = with_fc(|fc| fc.send_stop_motor_command(motor));
+
+        // Keep trying each frame until the FC confirms we sent it.
+        match result {
+            Ok(()) => {
+                self.mode = Mode::Normal;
+                self.status = format!("Stopped the {:?} motor.", motor);
+            }
+            Err(e) => self.status = format!("Problem stopping the motor; retrying: {}", e),
+        }
+    }
+
+    fn draw(&self, f: &mut Frame, data: &ReadData) {
+        let [top, middle, bottom, footer] = Layout::vertical([
+            Constraint::Min(11),
+            Constraint::Length(8),
+            Constraint::Min(8),
+            Constraint::Length(2),
+        ])
+        .areas(f.area());
+
+        let [attitude, altitude_battery] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(top);
+        let [altitude, battery] =
+            Layout::vertical([Constraint::Length(4), Constraint::Min(6)]).areas(altitude_battery);
+        let [sticks, link] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(middle);
+        let [waypoints, checks] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(bottom);
+
+        draw_attitude(f, attitude, data);
+        draw_altitude(f, altitude, data);
+        draw_battery(f, battery, data);
+        draw_sticks(f, sticks, data);
+        draw_link(f, link, data);
+        draw_waypoints(f, waypoints, data);
+        self.draw_preflight(f, checks);
+        self.draw_footer(f, footer);
+    }
+
+    fn draw_preflight(&self, f: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Preflight checks ");
+
+        let report = match &self.preflight {
+            Some(r) => r,
+            None => {
+                f.render_widget(
+                    Paragraph::new("Press p to run the checklist.").block(block),
+                    area,
+                );
+                return;
+            }
+        };
+
+        let rows = report.results.iter().map(|r| {
+            Row::new([
+                Cell::from(format!("{:?}", r.status)).style(check_style(r.status)),
+                Cell::from(r.name),
+                Cell::from(r.message.clone()),
+            ])
+        });
+
+        let title = if report.go { " GO " } else { " NO GO " };
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(5),
+                Constraint::Length(16),
+                Constraint::Fill(1),
+            ],
+        )
+        .block(block.title_bottom(Span::styled(title, check_style(report.status))));
+
+        f.render_widget(table, area);
+    }
+
+    fn draw_footer(&self, f: &mut Frame, area: Rect) {
+        let connection = match &self.connection_error {
+            None => Span::styled(" FC connected ", Style::new().black().on_green()),
+            Some(e) => Span::styled(format!(" {} ", e), Style::new().black().on_red()),
+        };
+
+        let keys = match self.mode {
+            Mode::Normal => " q quit  p preflight checks  m motor test",
+            Mode::ChooseMotor => " 1-4 choose a motor  Esc cancel",
+            Mode::Confirm(_) => " y spin it  any other key cancels",
+            Mode::Spinning { .. } => " Any key stops the motor",
+        };
+
+        let text = vec![
+            Line::from(vec![connection, Span::raw(keys)]),
+            Line::from(self.status.clone()),
+        ];
+        f.render_widget(Paragraph::new(text), area);
+    }
+}
+
+fn check_style(status: CheckStatus) -> Style {
+    match status {
+        CheckStatus::Pass => Style::new().green(),
+        CheckStatus::Warn => Style::new().yellow(),
+        CheckStatus::Fail => Style::new().red().bold(),
+    }
+}
+
+/// An artificial horizon in ASCII: Sky is blank, ground is `.`, the horizon line is `-`, and the
+/// aircraft is the `-o-` in the middle. Roll tilts the line; pitch moves it up and down.
+fn horizon(width: usize, height: usize, roll: f32, pitch: f32) -> Vec<String> {
+    let (w, h) = (width as f32, height as f32);
+    // Terminal cells are about twice as tall as they're wide.
+    let slope = roll.to_radians().tan() / 2.;
+    let offset = pitch / HORIZON_PITCH_SPAN * h;
+
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let x = col as f32 - (w - 1.) / 2.;
+                    let y = (h - 1.) / 2. - row as f32;
+
+                    // Rolling right drops the horizon on the right, as seen from the cockpit.
+                    let horizon_y = -slope * x - offset;
+
+                    let center = row == height / 2 && x.abs() <= 1.;
+                    if center {
+                        if x.abs() < 1. {
+                            'o'
+                        } else {
+                            '-'
+                        }
+                    } else if (y - horizon_y).abs() < 0.5 {
+                        '-'
+                    } else if y < horizon_y {
+                        '.'
+                    } else {
+                        ' '
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn draw_attitude(f: &mut Frame, area: Rect, data: &ReadData) {
+    let block = Block::bordered().title(" Attitude ");
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let [numbers, horizon_area] =
+        Layout::horizontal([Constraint::Length(16), Constraint::Min(10)]).areas(inner);
+
+    let (roll, pitch, yaw) = to_euler(&data.attitude_quat);
+    let text = vec![
+        Line::from(format!("Roll  {:7.1}°", roll)),
+        Line::from(format!("Pitch {:7.1}°", pitch)),
+        Line::from(format!("Yaw   {:7.1}°", yaw)),
+    ];
+    f.render_widget(Paragraph::new(text), numbers);
+
+    let lines: Vec<Line> = horizon(
+        horizon_area.width as usize,
+        horizon_area.height as usize,
+        roll,
+        pitch,
+    )
+    .into_iter()
+    .map(Line::from)
+    .collect();
+    f.render_widget(Paragraph::new(lines).cyan(), horizon_area);
+}
+
+fn draw_altitude(f: &mut Frame, area: Rect, data: &ReadData) {
+    let agl = match data.altimeter_agl {
+        Some(a) => format!("{:.2} m", a),
+        None => "Out of range".into(),
+    };
+
+    let text = vec![
+        Line::from(format!("Baro (MSL)  {:.1} m", data.altimeter)),
+        Line::from(format!("ToF (AGL)   {}", agl)),
+    ];
+    f.render_widget(
+        Paragraph::new(text).block(Block::bordered().title(" Altimeters ")),
+        area,
+    );
+}
+
+fn draw_battery(f: &mut Frame, area: Rect, data: &ReadData) {
+    let mut text = vec![Line::from(format!(
+        "{:.2} V   {:.1} A",
+        data.batt_v, data.current
+    ))];
+
+    if let Some(b) = &data.battery {
+        let style = match b.alert {
+            BatteryAlert::Normal => Style::new().green(),
+            BatteryAlert::Low => Style::new().yellow(),
+            BatteryAlert::Critical => Style::new().red().bold(),
+        };
+
+        match (b.cell_count, b.cell_v) {
+            (Some(cells), Some(cell_v)) => {
+                text.push(Line::from(format!("{}S   {:.2} V/cell", cells, cell_v)));
+            }
+            _ => text.push(Line::from("No battery connected")),
+        }
+        if let Some(pct) = b.remaining_pct {
+            text.push(Line::from(format!(
+                "{:.0}% left   {:.0} mAh used",
+                pct, b.mah_used
+            )));
+        }
+        text.push(Line::from(vec![
+            Span::raw(format!("{}  ", b.profile)),
+            Span::styled(format!("{:?}", b.alert), style),
+        ]));
+    }
+
+    f.render_widget(
+        Paragraph::new(text).block(Block::bordered().title(" Battery ")),
+        area,
+    );
+}
+
+fn draw_sticks(f: &mut Frame, area: Rect, data: &ReadData) {
+    let block = Block::bordered().title(" Sticks ");
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let c = &data.controls;
+    let rows = Layout::vertical([Constraint::Length(1); 6]).split(inner);
+
+    // Map -1. to 1. onto the gauge. Throttle may be either range; treat it the same.
+    for (i, (name, v)) in [
+        ("Roll", c.roll),
+        ("Pitch", c.pitch),
+        ("Throttle", c.throttle),
+        ("Yaw", c.yaw),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let gauge = LineGauge::default()
+            .label(format!("{:<8} {:5.2}", name, v))
+            .ratio(((v as f64 + 1.) / 2.).clamp(0., 1.))
+            .filled_style(Style::new().cyan());
+        f.render_widget(gauge, rows[i]);
+    }
+
+    let armed = match c.arm_status {
+        ArmStatus::Armed => Span::styled("ARMED", Style::new().red().bold()),
+        ArmStatus::Disarmed => Span::styled("Disarmed", Style::new().green()),
+    };
+    f.render_widget(Paragraph::new(Line::from(armed)), rows[4]);
+    f.render_widget(
+        Paragraph::new(format!(
+            "{:?}  alt hold {:?}  autopilot {:?}",
+            c.input_mode, c.alt_hold, c.autopilot
+        )),
+        rows[5],
+    );
+}
+
+fn draw_link(f: &mut Frame, area: Rect, data: &ReadData) {
+    let l = &data.link_stats;
+
+    let rf_mode = RfMode::try_from(l.rf_mode)
+        .map(|m| m.label().to_owned())
+        .unwrap_or_else(|_| format!("mode {}", l.rf_mode));
+    let tx_power = ElrsTxPower::try_from(l.uplink_tx_power)
+        .map(|p| format!("{}mW", p.mw()))
+        .unwrap_or_else(|_| format!("power {}", l.uplink_tx_power));
+
+    let lq_style = match l.uplink_link_quality {
+        0..=49 => Style::new().red(),
+        50..=79 => Style::new().yellow(),
+        _ => Style::new().green(),
+    };
+
+    let text = vec![
+        Line::from(vec![
+            Span::raw("Uplink LQ  "),
+            Span::styled(format!("{}%", l.uplink_link_quality), lq_style),
+        ]),
+        Line::from(format!(
+            "RSSI  {} / {} dBm   SNR {} dB",
+            l.uplink_rssi_1, l.uplink_rssi_2, l.uplink_snr
+        )),
+        Line::from(format!(
+            "Downlink LQ {}%   RSSI {} dBm",
+            l.downlink_link_quality, l.downlink_rssi
+        )),
+        Line::from(format!(
+            "{}   {}   antenna {}",
+            rf_mode, tx_power, l.active_antenna
+        )),
+    ];
+
+    f.render_widget(
+        Paragraph::new(text).block(Block::bordered().title(" Link ")),
+        area,
+    );
+}
+
+fn draw_waypoints(f: &mut Frame, area: Rect, data: &ReadData) {
+    let rows = data
+        .waypoints
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, wp)| wp.as_ref().map(|wp| (slot, wp)))
+        .map(|(slot, wp)| {
+            Row::new([
+                slot.to_string(),
+                wp.name.clone(),
+                format!("{:.1}", wp.x),
+                format!("{:.1}", wp.y),
+                format!("{:.1}", wp.z),
+            ])
+        });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(4),
+            Constraint::Length(8),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(Row::new(["Slot", "Name", "x", "y", "z"]).style(Style::new().bold().fg(Color::Gray)))
+    .block(Block::bordered().title(" Waypoints "));
+
+    f.render_widget(table, area);
+}
@@ -3,64 +3,179 @@
 pub const F32_BYTES: usize = 4;
 
 const CRC_POLY: u8 = 0xab;
-const CRC_LUT: [u8; 256] = crc_init(CRC_POLY);
+pub const CRC_LUT: [u8; 256] = crc_init(CRC_POLY);
 
 pub const QUATERNION_SIZE: usize = F32_BYTES * 4; // Quaternion (4x4 + altimeter + voltage reading + current reading)
-pub const PARAMS_SIZE: usize = QUATERNION_SIZE + F32_BYTES * 4 + 1; //
-pub const CONTROLS_SIZE: usize = 18;
-pub const LINK_STATS_SIZE: usize = 5; // Only the first 4 fields.
+pub const PARAMS_SIZE: usize = QUATERNION_SIZE + F32_BYTES * 4 + 1;
+// Sticks (4 f32s), switches (6 u8s), and raw aux channels.
+pub const CONTROLS_SIZE: usize = F32_BYTES * 4 + 6 + AUX_CHANNELS * 2;
+/// CRSF channels 5 - 16.
+pub const AUX_CHANNELS: usize = 12;
+pub const LINK_STATS_SIZE: usize = 10; // The full CRSF link statistics frame.
 
 pub const MAX_WAYPOINTS: usize = 30;
 pub const WAYPOINT_SIZE: usize = F32_BYTES * 3 + WAYPOINT_MAX_NAME_LEN + 1;
 pub const WAYPOINTS_SIZE: usize = MAX_WAYPOINTS * WAYPOINT_SIZE;
 pub const SET_SERVO_POSIT_SIZE: usize = 1 + F32_BYTES;
+// Servo position, min, center, max, and reversed flag.
+pub const SERVO_CAL_SIZE: usize = 1 + F32_BYTES * 3 + 1;
+// Left, and right servo PWM high times the FC is currently commanding.
+pub const SERVO_OUTPUTS_SIZE: usize = F32_BYTES * 2;
+// Stick axis, min, center, max, deadband, and reversed flag.
+pub const RC_CAL_SIZE: usize = 1 + F32_BYTES * 4 + 1;
+// Calibration kind, state, progress (%), gyro bias (3 f32s), and accelerometer bias (3 f32s).
+pub const IMU_CAL_STATUS_SIZE: usize = 3 + F32_BYTES * 6;
+// Raw magnetometer reading: x, y, z.
+pub const MAG_RAW_SIZE: usize = F32_BYTES * 3;
+// Hard-iron offset (3 f32s), then the soft-iron matrix (9 f32s, row-major).
+pub const MAG_CAL_SIZE: usize = F32_BYTES * 12;
+// Rate, then attitude gains (4 f32s each) for roll, pitch, and yaw, then 2 filter cutoffs.
+pub const PID_SIZE: usize = F32_BYTES * (3 * 2 * 4 + 2);
+// Parameters are addressed by a u16 index. Values are 4 bytes, interpreted per `ParamType`.
+pub const PARAM_NAME_LEN: usize = 16;
+pub const PARAM_VALUE_SIZE: usize = 4;
+pub const PARAM_COUNT_SIZE: usize = 2;
+// Index, type, name (null-padded ASCII), then min, max, and default values.
+pub const PARAM_INFO_SIZE: usize = 2 + 1 + PARAM_NAME_LEN + PARAM_VALUE_SIZE * 3;
+// Index, then value.
+pub const PARAM_VALUE_MSG_SIZE: usize = 2 + PARAM_VALUE_SIZE;
+// Packed bits: motors 1-4, R-L. True = CW.
+pub const MOTOR_DIRS_SIZE: usize = 1;
+// Gyro bias (3 f32s), then accelerometer bias (3 f32s).
+pub const IMU_BIAS_SIZE: usize = F32_BYTES * 6;
+// Aircraft type, and firmware build number (u32).
+pub const AIRCRAFT_INFO_SIZE: usize = 1 + 4;
+// Firmware version (3), protocol version (2), board ID (u16), and feature bits (u32).
+pub const FIRMWARE_INFO_SIZE: usize = 3 + 2 + 2 + 4;
 pub const WAYPOINT_MAX_NAME_LEN: usize = 7;
 
+/// Servo PWM high-time limits, in ms. Commands outside this range aren't sent to the FC.
+pub const SERVO_PWM_MIN: f32 = 0.5;
+pub const SERVO_PWM_MAX: f32 = 2.0;
+
 // Packet sizes are payload size + 2. Additional data are message type, and CRC.
-pub const PARAMS_PACKET_SIZE: usize = PARAMS_SIZE + 2;
-pub const CONTROLS_PACKET_SIZE: usize = CONTROLS_SIZE + 2;
-pub const LINK_STATS_PACKET_SIZE: usize = LINK_STATS_SIZE + 2;
 pub const WAYPOINTS_PACKET_SIZE: usize = WAYPOINTS_SIZE + 2;
-
-pub struct DecodeError {}
+pub const SERVO_CAL_PACKET_SIZE: usize = SERVO_CAL_SIZE + 2;
+pub const SERVO_OUTPUTS_PACKET_SIZE: usize = SERVO_OUTPUTS_SIZE + 2;
+pub const RC_CAL_PACKET_SIZE: usize = RC_CAL_SIZE + 2;
+pub const IMU_CAL_STATUS_PACKET_SIZE: usize = IMU_CAL_STATUS_SIZE + 2;
+pub const MAG_RAW_PACKET_SIZE: usize = MAG_RAW_SIZE + 2;
+pub const PID_PACKET_SIZE: usize = PID_SIZE + 2;
+pub const PARAM_COUNT_PACKET_SIZE: usize = PARAM_COUNT_SIZE + 2;
+pub const PARAM_INFO_PACKET_SIZE: usize = PARAM_INFO_SIZE + 2;
+pub const PARAM_VALUE_PACKET_SIZE: usize = PARAM_VALUE_MSG_SIZE + 2;
+pub const MOTOR_DIRS_PACKET_SIZE: usize = MOTOR_DIRS_SIZE + 2;
+pub const MAG_CAL_PACKET_SIZE: usize = MAG_CAL_SIZE + 2;
+pub const AIRCRAFT_INFO_PACKET_SIZE: usize = AIRCRAFT_INFO_SIZE + 2;
+pub const FIRMWARE_INFO_PACKET_SIZE: usize = FIRMWARE_INFO_SIZE + 2;
+
+/// The version of the USB protocol described by this module. Bump `major` on any change to an
+/// existing message layout, and `minor` when adding messages. The `ReqFirmwareInfo` and
+/// `FirmwareInfo` messages must never change, so we can always negotiate.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 3, minor: 6 };
+
+// Feature bits reported in `FirmwareInfo`.
+pub const FEATURE_GPS: u32 = 1 << 0;
+pub const FEATURE_TOF_ALTIMETER: u32 = 1 << 1;
+pub const FEATURE_COMPASS: u32 = 1 << 2;
+pub const FEATURE_SERVO_OUTPUTS: u32 = 1 << 3;
+/// The FC stores stick calibration.
+pub const FEATURE_RC_CAL: u32 = 1 << 4;
+/// The FC can calibrate gyro bias, and accelerometer level, on command. (Protocol v3.1)
+pub const FEATURE_IMU_CAL: u32 = 1 << 5;
+/// The FC streams raw magnetometer readings, and accepts a compass calibration. (Protocol v3.2)
+pub const FEATURE_MAG_CAL: u32 = 1 << 6;
+/// The FC reports, and accepts PID gains. (Protocol v3.3)
+pub const FEATURE_PID: u32 = 1 << 7;
+/// The FC has a parameter table. (Protocol v3.4)
+pub const FEATURE_PARAMS: u32 = 1 << 8;
+/// The FC reports its motor directions, and compass calibration, and accepts IMU biases, and a
+/// full waypoint table, so its whole configuration can be backed up and restored. (Protocol v3.5)
+pub const FEATURE_CONFIG_BACKUP: u32 = 1 << 9;
+/// The FC reboots into the STM32 system bootloader on command, for firmware updates. (Protocol
+/// v3.6)
+pub const FEATURE_BOOTLOADER_REBOOT: u32 = 1 << 10;
+
+pub const FEATURE_NAMES: [(u32, &str); 11] = [
+    (FEATURE_GPS, "gps"),
+    (FEATURE_TOF_ALTIMETER, "tof_altimeter"),
+    (FEATURE_COMPASS, "compass"),
+    (FEATURE_SERVO_OUTPUTS, "servo_outputs"),
+    (FEATURE_RC_CAL, "rc_cal"),
+    (FEATURE_IMU_CAL, "imu_cal"),
+    (FEATURE_MAG_CAL, "mag_cal"),
+    (FEATURE_PID, "pid"),
+    (FEATURE_PARAMS, "params"),
+    (FEATURE_CONFIG_BACKUP, "config_backup"),
+    (FEATURE_BOOTLOADER_REBOOT, "bootloader_reboot"),
+];
 
 // Time between querying the FC for readings, in ms.
 pub const REFRESH_INTERVAL: u32 = 50;
 
 use num_enum::TryFromPrimitive; // Enum from integer
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize, Serializer};
 
 // Note that serialize, and for ArmStatus, default, are not part of the firmware
 
-#[derive(Clone, Copy, PartialEq, Serialize, TryFromPrimitive)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, TryFromPrimitive)]
 #[repr(u8)]
 pub enum InputModeSwitch {
     /// Acro mode
+    #[default]
     Acro = 0,
     /// Command if GPS is present; Attitude if not
     AttitudeCommand = 1,
 }
 
-impl Default for InputModeSwitch {
-    fn default() -> Self {
-        Self::Acro
-    }
-}
-
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Serialize, TryFromPrimitive)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, TryFromPrimitive)]
 pub enum ArmStatus {
     /// Motors are [pre]disarmed
+    #[default]
     Disarmed = 0,
     /// Motors are [pre]armed
     Armed = 1,
 }
 
-impl Default for ArmStatus {
-    fn default() -> Self {
-        Self::Disarmed
-    }
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, TryFromPrimitive)]
+#[repr(u8)]
+pub enum AltHoldSwitch {
+    #[default]
+    Disabled = 0,
+    /// Hold altitude MSL, using the barometer.
+    EnabledMsl = 1,
+    /// Hold altitude AGL, using the time-of-flight sensor.
+    EnabledAgl = 2,
+}
+
+/// Level the aircraft when the pilot lets go of the sticks.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, TryFromPrimitive)]
+#[repr(u8)]
+pub enum AutoRecoverSwitch {
+    #[default]
+    Disabled = 0,
+    Enabled = 1,
+}
+
+/// Autonomous flight modes.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, TryFromPrimitive)]
+#[repr(u8)]
+pub enum AutopilotSwitch {
+    #[default]
+    Disabled = 0,
+    Takeoff = 1,
+    Land = 2,
+    ReturnToBase = 3,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, TryFromPrimitive)]
+#[repr(u8)]
+pub enum ObstacleAvoidSwitch {
+    #[default]
+    Disabled = 0,
+    Enabled = 1,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
@@ -82,13 +197,54 @@ pub enum MsgType {
     Updatewaypoints = 13,
     Waypoints = 14,
     SetServoPosit = 15,
+    ReqServoCal = 16,
+    ServoCal = 17,
+    SetServoCal = 18,
+    ReqServoOutputs = 19,
+    ServoOutputs = 20,
+    ReqAircraftInfo = 21,
+    AircraftInfo = 22,
+    ReqFirmwareInfo = 23,
+    FirmwareInfo = 24,
+    ReqRcCal = 25,
+    RcCal = 26,
+    SetRcCal = 27,
+    StartImuCal = 28,
+    ReqImuCalStatus = 29,
+    ImuCalStatus = 30,
+    ReqMagRaw = 31,
+    MagRaw = 32,
+    SetMagCal = 33,
+    ReqPid = 34,
+    Pid = 35,
+    SetPid = 36,
+    ReqParamCount = 37,
+    ParamCount = 38,
+    ReqParamInfo = 39,
+    ParamInfo = 40,
+    ReqParamValue = 41,
+    ParamValue = 42,
+    /// The FC replies with `ParamValue`, holding the value it applied.
+    SetParamValue = 43,
+    ReqMotorDirs = 44,
+    MotorDirs = 45,
+    ReqMagCal = 46,
+    MagCal = 47,
+    SetImuBias = 48,
+    /// Replaces the whole waypoint table. Same layout as `Waypoints`.
+    SetWaypoints = 49,
+    /// The FC resets into the system bootloader, and stops responding over serial.
+    RebootToBootloader = 50,
 }
 
 impl MsgType {
+    /// Mirrors the firmware's table. We frame messages from the payload we're given, so only the
+    /// tests use this for now.
+    #[allow(dead_code)]
     pub fn payload_size(&self) -> usize {
         match self {
             Self::Params => PARAMS_SIZE,
-            Self::SetMotorDirs => 1, // Packed bits: motors 1-4, R-L. True = CW.
+            Self::SetMotorDirs => MOTOR_DIRS_SIZE,
             Self::ReqParams => 0,
             Self::Ack => 0,
             Self::Controls => CONTROLS_SIZE,
@@ -103,6 +259,41 @@ impl MsgType {
             Self::Updatewaypoints => 10, // todo?
             Self::Waypoints => WAYPOINTS_SIZE,
             Self::SetServoPosit => SET_SERVO_POSIT_SIZE,
+            Self::ReqServoCal => 1, // Servo position.
+            Self::ServoCal => SERVO_CAL_SIZE,
+            Self::SetServoCal => SERVO_CAL_SIZE,
+            Self::ReqServoOutputs => 0,
+            Self::ServoOutputs => SERVO_OUTPUTS_SIZE,
+            Self::ReqAircraftInfo => 0,
+            Self::AircraftInfo => AIRCRAFT_INFO_SIZE,
+            Self::ReqFirmwareInfo => 0,
+            Self::FirmwareInfo => FIRMWARE_INFO_SIZE,
+            Self::ReqRcCal => 1, // Stick axis.
+            Self::RcCal => RC_CAL_SIZE,
+            Self::SetRcCal => RC_CAL_SIZE,
+            Self::StartImuCal => 1, // Calibration kind.
+            Self::ReqImuCalStatus => 0,
+            Self::ImuCalStatus => IMU_CAL_STATUS_SIZE,
+            Self::ReqMagRaw => 0,
+            Self::MagRaw => MAG_RAW_SIZE,
+            Self::SetMagCal => MAG_CAL_SIZE,
+            Self::ReqPid => 0,
+            Self::Pid => PID_SIZE,
+            Self::SetPid => PID_SIZE,
+            Self::ReqParamCount => 0,
+            Self::ParamCount => PARAM_COUNT_SIZE,
+            Self::ReqParamInfo => 2, // Param index.
+            Self::ParamInfo => PARAM_INFO_SIZE,
+            Self::ReqParamValue => 2, // Param index.
+            Self::ParamValue => PARAM_VALUE_MSG_SIZE,
+            Self::SetParamValue => PARAM_VALUE_MSG_SIZE,
+            Self::ReqMotorDirs => 0,
+            Self::MotorDirs => MOTOR_DIRS_SIZE,
+            Self::ReqMagCal => 0,
+            Self::MagCal => MAG_CAL_SIZE,
+            Self::SetImuBias => IMU_BIAS_SIZE,
+            Self::SetWaypoints => WAYPOINTS_SIZE,
+            Self::RebootToBootloader => 0,
         }
     }
 }
@@ -119,8 +310,13 @@ pub struct ChannelData {
     pub yaw: f32,
     pub arm_status: ArmStatus,
     pub input_mode: InputModeSwitch,
-    // pub alt_hold: AltHoldSwitch, // todo
-    // todo: Auto-recover commanded, auto-TO/land/RTB, obstacle avoidance etc.
+    pub alt_hold: AltHoldSwitch,
+    pub auto_recover: AutoRecoverSwitch,
+    pub autopilot: AutopilotSwitch,
+    pub obstacle_avoid: ObstacleAvoidSwitch,
+    /// Raw values of CRSF channels 5 - 16, 172 to 1811. Includes the channels the switches above
+    /// are decoded from.
+    pub aux: [u16; AUX_CHANNELS],
 }
 
 // #[derive(Default, Serialize, Clone)]
@@ -158,15 +354,6 @@ pub struct ChannelData {
 //     pub a_yaw: f32,
 // }
 
-#[derive(Clone, Copy)]
-#[repr(u8)]
-pub enum Rotor {
-    R1 = 0,
-    R2 = 1,
-    R3 = 2,
-    R4 = 3,
-}
-
 #[derive(Clone, Copy, Debug)]
 #[repr(u8)]
 pub enum RotorPosition {
@@ -176,19 +363,199 @@ pub enum RotorPosition {
     AftRight = 3,
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum ServoWing {
-    S1,
-    S2,
-}
-
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, TryFromPrimitive)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum ServoWingPosition {
     Left = 0,
     Right = 1,
 }
 
+/// Endpoint calibration for a single servo. Pulse widths are PWM high times, in ms.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServoCalibration {
+    pub min: f32,
+    pub center: f32,
+    pub max: f32,
+    /// Reverses the servo's direction of travel.
+    pub reversed: bool,
+}
+
+impl Default for ServoCalibration {
+    fn default() -> Self {
+        Self {
+            min: 1.,
+            center: 1.5,
+            max: 2.,
+            reversed: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, TryFromPrimitive)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum RcAxis {
+    Roll = 0,
+    Pitch = 1,
+    Yaw = 2,
+    Throttle = 3,
+}
+
+/// Stick calibration for a single axis, in the raw units the receiver reports in `ChannelData`.
+/// The FC maps `min` and `max` to full deflection, and `center` to 0. For throttle, `center` is
+/// idle.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StickCalibration {
+    pub min: f32,
+    pub center: f32,
+    pub max: f32,
+    /// Deflections from center smaller than this are treated as 0.
+    pub deadband: f32,
+    pub reversed: bool,
+}
+
+impl Default for StickCalibration {
+    fn default() -> Self {
+        Self {
+            min: -1.,
+            center: 0.,
+            max: 1.,
+            deadband: 0.,
+            reversed: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, TryFromPrimitive)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum ImuCalKind {
+    None = 0,
+    /// Gyro bias. The aircraft must be still.
+    Gyro = 1,
+    /// Accelerometer level. The aircraft must be on flat ground.
+    Level = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TryFromPrimitive)]
+#[repr(u8)]
+pub enum ImuCalState {
+    Idle = 0,
+    Running = 1,
+    Done = 2,
+    /// Eg the aircraft moved during calibration.
+    Failed = 3,
+}
+
+/// The FC's report on the calibration in progress, or the latest one.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ImuCalStatus {
+    pub kind: ImuCalKind,
+    pub state: ImuCalState,
+    /// 0 to 100.
+    pub progress: u8,
+    /// Gyro bias, in rad/s, for the x, y, and z axes.
+    pub gyro_bias: [f32; 3],
+    /// Accelerometer bias, in m/s^2, for the x, y, and z axes.
+    pub accel_bias: [f32; 3],
+}
+
+/// Gyro and accelerometer biases, as found by an IMU calibration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImuBias {
+    /// rad/s, for the x, y, and z axes.
+    pub gyro: [f32; 3],
+    /// m/s^2, for the x, y, and z axes.
+    pub accel: [f32; 3],
+}
+
+/// The compass correction the FC applies to raw magnetometer readings.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MagCalibration {
+    /// Offset to subtract from raw readings.
+    pub hard_iron: [f32; 3],
+    /// Matrix applied after removing the hard-iron offset, row-major.
+    pub soft_iron: [[f32; 3]; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RotorDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Spin direction of each rotor, as packed in `SetMotorDirs`, and `MotorDirs`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MotorDirections {
+    pub front_left: RotorDirection,
+    pub front_right: RotorDirection,
+    pub aft_left: RotorDirection,
+    pub aft_right: RotorDirection,
+}
+
+/// Gains for one PID loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Feed-forward, from the setpoint.
+    pub kff: f32,
+}
+
+/// The inner (rate), and outer (attitude) loops for one axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AxisPid {
+    pub rate: PidGains,
+    pub attitude: PidGains,
+}
+
+/// Filter cutoffs come first, since TOML requires plain values before tables. On the wire, they
+/// come last; see `PID_SIZE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PidConfig {
+    /// Gyro low-pass filter cutoff, in Hz.
+    pub gyro_lpf_hz: f32,
+    /// D-term low-pass filter cutoff, in Hz.
+    pub d_term_lpf_hz: f32,
+    pub roll: AxisPid,
+    pub pitch: AxisPid,
+    pub yaw: AxisPid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TryFromPrimitive)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum ParamType {
+    Bool = 0,
+    U8 = 1,
+    U32 = 2,
+    I32 = 3,
+    F32 = 4,
+}
+
+/// A parameter value. (Not part of the firmware, which sends the raw 4 bytes)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+}
+
+/// A parameter's description, as reported by the FC.
+#[derive(Clone, Debug, Serialize)]
+pub struct ParamInfo {
+    pub index: u16,
+    pub name: String,
+    pub param_type: ParamType,
+    pub min: ParamValue,
+    pub max: ParamValue,
+    pub default: ParamValue,
+}
+
 pub const fn crc_init(poly: u8) -> [u8; 256] {
     let mut lut = [0; 256];
 
@@ -210,18 +577,23 @@ pub const fn crc_init(poly: u8) -> [u8; 256] {
     lut
 }
 
-/// CRC8 using a specific poly, includes all bytes from type (buffer[2]) to end of payload.
+/// CRC8 using a specific poly, over all of `data`: from the message type to the end of the
+/// payload. Messages may be longer than 255 bytes, eg waypoints.
 /// https://github.com/chris1seto/OzarkRiver/blob/4channel/FlightComputerFirmware/Src/Crsf.c
-pub fn calc_crc(lut: &[u8; 256], data: &[u8], mut size: u8) -> u8 {
-    let mut crc = 0;
-    let mut i = 0;
+pub fn calc_crc(lut: &[u8; 256], data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, b| lut[(crc ^ b) as usize])
+}
 
-    while size > 0 {
-        size -= 1;
-        crc = lut[(crc ^ data[i]) as usize];
-        i += 1;
-    }
-    crc
+/// A message to the FC: its type, the payload, then a CRC over both.
+pub fn frame_msg(msg_type: MsgType, payload: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(payload.len() + 2);
+    result.push(msg_type as u8);
+    result.extend_from_slice(payload);
+
+    let crc = calc_crc(&CRC_LUT, &result);
+    result.push(crc);
+
+    result
 }
 
 #[derive(Clone, Copy, Default, Serialize)]
@@ -232,23 +604,56 @@ pub struct Quaternion {
     pub z: f32,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, TryFromPrimitive)]
+#[repr(u8)]
 pub enum AircraftType {
-    Quadcopter,
-    FlyingWing,
+    Quadcopter = 0,
+    FlyingWing = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+/// Reported by the FC during the connection handshake.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct FirmwareInfo {
+    pub firmware_version: FirmwareVersion,
+    pub protocol_version: ProtocolVersion,
+    pub board_id: u16,
+    /// See `FEATURE_NAMES`.
+    pub features: u32,
+}
+
+/// Airframe, and firmware build reported by the FC. (Not part of the firmware)
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct AircraftInfo {
+    pub aircraft_type: AircraftType,
+    pub firmware_build: u32,
 }
 
 #[derive(Clone, Default, Serialize)]
 /// https://www.expresslrs.org/2.0/faq/#how-many-channels-does-elrs-support
+/// Unlike the firmware, we store RSSI as signed dBm; CRSF sends it as a positive number of -dBm.
 pub struct LinkStats {
-    /// Timestamp these stats were recorded. (TBD format; processed locally; not part of packet from tx).
-    pub timestamp: u32,
+    /// Timestamp these stats were received, in ms since the UNIX epoch. (Processed locally; not
+    /// part of packet from tx).
+    pub timestamp: i64,
     /// Uplink - received signal strength antenna 1 (RSSI). RSSI dBm as reported by the RX. Values
     /// vary depending on mode, antenna quality, output power and distance. Ranges from -128 to 0.
-    pub uplink_rssi_1: u8,
-    /// Uplink - received signal strength antenna 2 (RSSI).  	Second antenna RSSI, used in diversity mode
+    pub uplink_rssi_1: i16,
+    /// Uplink - received signal strength antenna 2 (RSSI). Second antenna RSSI, used in diversity mode
     /// (Same range as rssi_1)
-    pub uplink_rssi_2: u8,
+    pub uplink_rssi_2: i16,
     /// Uplink - link quality (valid packets). The number of successful packets out of the last
     /// 100 from TX → RX
     pub uplink_link_quality: u8,
@@ -257,19 +662,167 @@ pub struct LinkStats {
     pub uplink_snr: i8,
     /// Active antenna for diversity RX (0 - 1)
     pub active_antenna: u8,
+    /// See `RfMode`. Serialized with its label, and packet rate.
+    #[serde(serialize_with = "serialize_rf_mode")]
     pub rf_mode: u8,
-    /// Uplink - transmitting power. (mW?) 50mW reported as 0, as CRSF/OpenTX do not have this option
+    /// Uplink - transmitting power. See `ElrsTxPower`. Serialized with its label, and mW.
+    #[serde(serialize_with = "serialize_tx_power")]
     pub uplink_tx_power: u8,
     /// Downlink - received signal strength (RSSI). RSSI dBm of telemetry packets received by TX.
-    pub downlink_rssi: u8,
+    pub downlink_rssi: i16,
     /// Downlink - link quality (valid packets). An LQ indicator of telemetry packets received RX → TX
     /// (0 - 100)
     pub downlink_link_quality: u8,
-    /// Downlink - signal-to-noise ratio. 	SNR reported by the TX for telemetry packets
+    /// Downlink - signal-to-noise ratio. SNR reported by the TX for telemetry packets
     pub downlink_snr: i8,
 }
 
-#[derive(Default, Clone, Serialize)]
+/// CRSF uplink tx power codes, as reported by ELRS.
+#[derive(Clone, Copy, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum ElrsTxPower {
+    Mw0 = 0,
+    Mw10 = 1,
+    Mw25 = 2,
+    Mw100 = 3,
+    Mw500 = 4,
+    Mw1000 = 5,
+    Mw2000 = 6,
+    Mw250 = 7,
+    Mw50 = 8,
+}
+
+impl ElrsTxPower {
+    pub fn mw(&self) -> u16 {
+        match self {
+            Self::Mw0 => 0,
+            Self::Mw10 => 10,
+            Self::Mw25 => 25,
+            Self::Mw50 => 50,
+            Self::Mw100 => 100,
+            Self::Mw250 => 250,
+            Self::Mw500 => 500,
+            Self::Mw1000 => 1_000,
+            Self::Mw2000 => 2_000,
+        }
+    }
+}
+
+/// ELRS packet rates, in the order of `expresslrs_RFrates_e`, as reported in the link statistics
+/// `rf_mode` field.
+#[derive(Clone, Copy, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum RfMode {
+    Lora4Hz = 0,
+    Lora25Hz = 1,
+    Lora50Hz = 2,
+    Lora100Hz = 3,
+    Lora100HzFull = 4,
+    Lora150Hz = 5,
+    Lora200Hz = 6,
+    Lora250Hz = 7,
+    Lora333HzFull = 8,
+    Lora500Hz = 9,
+    Dvda250Hz = 10,
+    Dvda500Hz = 11,
+    Flrc500Hz = 12,
+    Flrc1000Hz = 13,
+}
+
+impl RfMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Lora4Hz => "4Hz",
+            Self::Lora25Hz => "25Hz",
+            Self::Lora50Hz => "50Hz",
+            Self::Lora100Hz => "100Hz",
+            Self::Lora100HzFull => "100Hz Full",
+            Self::Lora150Hz => "150Hz",
+            Self::Lora200Hz => "200Hz",
+            Self::Lora250Hz => "250Hz",
+            Self::Lora333HzFull => "333Hz Full",
+            Self::Lora500Hz => "500Hz",
+            Self::Dvda250Hz => "D250",
+            Self::Dvda500Hz => "D500",
+            Self::Flrc500Hz => "F500",
+            Self::Flrc1000Hz => "F1000",
+        }
+    }
+
+    /// Packets per second. For the DVDA modes, this is the over-the-air rate; each packet is
+    /// sent twice.
+    pub fn packet_rate(&self) -> u16 {
+        match self {
+            Self::Lora4Hz => 4,
+            Self::Lora25Hz => 25,
+            Self::Lora50Hz => 50,
+            Self::Lora100Hz | Self::Lora100HzFull => 100,
+            Self::Lora150Hz => 150,
+            Self::Lora200Hz => 200,
+            Self::Lora250Hz | Self::Dvda250Hz => 250,
+            Self::Lora333HzFull => 333,
+            Self::Lora500Hz | Self::Dvda500Hz | Self::Flrc500Hz => 500,
+            Self::Flrc1000Hz => 1_000,
+        }
+    }
+}
+
+// Serialized forms of coded link statistics fields (Not part of the firmware). Unknown codes
+// serialize with an `error`, so clients don't have to guess.
+
+#[derive(Serialize)]
+struct TxPowerJson {
+    code: u8,
+    label: String,
+    mw: u16,
+}
+
+#[derive(Serialize)]
+struct RfModeJson {
+    code: u8,
+    label: &'static str,
+    packet_rate_hz: u16,
+}
+
+#[derive(Serialize)]
+struct UnknownCodeJson {
+    code: u8,
+    error: String,
+}
+
+fn serialize_tx_power<S: Serializer>(code: &u8, s: S) -> Result<S::Ok, S::Error> {
+    match ElrsTxPower::try_from(*code) {
+        Ok(p) => TxPowerJson {
+            code: *code,
+            label: format!("{}mW", p.mw()),
+            mw: p.mw(),
+        }
+        .serialize(s),
+        Err(_) => UnknownCodeJson {
+            code: *code,
+            error: format!("Unknown ELRS tx power code: {}", code),
+        }
+        .serialize(s),
+    }
+}
+
+fn serialize_rf_mode<S: Serializer>(code: &u8, s: S) -> Result<S::Ok, S::Error> {
+    match RfMode::try_from(*code) {
+        Ok(m) => RfModeJson {
+            code: *code,
+            label: m.label(),
+            packet_rate_hz: m.packet_rate(),
+        }
+        .serialize(s),
+        Err(_) => UnknownCodeJson {
+            code: *code,
+            error: format!("Unknown ELRS RF mode: {}", code),
+        }
+        .serialize(s),
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Location {
     // Note: unlike Location in the main program, we ommit location type, and use String for name.
     pub name: String,
@@ -277,3 +830,19 @@ pub struct Location {
     pub y: f32,
     pub z: f32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_start_motor() {
+        let msg = frame_msg(MsgType::StartMotor, &[RotorPosition::AftLeft as u8]);
+
+        assert_eq!(msg.len(), MsgType::StartMotor.payload_size() + 2);
+        assert_eq!(msg[..2], [10, 2]);
+        // The CRC covers the motor, not just the message type.
+        assert_eq!(msg[2], calc_crc(&CRC_LUT, &[10, 2]));
+        assert_ne!(msg[2], calc_crc(&CRC_LUT, &[10]));
+    }
+}
@@ -0,0 +1,84 @@
+//! Protocol version negotiation with the FC. Our message layouts in `types` are copied from the
+//! firmware, so a mismatch silently produces garbage readings; we check before talking.
+
+use serde::Serialize;
+
+use crate::types::{FirmwareInfo, ProtocolVersion, FEATURE_NAMES, PROTOCOL_VERSION};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Compatibility {
+    /// Protocol versions match.
+    Compatible,
+    /// Major versions match, but one side has messages the other doesn't. Readings are valid;
+    /// some features may be missing.
+    Degraded(String),
+    /// Message layouts differ. We refuse to talk to the FC.
+    Incompatible(String),
+}
+
+/// The result of the handshake, as reported by the API and on startup.
+#[derive(Clone, Debug, Serialize)]
+pub struct Handshake {
+    pub firmware: FirmwareInfo,
+    /// The protocol version we speak.
+    pub preflight_protocol: ProtocolVersion,
+    /// The protocol version in use: The lower of ours, and the FC's.
+    pub negotiated_protocol: ProtocolVersion,
+    pub features: Vec<&'static str>,
+    pub compatibility: Compatibility,
+}
+
+impl Handshake {
+    pub fn new(firmware: FirmwareInfo) -> Self {
+        let fw = firmware.protocol_version;
+        let ours = PROTOCOL_VERSION;
+
+        let compatibility = if fw.major != ours.major {
+            Compatibility::Incompatible(format!(
+                "The FC speaks protocol v{}.{}, and Preflight speaks v{}.{}. Update {}.",
+                fw.major,
+                fw.minor,
+                ours.major,
+                ours.minor,
+                if fw.major < ours.major {
+                    "the firmware"
+                } else {
+                    "Preflight"
+                }
+            ))
+        } else if fw.minor < ours.minor {
+            Compatibility::Degraded(format!(
+                "The FC firmware (protocol v{}.{}) predates some Preflight features. Update the \
+                firmware to use them.",
+                fw.major, fw.minor
+            ))
+        } else if fw.minor > ours.minor {
+            Compatibility::Degraded(format!(
+                "The FC firmware (protocol v{}.{}) has features this version of Preflight doesn't \
+                support. Update Preflight to use them.",
+                fw.major, fw.minor
+            ))
+        } else {
+            Compatibility::Compatible
+        };
+
+        Self {
+            firmware,
+            preflight_protocol: ours,
+            negotiated_protocol: ProtocolVersion {
+                major: ours.major,
+                minor: ours.minor.min(fw.minor),
+            },
+            features: FEATURE_NAMES
+                .iter()
+                .filter(|(bit, _)| firmware.features & bit != 0)
+                .map(|(_, name)| *name)
+                .collect(),
+            compatibility,
+        }
+    }
+
+    pub fn has_feature(&self, feature: u32) -> bool {
+        self.firmware.features & feature != 0
+    }
+}
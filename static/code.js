@@ -44,28 +44,13 @@ function update_readings() {
             ATTITUDE_QUAT.y = r.attitude_quat.y
             ATTITUDE_QUAT.z = r.attitude_quat.z
 
-            let txPwrText
-            // See firmware: `ElrsTxPower`.
-            switch(r.link_stats.uplink_tx_power) {
-                case 1:
-                    txPwrText = "10mW"
-                    break;
-                case 2:
-                    txPwrText = "25mW"
-                    break;
-                case 8:
-                    txPwrText = "50mW"
-                    break;
-                case 3:
-                    txPwrText = "100mW"
-                    break;
-                case 7:
-                    txPwrText = "250mW"
-                    break;
-                default:
-                    txPwrText = "(unknown)"
-                    break;
-            }
+            // Codes are decoded on the server; unknown ones come back with an `error`.
+            let txPwr = r.link_stats.uplink_tx_power
+            let txPwrText = txPwr.error === undefined ? txPwr.label : "(" + txPwr.error + ")"
+
+            let rfMode = r.link_stats.rf_mode
+            let rfModeText = rfMode.error === undefined ? rfMode.label : "(" + rfMode.error + ")"
+
 
             document.getElementById("altimeter-reading").textContent = format(r.altimeter, 0)
             document.getElementById("altimeter-agl-reading").textContent = format(r.altimeter_agl, 0)
@@ -73,6 +58,18 @@ function update_readings() {
             document.getElementById("voltage-reading").textContent = format(r.batt_v, 1)
             document.getElementById("current-reading").textContent = format(r.current, 1)
 
+            if (r.battery !== null && r.battery.cell_v !== null) {
+                let cellEl = document.getElementById("cell-voltage-reading")
+                cellEl.textContent = format(r.battery.cell_v, 2) + "V (" + r.battery.cell_count + "S)"
+                cellEl.style.color = {Normal: "#222222", Low: "#cc8800", Critical: "#cc2222"}[r.battery.alert]
+
+                document.getElementById("mah-used-reading").textContent = format(r.battery.mah_used, 0)
+                document.getElementById("remaining-reading").textContent =
+                    r.battery.remaining_pct === null ? "(unknown)" : format(r.battery.remaining_pct, 0) + "%"
+            } else {
+                document.getElementById("cell-voltage-reading").textContent = "(no battery)"
+            }
+
             document.getElementById("control-roll-reading").textContent = format(r.controls.roll, 2)
             document.getElementById("control-pitch-reading").textContent = format(r.controls.pitch, 2)
             document.getElementById("control-yaw-reading").textContent = format(r.controls.yaw, 2)
@@ -80,13 +77,921 @@ function update_readings() {
 
             document.getElementById("control-arm-reading").textContent = r.controls.arm_status
             document.getElementById("control-mode-reading").textContent = r.controls.input_mode
+            document.getElementById("control-alt-hold-reading").textContent = r.controls.alt_hold
+            document.getElementById("control-auto-recover-reading").textContent = r.controls.auto_recover
+            document.getElementById("control-autopilot-reading").textContent = r.controls.autopilot
+            document.getElementById("control-obstacle-avoid-reading").textContent = r.controls.obstacle_avoid
+            document.getElementById("control-aux-reading").textContent = r.controls.aux.join(" ")
 
-            document.getElementById("rssi-1-reading").textContent = "-" + r.link_stats.uplink_rssi_1 + "dB"
-            document.getElementById("rssi-2-reading").textContent = "-" + r.link_stats.uplink_rssi_2 + "dB"
+            document.getElementById("rssi-1-reading").textContent = r.link_stats.uplink_rssi_1 + "dBm"
+            document.getElementById("rssi-2-reading").textContent = r.link_stats.uplink_rssi_2 + "dBm"
             document.getElementById("link-quality-reading").textContent = r.link_stats.uplink_link_quality + "%"
             document.getElementById("snr-reading").textContent = r.link_stats.uplink_snr
+            document.getElementById("active-antenna-reading").textContent = r.link_stats.active_antenna + 1
+
+            document.getElementById("downlink-rssi-reading").textContent = r.link_stats.downlink_rssi + "dBm"
+            document.getElementById("downlink-link-quality-reading").textContent = r.link_stats.downlink_link_quality + "%"
+            document.getElementById("downlink-snr-reading").textContent = r.link_stats.downlink_snr
             document.getElementById("tx-power-reading").textContent = txPwrText
+            document.getElementById("rf-mode-reading").textContent = rfModeText
+
+        })
+}
+
+function updateElevonPreview() {
+    // Expected and commanded elevon positions, for flying wings.
+    fetch("/api/elevon_preview", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (!response.ok) {
+                return null
+            }
+            return response.json()
+        })
+        .then(r => {
+            if (r === null) {
+                return
+            }
+
+            for (const side of ["left", "right"]) {
+                let e = r[side]
+                document.getElementById("elevon-" + side + "-expected").textContent = format(e.expected_pulse, 2)
+                document.getElementById("elevon-" + side + "-commanded").textContent =
+                    e.commanded_pulse === null ? "(unknown)" : format(e.commanded_pulse, 2)
+
+                let dirEl = document.getElementById("elevon-" + side + "-direction")
+                dirEl.textContent = e.direction
+                dirEl.style.color = e.direction === "Reversed" || e.direction === "NoResponse" ? "#cc2222" : "#222222"
+            }
+        })
+}
+
+function loadBatteryProfiles() {
+    fetch("/api/battery/profiles", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            let select = document.getElementById("battery-profile")
+            select.innerHTML = ""
+
+            for (const profile of r.profiles) {
+                let option = document.createElement("option")
+                option.value = profile.name
+                option.textContent = profile.name + " (" + profile.chemistry + ", " + profile.capacity_mah + "mAh)"
+                option.selected = profile.name === r.active
+                select.appendChild(option)
+            }
+        })
+}
+
+function setBatteryProfile() {
+    // Selecting a profile resets capacity tracking, eg when plugging in a fresh pack.
+    fetch("/api/battery/profile", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+        body: document.getElementById("battery-profile").value
+    })
+}
+
+function startRecording() {
+    fetch("/api/record/start", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.text())
+        .then(r => {
+            document.getElementById("recording-status").textContent = "Recording to " + r
+        })
+}
+
+function stopRecording() {
+    fetch("/api/record/stop", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.text())
+        .then(r => {
+            document.getElementById("recording-status").textContent = "Saved " + r
+            loadRecordings()
+        })
+}
+
+function loadRecordings() {
+    // Add saved recordings as export sources, after the live history.
+    fetch("/api/recordings", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            let select = document.getElementById("export-source")
+            while (select.options.length > 1) {
+                select.remove(1)
+            }
+
+            for (const name of r) {
+                let option = document.createElement("option")
+                option.value = name
+                option.textContent = name
+                select.appendChild(option)
+            }
+        })
+}
+
+function updateExportLinks() {
+    let source = encodeURIComponent(document.getElementById("export-source").value)
+
+    document.getElementById("export-csv").href = "/api/export?source=" + source + "&format=csv"
+    document.getElementById("export-parquet").href = "/api/export?source=" + source + "&format=parquet"
+}
+
+function runPreflight() {
+    // Run the server's preflight checklist against the latest readings.
+    fetch("/api/preflight", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            const colors = {Pass: "#228822", Warn: "#cc8800", Fail: "#cc2222"}
+
+            let statusEl = document.getElementById("preflight-status")
+            statusEl.textContent = r.go ? "GO" : "NO GO"
+            statusEl.style.color = colors[r.status]
+
+            let resultsEl = document.getElementById("preflight-results")
+            resultsEl.innerHTML = ""
+
+            for (const check of r.results) {
+                let el = document.createElement("h3")
+                el.textContent = check.status + " - " + check.name + ": " + check.message
+                el.style.color = colors[check.status]
+                resultsEl.appendChild(el)
+            }
+        })
+}
+
+function linkSummaryText(s) {
+    if (s.num_samples === 0) {
+        return "No readings"
+    }
+
+    let rssi = [s.rssi_1_min, s.rssi_2_min].map(r => r === null ? "-" : r + "dBm").join(" / ")
+
+    return "LQ min " + s.lq_min + "%, mean " + format(s.lq_mean, 0) + "%. Worst RSSI " + rssi +
+        ". " + s.dropouts + " dropouts (longest " + format(s.longest_dropout_ms / 1_000., 1) +
+        "s), " + s.failsafes + " failsafes." +
+        (s.first_dropout_rssi === null ? "" : " First dropout at " + s.first_dropout_rssi + "dBm.")
+}
+
+function updateLinkReport() {
+    fetch("/api/link", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            let statusEl = document.getElementById("link-status")
+            if (r.stale) {
+                statusEl.textContent = "Stale link data" +
+                    (r.age_ms === null ? "" : " (" + format(r.age_ms / 1_000., 1) + "s old)")
+                statusEl.style.color = "#cc8800"
+            } else if (r.failsafe) {
+                statusEl.textContent = "FAILSAFE: The receiver reports no link"
+                statusEl.style.color = "#cc2222"
+            } else {
+                statusEl.textContent = "Link OK"
+                statusEl.style.color = "#228822"
+            }
+
+            document.getElementById("link-session").textContent = "Session: " + linkSummaryText(r.session)
+
+            let a = r.antennas
+            let antennasEl = document.getElementById("link-antennas")
+            if (a.difference_db === null) {
+                antennasEl.textContent = "Antennas: Not enough readings"
+            } else {
+                antennasEl.textContent = "Antennas: " + format(a.rssi_1_mean, 0) + "dBm / " +
+                    format(a.rssi_2_mean, 0) + "dBm" +
+                    (a.imbalanced ? ". Imbalanced; check antenna placement and connectors." : "")
+            }
+            antennasEl.style.color = a.imbalanced ? "#cc8800" : ""
+
+            let rangeEl = document.getElementById("range-test-summary")
+            if (r.range_test === null) {
+                rangeEl.textContent = ""
+            } else {
+                rangeEl.textContent = (r.range_test.running ? "Range test running: " : "Range test: ") +
+                    linkSummaryText(r.range_test.summary)
+            }
+        })
+}
+
+function startRangeTest() {
+    fetch("/api/link/range_test/start", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+}
+
+function stopRangeTest() {
+    fetch("/api/link/range_test/stop", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(() => updateLinkReport())
+}
+
+let IMU_CAL_INTERVAL = null
+
+function updateImuCal() {
+    fetch("/api/imu_cal", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            let s = r.status
+            document.getElementById("imu-cal-status").textContent =
+                s.kind + " calibration: " + s.state + (s.state === "Running" ? " (" + s.progress + "%)" : "")
+
+            if (s.state !== "Running") {
+                clearInterval(IMU_CAL_INTERVAL)
+                IMU_CAL_INTERVAL = null
+            }
+
+            if (s.state === "Done") {
+                let vec = v => v.map(x => format(x, 4)).join(", ")
+                document.getElementById("imu-cal-results").textContent =
+                    "Gyro bias: " + vec(s.gyro_bias) + " rad/s. Accel bias: " + vec(s.accel_bias) +
+                    " m/s². Residual tilt: " + format(r.residual_tilt, 1) + "° (roll " +
+                    format(r.residual_roll, 1) + "°, pitch " + format(r.residual_pitch, 1) + "°)"
+            }
+        })
+}
+
+function startImuCal(kind) {
+    fetch("/api/imu_cal/" + kind, {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (!response.ok) {
+                document.getElementById("imu-cal-status").textContent =
+                    "Can't start calibration. Make sure the motors are disarmed, and the firmware supports it."
+                return
+            }
+            document.getElementById("imu-cal-results").textContent = ""
+            if (IMU_CAL_INTERVAL === null) {
+                IMU_CAL_INTERVAL = setInterval(updateImuCal, 1_000. / UPDATE_RATE)
+            }
+        })
+}
+
+let MAG_CAL_INTERVAL = null
+
+function updateMagCal() {
+    fetch("/api/mag_cal", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (!response.ok) {
+                throw new Error("No calibration in progress")
+            }
+            return response.json()
+        })
+        .then(r => {
+            document.getElementById("mag-cal-status").textContent = r.num_samples + " samples, " +
+                format(r.coverage * 100., 0) + "% coverage. " + r.message
+            document.getElementById("mag-cal-status").style.color = r.acceptable ? "#228822" : ""
+
+            // One cell per orientation bin; top row is +z.
+            let table = document.getElementById("mag-cal-coverage")
+            table.innerHTML = ""
+            for (const band of r.coverage_bins.slice().reverse()) {
+                let row = document.createElement("tr")
+                for (const covered of band) {
+                    let cell = document.createElement("td")
+                    cell.style.width = "16px"
+                    cell.style.height = "16px"
+                    cell.style.border = "1px solid #666666"
+                    cell.style.backgroundColor = covered ? "#228822" : ""
+                    row.appendChild(cell)
+                }
+                table.appendChild(row)
+            }
+
+            let f = r.fit
+            document.getElementById("mag-cal-fit").textContent = f === null ? "" :
+                "Hard iron: " + f.hard_iron.map(v => format(v, 2)).join(", ") +
+                ". Field strength: " + format(f.field_strength, 2) +
+                ". Axis ratio: " + format(f.axis_ratio, 2) +
+                ". Residual: " + format(f.residual * 100., 1) + "%"
+        })
+        .catch(() => {
+            clearInterval(MAG_CAL_INTERVAL)
+            MAG_CAL_INTERVAL = null
+        })
+}
+
+function startMagCal() {
+    fetch("/api/mag_cal/start", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (!response.ok) {
+                document.getElementById("mag-cal-status").textContent =
+                    "Can't start calibration. Make sure the motors are disarmed, and the FC has a compass."
+                return
+            }
+            if (MAG_CAL_INTERVAL === null) {
+                MAG_CAL_INTERVAL = setInterval(updateMagCal, 200)
+            }
+        })
+}
+
+function saveMagCal() {
+    fetch("/api/mag_cal/save", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.ok ? "Saved the compass calibration to the FC." : response.text())
+        .then(t => {
+            document.getElementById("mag-cal-status").textContent = t
+        })
+}
+
+function cancelMagCal() {
+    fetch("/api/mag_cal/cancel", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(() => {
+            document.getElementById("mag-cal-status").textContent = ""
+            document.getElementById("mag-cal-coverage").innerHTML = ""
+            document.getElementById("mag-cal-fit").textContent = ""
+        })
+}
+
+const PID_AXES = ["roll", "pitch", "yaw"]
+const PID_LOOPS = ["rate", "attitude"]
+const PID_TERMS = ["kp", "ki", "kd", "kff"]
+
+function buildPidTable() {
+    let table = document.getElementById("pid-table")
+
+    let header = document.createElement("tr")
+    for (const text of ["", ...PID_TERMS]) {
+        let th = document.createElement("th")
+        th.textContent = text
+        header.appendChild(th)
+    }
+    table.appendChild(header)
+
+    for (const axis of PID_AXES) {
+        for (const loop of PID_LOOPS) {
+            let row = document.createElement("tr")
+            let label = document.createElement("td")
+            label.textContent = axis + " " + loop
+            row.appendChild(label)
+
+            for (const term of PID_TERMS) {
+                let cell = document.createElement("td")
+                let input = document.createElement("input")
+                input.id = "pid-" + axis + "-" + loop + "-" + term
+                input.type = "number"
+                input.step = "0.001"
+                input.style.width = "80px"
+                cell.appendChild(input)
+                row.appendChild(cell)
+            }
+            table.appendChild(row)
+        }
+    }
+}
+
+function showPid(pid) {
+    for (const axis of PID_AXES) {
+        for (const loop of PID_LOOPS) {
+            for (const term of PID_TERMS) {
+                document.getElementById("pid-" + axis + "-" + loop + "-" + term).value = pid[axis][loop][term]
+            }
+        }
+    }
+    document.getElementById("pid-gyro-lpf").value = pid.gyro_lpf_hz
+    document.getElementById("pid-d-term-lpf").value = pid.d_term_lpf_hz
+}
+
+function pidFromInputs() {
+    let result = {
+        gyro_lpf_hz: parseFloat(document.getElementById("pid-gyro-lpf").value),
+        d_term_lpf_hz: parseFloat(document.getElementById("pid-d-term-lpf").value),
+    }
+
+    for (const axis of PID_AXES) {
+        result[axis] = {}
+        for (const loop of PID_LOOPS) {
+            result[axis][loop] = {}
+            for (const term of PID_TERMS) {
+                result[axis][loop][term] =
+                    parseFloat(document.getElementById("pid-" + axis + "-" + loop + "-" + term).value)
+            }
+        }
+    }
+
+    return result
+}
+
+function showPidResult(response, okText) {
+    // Server errors, eg out-of-range gains, come back as text.
+    return (response.ok ? Promise.resolve(okText) : response.text())
+        .then(t => {
+            document.getElementById("pid-status").textContent = t
+        })
+}
+
+function loadPid() {
+    fetch("/api/pid", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            showPid(r)
+            document.getElementById("pid-status").textContent = "Read gains from the FC."
+        })
+        .catch(() => {
+            document.getElementById("pid-status").textContent = "Can't read gains from the FC."
+        })
+}
+
+function writePid() {
+    fetch("/api/pid", {
+        method: "PUT",
+        headers: HEADERS,
+        credentials: "include",
+        body: JSON.stringify(pidFromInputs())
+    })
+        .then(response => showPidResult(response, "Wrote gains to the FC."))
+}
+
+function loadPidProfiles() {
+    fetch("/api/pid/profiles", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            let select = document.getElementById("pid-profile")
+            select.innerHTML = ""
+
+            for (const profile of r.profiles) {
+                let option = document.createElement("option")
+                option.value = profile.name
+                option.textContent = profile.name
+                select.appendChild(option)
+            }
+        })
+}
+
+function selectedPidProfile() {
+    return encodeURIComponent(document.getElementById("pid-profile").value)
+}
+
+function savePidProfile() {
+    let name = document.getElementById("pid-profile-name").value
+    if (name === "") {
+        return
+    }
 
+    fetch("/api/pid/profiles/" + encodeURIComponent(name), {
+        method: "PUT",
+        headers: HEADERS,
+        credentials: "include",
+        body: JSON.stringify(pidFromInputs())
+    })
+        .then(response => showPidResult(response, "Saved profile " + name + "."))
+        .then(() => loadPidProfiles())
+}
+
+function applyPidProfile() {
+    fetch("/api/pid/profiles/" + selectedPidProfile() + "/apply", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => showPidResult(response, "Applied the profile to the FC."))
+        .then(() => loadPid())
+}
+
+function deletePidProfile() {
+    fetch("/api/pid/profiles/" + selectedPidProfile(), {
+        method: "DELETE",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(() => loadPidProfiles())
+}
+
+function diffPidProfile() {
+    fetch("/api/pid/profiles/" + selectedPidProfile() + "/diff", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            let diffEl = document.getElementById("pid-diff")
+            diffEl.innerHTML = ""
+
+            document.getElementById("pid-status").textContent =
+                r.length === 0 ? "The profile matches the FC." : "Profile vs FC:"
+
+            for (const d of r) {
+                let el = document.createElement("h3")
+                el.textContent = d.field + ": " + d.profile + " vs " + d.fc
+                diffEl.appendChild(el)
+            }
+        })
+}
+
+function paramInput(p) {
+    let input = document.createElement("input")
+
+    if (p.param_type === "bool") {
+        input.type = "checkbox"
+        input.checked = p.value
+    } else {
+        input.type = "number"
+        input.step = p.param_type === "f32" ? "any" : "1"
+        input.min = p.min
+        input.max = p.max
+        input.value = p.value
+        input.style.width = "100px"
+    }
+
+    return input
+}
+
+function paramInputValue(p, input) {
+    return p.param_type === "bool" ? input.checked : Number(input.value)
+}
+
+function loadParams() {
+    let search = document.getElementById("param-search").value
+
+    fetch("/api/params?search=" + encodeURIComponent(search), {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.ok ? response.json() : response.text().then(t => Promise.reject(t)))
+        .then(r => {
+            let table = document.getElementById("param-table")
+            table.innerHTML = "<tr><th>#</th><th>Name</th><th>Type</th><th>Value</th>" +
+                "<th>Min</th><th>Max</th><th>Default</th><th></th></tr>"
+
+            for (const p of r) {
+                let row = document.createElement("tr")
+                for (const v of [p.index, p.name, p.param_type]) {
+                    let cell = document.createElement("td")
+                    cell.textContent = v
+                    row.appendChild(cell)
+                }
+
+                let input = paramInput(p)
+                let inputCell = document.createElement("td")
+                inputCell.appendChild(input)
+                row.appendChild(inputCell)
+
+                for (const v of [p.min, p.max, p.default]) {
+                    let cell = document.createElement("td")
+                    cell.textContent = p.param_type === "bool" ? "" : v
+                    row.appendChild(cell)
+                }
+
+                let button = document.createElement("button")
+                button.textContent = "Write"
+                button.onclick = () => writeParam(p, paramInputValue(p, input))
+                let buttonCell = document.createElement("td")
+                buttonCell.appendChild(button)
+                row.appendChild(buttonCell)
+
+                table.appendChild(row)
+            }
+
+            document.getElementById("param-status").textContent = ""
+        })
+        .catch(e => {
+            document.getElementById("param-status").textContent =
+                typeof e === "string" ? e : "Can't read parameters from the FC."
+        })
+}
+
+function writeParam(p, value) {
+    fetch("/api/params/" + encodeURIComponent(p.name), {
+        method: "PUT",
+        headers: HEADERS,
+        credentials: "include",
+        body: JSON.stringify(value)
+    })
+        .then(response => response.text().then(t => {
+            let statusEl = document.getElementById("param-status")
+            if (response.ok) {
+                statusEl.textContent = "Set " + p.name + " to " + t + "."
+            } else if (response.status === 400) {
+                // Rejected values come back as text.
+                statusEl.textContent = t
+            } else {
+                statusEl.textContent =
+                    "Can't set " + p.name + ". Make sure the FC is connected, and the motors are disarmed."
+            }
+        }))
+}
+
+function refreshParams() {
+    fetch("/api/params/refresh", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(() => loadParams())
+}
+
+function readBackupFile(id) {
+    let file = document.getElementById(id).files[0]
+    return file === undefined ? Promise.resolve(null) : file.text()
+}
+
+function showConfigDetails(lines) {
+    let detailsEl = document.getElementById("config-details")
+    detailsEl.innerHTML = ""
+
+    for (const line of lines) {
+        let el = document.createElement("h3")
+        el.textContent = line
+        detailsEl.appendChild(el)
+    }
+}
+
+function restoreConfig() {
+    readBackupFile("config-backup-a").then(contents => {
+        if (contents === null) {
+            document.getElementById("config-status").textContent = "Choose a backup to restore."
+            return
+        }
+
+        document.getElementById("config-status").textContent = "Restoring..."
+        showConfigDetails([])
+
+        fetch("/api/config/restore", {
+            method: "POST",
+            headers: HEADERS,
+            credentials: "include",
+            body: contents
+        })
+            .then(response => response.text().then(t => {
+                if (!response.ok) {
+                    document.getElementById("config-status").textContent = t
+                    return
+                }
+
+                let r = JSON.parse(t)
+                document.getElementById("config-status").textContent =
+                    "Restored, and confirmed: " + r.restored.join(", ")
+                showConfigDetails(r.skipped.map(s => "Skipped " + s))
+            }))
+    })
+}
+
+function diffConfig() {
+    Promise.all([readBackupFile("config-backup-a"), readBackupFile("config-backup-b")])
+        .then(([a, b]) => {
+            if (a === null) {
+                document.getElementById("config-status").textContent = "Choose a backup to compare."
+                return
+            }
+
+            fetch("/api/config/diff", {
+                method: "POST",
+                headers: HEADERS,
+                credentials: "include",
+                body: JSON.stringify({a: a, b: b})
+            })
+                .then(response => response.text().then(t => {
+                    if (!response.ok) {
+                        document.getElementById("config-status").textContent = t
+                        return
+                    }
+
+                    let r = JSON.parse(t)
+                    let show = v => v === null ? "(none)" : JSON.stringify(v)
+
+                    document.getElementById("config-status").textContent = r.length === 0 ?
+                        "No differences." : (b === null ? "Backup vs FC:" : "First vs second backup:")
+                    showConfigDetails(r.map(d => d.field + ": " + show(d.a) + " -> " + show(d.b)))
+                }))
+        })
+}
+
+let FIRMWARE_INTERVAL = null
+
+const FLASH_STAGE_TEXT = {
+    waiting_for_bootloader: "Waiting for the bootloader...",
+    erasing: "Erasing...",
+    writing: "Writing...",
+    verifying: "Verifying...",
+    restarting: "Restarting the FC...",
+    checking_version: "Checking the firmware version...",
+}
+
+function updateFirmwareStatus() {
+    fetch("/api/firmware/status", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            if (r === null) {
+                return
+            }
+
+            let finished = r.stage === "done" || r.stage === "failed"
+            document.getElementById("firmware-status").textContent =
+                finished ? r.message : FLASH_STAGE_TEXT[r.stage]
+
+            let progressEl = document.getElementById("firmware-progress")
+            progressEl.max = r.total > 0 ? r.total : 1
+            progressEl.value = r.stage === "done" ? progressEl.max : r.done
+
+            if (finished) {
+                clearInterval(FIRMWARE_INTERVAL)
+                FIRMWARE_INTERVAL = null
+            }
+        })
+}
+
+function flashFirmware() {
+    let file = document.getElementById("firmware-image").files[0]
+    if (file === undefined) {
+        document.getElementById("firmware-status").textContent = "Choose a firmware image (.bin) to flash."
+        return
+    }
+
+    let version = document.getElementById("firmware-version").value.trim()
+
+    file.arrayBuffer().then(image => {
+        document.getElementById("firmware-status").textContent = "Checking the image..."
+
+        fetch("/api/firmware/flash?version=" + encodeURIComponent(version), {
+            method: "POST",
+            headers: {"Content-Type": "application/octet-stream", "X-CSRFToken": getCookie()},
+            credentials: "include",
+            body: image
+        })
+            .then(response => response.text().then(t => {
+                if (!response.ok) {
+                    document.getElementById("firmware-status").textContent = t
+                    return
+                }
+
+                if (FIRMWARE_INTERVAL === null) {
+                    FIRMWARE_INTERVAL = setInterval(updateFirmwareStatus, 500)
+                }
+            }))
+    })
+}
+
+let RC_CAL_INTERVAL = null
+
+function updateRcCal() {
+    fetch("/api/rc_cal", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (!response.ok) {
+                throw new Error("No calibration in progress")
+            }
+            return response.json()
+        })
+        .then(r => {
+            document.getElementById("rc-cal-instructions").textContent = r.instructions
+
+            let axesEl = document.getElementById("rc-cal-axes")
+            axesEl.innerHTML = ""
+
+            if (r.report !== null) {
+                for (const a of r.report.axes) {
+                    let c = a.calibration
+                    let el = document.createElement("h3")
+                    el.textContent = a.axis + ": " + format(c.min, 2) + " to " + format(c.max, 2) +
+                        ", center " + format(c.center, 2) + " (offset " + format(a.center_offset, 2) +
+                        "), deadband " + format(c.deadband, 2) + ", travel " + format(a.travel * 100., 0) + "%" +
+                        (c.reversed ? ", reversed" : "") +
+                        (a.issues.length > 0 ? ". " + a.issues.join(" ") : "")
+                    el.style.color = a.issues.length > 0 ? "#cc8800" : "#228822"
+                    axesEl.appendChild(el)
+                }
+
+                let t = r.report.throttle
+                let el = document.createElement("h3")
+                el.textContent = "Throttle range: " + format(t.min, 2) + " to " + format(t.max, 2) +
+                    ", idle " + format(t.idle, 2)
+                axesEl.appendChild(el)
+                return
+            }
+
+            for (const a of r.axes) {
+                let el = document.createElement("h3")
+                el.textContent = a.axis + ": " + format(a.value, 2) +
+                    (a.min === null ? "" : " (" + format(a.min, 2) + " to " + format(a.max, 2) + ")")
+                axesEl.appendChild(el)
+            }
+        })
+        .catch(() => {
+            clearInterval(RC_CAL_INTERVAL)
+            RC_CAL_INTERVAL = null
+        })
+}
+
+function startRcCal() {
+    fetch("/api/rc_cal/start", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (!response.ok) {
+                document.getElementById("rc-cal-instructions").textContent =
+                    "Can't start calibration. Make sure the aircraft is disarmed."
+                return
+            }
+            if (RC_CAL_INTERVAL === null) {
+                RC_CAL_INTERVAL = setInterval(updateRcCal, 1_000. / UPDATE_RATE)
+            }
+            updateRcCal()
+        })
+}
+
+function advanceRcCal() {
+    fetch("/api/rc_cal/next", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.ok ? updateRcCal() : response.text().then(t => {
+            document.getElementById("rc-cal-instructions").textContent = t
+        }))
+}
+
+function saveRcCal() {
+    fetch("/api/rc_cal/save", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.text())
+        .then(r => {
+            document.getElementById("rc-cal-instructions").textContent = r
+        })
+}
+
+function cancelRcCal() {
+    fetch("/api/rc_cal/cancel", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(() => {
+            document.getElementById("rc-cal-instructions").textContent = ""
+            document.getElementById("rc-cal-axes").innerHTML = ""
         })
 }
 
@@ -98,8 +1003,11 @@ function armMotors() {
         credentials: "include",
         body: "confirm",
     })
-        .then(response => response.json())
-        .then(r => {})
+        .then(response => {
+            if (!response.ok) {
+                response.text().then(t => alert("Motors not armed: " + t))
+            }
+        })
 }
 
 
@@ -110,26 +1018,184 @@ function startMotor(motor) {
         credentials: "include",
         body: motor
     })
-        .then(response => response.json())
-        .then(r => {})
+        .then(response => {
+            if (!response.ok) {
+                response.text().then(t => alert("Motor not started: " + t))
+            }
+        })
 }
 
 function setServoPosition(servo) {
+    // `servo` is "left" or "right". Values are PWM high times, in ms.
     let body = {
         servo: servo,
-        value: parseFloat(document.getElementById("servo-posit-left").value)
+        value: parseFloat(document.getElementById("servo-posit-" + servo).value)
     }
 
     fetch("/api/set_servo_position", {
         method: "POST",
         headers: HEADERS,
         credentials: "include",
-        body: body
+        body: JSON.stringify(body)
     })
         .then(response => response.json())
         .then(r => {})
 }
 
+function loadAircraftInfo() {
+    // Show only the sections that apply to the airframe the FC reports. Retry until we
+    // find the FC.
+    fetch("/api/info", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (!response.ok) {
+                throw new Error("FC not found")
+            }
+            return response.json()
+        })
+        .then(r => {
+            let h = r.handshake
+            let fw = h.firmware.firmware_version
+            let proto = h.negotiated_protocol
+
+            let infoEl = document.getElementById("aircraft-info")
+            infoEl.textContent = "Firmware v" + fw.major + "." + fw.minor + "." + fw.patch +
+                ", board " + h.firmware.board_id + ", protocol v" + proto.major + "." + proto.minor
+
+            // `compatibility` is either "Compatible", or an object with a message.
+            if (h.compatibility.Degraded !== undefined) {
+                infoEl.textContent += ". ⚠️" + h.compatibility.Degraded
+            } else if (h.compatibility.Incompatible !== undefined) {
+                infoEl.textContent += ". ⚠️Not connected: " + h.compatibility.Incompatible
+                infoEl.style.color = "#cc2222"
+                return
+            }
+
+            // The handshake worked, but the FC didn't report its airframe. Retrying won't help.
+            if (r.aircraft === null) {
+                infoEl.textContent = "No aircraft. " + infoEl.textContent
+                infoEl.style.color = "#cc2222"
+                return
+            }
+
+            infoEl.textContent = r.aircraft.aircraft_type + ", build " + r.aircraft.firmware_build +
+                ". " + infoEl.textContent
+
+            if (r.aircraft.aircraft_type === "Quadcopter") {
+                document.getElementById("quadcopter-specific").style.display = "block"
+            } else if (r.aircraft.aircraft_type === "FlyingWing") {
+                document.getElementById("fixedwing-specific").style.display = "block"
+                loadServoCalibration()
+                setInterval(updateElevonPreview, 1_000. / UPDATE_RATE)
+            }
+        })
+        .catch(() => setTimeout(loadAircraftInfo, 2_000))
+}
+
+function loadServoCalibration() {
+    // Populate the servo endpoint fields with the calibration stored on the FC.
+    fetch("/api/servo_calibration", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            for (const servo of ["left", "right"]) {
+                document.getElementById("servo-min-" + servo).value = r[servo].min
+                document.getElementById("servo-center-" + servo).value = r[servo].center
+                document.getElementById("servo-max-" + servo).value = r[servo].max
+                document.getElementById("servo-reversed-" + servo).checked = r[servo].reversed
+            }
+        })
+}
+
+function setServoCalibration(servo) {
+    // Send min, center, max, and reverse for a servo to the FC. The server rejects
+    // endpoints outside the PWM limits, or that aren't ordered min < center < max.
+    let body = {
+        servo: servo,
+        calibration: {
+            min: parseFloat(document.getElementById("servo-min-" + servo).value),
+            center: parseFloat(document.getElementById("servo-center-" + servo).value),
+            max: parseFloat(document.getElementById("servo-max-" + servo).value),
+            reversed: document.getElementById("servo-reversed-" + servo).checked,
+        }
+    }
+
+    fetch("/api/set_servo_calibration", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+        body: JSON.stringify(body)
+    })
+        .then(response => {
+            if (!response.ok) {
+                response.text().then(t => alert("Servo calibration not set: " + t))
+            }
+        })
+}
+
+function startSession() {
+    // Get a session, and CSRF token. Until paired, it's read-only.
+    fetch("/api/session", {
+        method: "GET",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => response.json())
+        .then(r => {
+            HEADERS["X-CSRFToken"] = getCookie()
+            showRole(r.role)
+        })
+}
+
+function showRole(role) {
+    const paired = role === "Control"
+
+    document.getElementById("session-role").textContent = paired ?
+        "Paired: This device can control the aircraft." :
+        "Read-only. To arm, test motors, or change settings, enter the PIN shown where Preflight is running."
+    document.getElementById("pairing-pin").style.display = paired ? "none" : "inline"
+    document.getElementById("pair-button").style.display = paired ? "none" : "inline"
+    document.getElementById("unpair-button").style.display = paired ? "inline" : "none"
+}
+
+function pairDevice() {
+    const pin = document.getElementById("pairing-pin")
+
+    fetch("/api/pair", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+        body: pin.value.trim()
+    })
+        .then(response => {
+            pin.value = ""
+            if (response.ok) {
+                showRole("Control")
+            } else {
+                response.text().then(t => alert("Not paired: " + t))
+            }
+        })
+}
+
+function unpairDevice() {
+    fetch("/api/unpair", {
+        method: "POST",
+        headers: HEADERS,
+        credentials: "include",
+    })
+        .then(response => {
+            if (response.ok) {
+                showRole("ReadOnly")
+            }
+        })
+}
+
 function getCookie() {
     let name_ = "csrftoken"
     let cookieValue = null;